@@ -0,0 +1,156 @@
+// Memory for the nodes of a skip list. Allocations are carved out of 4 KiB
+// blocks, and all of them are freed at once when the arena is dropped.
+// Allocations too big to share a block get a block of their own, so at most
+// a quarter of a block is wasted when a new one is started.
+
+use std::alloc::{self, Layout};
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+const BLOCK_SIZE: usize = 4096;
+/// Alignment of every allocation.
+const ALIGN: usize = mem::align_of::<usize>();
+
+struct ArenaState {
+    // The unused part of the current block.
+    ptr: *mut u8,
+    remaining: usize,
+    // Every block allocated, to free on drop.
+    blocks: Vec<(*mut u8, Layout)>,
+    // Bytes of every block, less what is left of the current one.
+    memory_usage: usize,
+}
+
+// SAFETY: the blocks are owned by the arena and only reached through it.
+unsafe impl Send for ArenaState {}
+
+/// Allocates memory that lives as long as the arena. Allocation takes a
+/// short lock, so several threads can allocate at once.
+pub struct Arena {
+    state: Mutex<ArenaState>,
+    memory_usage: AtomicUsize,
+}
+
+impl Default for Arena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(ArenaState {
+                ptr: ptr::null_mut(),
+                remaining: 0,
+                blocks: Vec::new(),
+                memory_usage: 0,
+            }),
+            memory_usage: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns `bytes` bytes of uninitialized memory, aligned for a `usize`
+    /// and valid until the arena is dropped.
+    pub fn allocate(&self, bytes: usize) -> *mut u8 {
+        debug_assert!(bytes > 0);
+        let bytes = bytes.next_multiple_of(ALIGN);
+        let mut state = self.state.lock().unwrap();
+        let result = if bytes <= state.remaining {
+            let result = state.ptr;
+            state.ptr = unsafe { state.ptr.add(bytes) };
+            state.remaining -= bytes;
+            state.memory_usage += bytes;
+            result
+        } else if bytes > BLOCK_SIZE / 4 {
+            // Keep what is left of the current block for later allocations.
+            let result = state.allocate_block(bytes);
+            state.memory_usage += bytes;
+            result
+        } else {
+            // The rest of the current block is wasted.
+            state.memory_usage += state.remaining;
+            let block = state.allocate_block(BLOCK_SIZE);
+            state.ptr = unsafe { block.add(bytes) };
+            state.remaining = BLOCK_SIZE - bytes;
+            state.memory_usage += bytes;
+            block
+        };
+        self.memory_usage
+            .store(state.memory_usage, Ordering::Relaxed);
+        result
+    }
+
+    /// Returns the bytes allocated so far, including what was wasted at the
+    /// end of blocks.
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+}
+
+impl ArenaState {
+    fn allocate_block(&mut self, bytes: usize) -> *mut u8 {
+        let layout = Layout::from_size_align(bytes, ALIGN).unwrap();
+        let block = unsafe { alloc::alloc(layout) };
+        if block.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        self.blocks.push((block, layout));
+        block
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap();
+        for &(block, layout) in &state.blocks {
+            unsafe { alloc::dealloc(block, layout) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::slice;
+
+    #[test]
+    fn allocations_are_aligned_and_disjoint() {
+        let arena = Arena::new();
+        assert_eq!(arena.memory_usage(), 0);
+
+        let mut allocations = Vec::new();
+        let mut total = 0;
+        for (i, bytes) in [1, 7, 8, 100, 1000, 2000, 5000, 3, 900]
+            .into_iter()
+            .enumerate()
+        {
+            let ptr = arena.allocate(bytes);
+            assert_eq!(ptr as usize % ALIGN, 0);
+            unsafe { ptr::write_bytes(ptr, i as u8, bytes) };
+            allocations.push((ptr, bytes, i as u8));
+            total += bytes.next_multiple_of(ALIGN);
+        }
+        for (ptr, bytes, fill) in allocations {
+            let contents = unsafe { slice::from_raw_parts(ptr, bytes) };
+            assert!(contents.iter().all(|&byte| byte == fill));
+        }
+        // Only the tail of blocks left behind is wasted.
+        assert!(arena.memory_usage() >= total);
+        assert!(arena.memory_usage() < total + BLOCK_SIZE / 4 * 2);
+    }
+
+    #[test]
+    fn big_allocations_keep_the_current_block() {
+        let arena = Arena::new();
+        arena.allocate(8);
+        arena.allocate(BLOCK_SIZE);
+        arena.allocate(8);
+        // The second small allocation reuses the first block, so nothing
+        // is wasted.
+        assert_eq!(arena.memory_usage(), 16 + BLOCK_SIZE);
+        assert_eq!(arena.state.lock().unwrap().blocks.len(), 2);
+    }
+}
@@ -0,0 +1,447 @@
+// Block layout. Keys are prefix-compressed against the previous key, except
+// at restart points, where the full key is stored:
+//
+// +----------------------------------------------------------------------+
+// | entry 0 | entry 1 | ... | restart[0] | ... | restart[R-1] | R: u32 LE |
+// +----------------------------------------------------------------------+
+//
+// entry:   [shared: varint][unshared: varint][value length: varint]
+//          [unshared key bytes][value]
+// restart: u32 LE offset of an entry with shared == 0
+//
+// The restart array lets readers binary-search a block and only decode the
+// entries following the closest restart point.
+
+use std::cmp::Ordering;
+use std::io;
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::common::{decode_var, put_var};
+use crate::comparator::Comparator;
+use crate::iterator::InternalIterator;
+
+/// Builds a block of key/value entries added in sorted order.
+pub struct BlockBuilder {
+    buffer: Vec<u8>,
+    restarts: Vec<u32>,
+    restart_interval: usize,
+    counter: usize,
+    last_key: Vec<u8>,
+}
+
+impl BlockBuilder {
+    /// Creates a builder storing a full key every `restart_interval` entries.
+    pub fn new(restart_interval: usize) -> Self {
+        assert!(restart_interval >= 1);
+        Self {
+            buffer: Vec::new(),
+            restarts: vec![0],
+            restart_interval,
+            counter: 0,
+            last_key: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, key: &[u8], value: &[u8]) {
+        let mut shared = 0;
+        if self.counter < self.restart_interval {
+            let max = self.last_key.len().min(key.len());
+            while shared < max && self.last_key[shared] == key[shared] {
+                shared += 1;
+            }
+        } else {
+            self.restarts.push(self.buffer.len() as u32);
+            self.counter = 0;
+        }
+        let unshared = key.len() - shared;
+
+        put_var(&mut self.buffer, shared);
+        put_var(&mut self.buffer, unshared);
+        put_var(&mut self.buffer, value.len());
+        self.buffer.extend_from_slice(&key[shared..]);
+        self.buffer.extend_from_slice(value);
+
+        self.last_key.truncate(shared);
+        self.last_key.extend_from_slice(&key[shared..]);
+        self.counter += 1;
+    }
+
+    /// Returns the size of the block `finish` would produce.
+    pub fn current_size_estimate(&self) -> usize {
+        self.buffer.len() + self.restarts.len() * 4 + 4
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Returns the finished block contents and resets the builder.
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut block = std::mem::take(&mut self.buffer);
+        for restart in &self.restarts {
+            block.extend(restart.to_le_bytes());
+        }
+        block.extend((self.restarts.len() as u32).to_le_bytes());
+
+        self.restarts = vec![0];
+        self.counter = 0;
+        self.last_key.clear();
+        block
+    }
+}
+
+/// The immutable contents of one block. Cloning is cheap.
+#[derive(Clone)]
+pub struct Block {
+    data: Arc<Vec<u8>>,
+    // Offset of the restart array.
+    restarts: usize,
+    num_restarts: usize,
+}
+
+impl Block {
+    pub fn new(data: Vec<u8>) -> io::Result<Self> {
+        let malformed = || io::Error::new(io::ErrorKind::InvalidData, "bad block contents");
+        if data.len() < 4 {
+            return Err(malformed());
+        }
+        let num_restarts = read_u32(&data, data.len() - 4) as usize;
+        let max_restarts = (data.len() - 4) / 4;
+        if num_restarts == 0 || num_restarts > max_restarts {
+            return Err(malformed());
+        }
+        Ok(Self {
+            restarts: data.len() - 4 - num_restarts * 4,
+            num_restarts,
+            data: Arc::new(data),
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn iter(&self, comparator: Arc<dyn Comparator>) -> BlockIter {
+        BlockIter {
+            block: self.clone(),
+            comparator,
+            current: self.restarts,
+            next: self.restarts,
+            restart_index: self.num_restarts,
+            key: Vec::new(),
+            value: 0..0,
+            corrupted: false,
+        }
+    }
+
+    fn restart_point(&self, index: usize) -> usize {
+        read_u32(&self.data, self.restarts + index * 4) as usize
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Iterates over the entries of a `Block`.
+pub struct BlockIter {
+    block: Block,
+    comparator: Arc<dyn Comparator>,
+    // Offset of the current entry; equal to the restart array offset when
+    // not valid.
+    current: usize,
+    // Offset of the entry following the current one.
+    next: usize,
+    // Index of the restart point at or before the current entry.
+    restart_index: usize,
+    key: Vec<u8>,
+    value: Range<usize>,
+    corrupted: bool,
+}
+
+impl BlockIter {
+    /// Returns true if the block was found to be malformed.
+    pub fn corrupted(&self) -> bool {
+        self.corrupted
+    }
+
+    fn seek_to_restart_point(&mut self, index: usize) {
+        self.key.clear();
+        self.restart_index = index;
+        self.next = self.block.restart_point(index);
+    }
+
+    fn mark_invalid(&mut self) {
+        self.current = self.block.restarts;
+        self.next = self.block.restarts;
+        self.restart_index = self.block.num_restarts;
+    }
+
+    /// Decodes the entry at `self.next`. Returns false at the end of the
+    /// block or on corruption.
+    fn parse_next_entry(&mut self) -> bool {
+        self.current = self.next;
+        if self.current >= self.block.restarts {
+            self.current = self.block.restarts;
+            return false;
+        }
+        match decode_entry(&self.block.data[..self.block.restarts], self.current) {
+            Some((shared, key, value)) if shared <= self.key.len() => {
+                self.key.truncate(shared);
+                self.key.extend_from_slice(&self.block.data[key]);
+                self.next = value.end;
+                self.value = value;
+                while self.restart_index + 1 < self.block.num_restarts
+                    && self.block.restart_point(self.restart_index + 1) < self.current
+                {
+                    self.restart_index += 1;
+                }
+                true
+            }
+            _ => {
+                self.corrupted = true;
+                self.mark_invalid();
+                false
+            }
+        }
+    }
+}
+
+impl InternalIterator for BlockIter {
+    fn valid(&self) -> bool {
+        self.current < self.block.restarts
+    }
+
+    fn key(&self) -> &[u8] {
+        debug_assert!(self.valid());
+        &self.key
+    }
+
+    fn value(&self) -> &[u8] {
+        debug_assert!(self.valid());
+        &self.block.data[self.value.clone()]
+    }
+
+    fn seek_to_first(&mut self) {
+        self.seek_to_restart_point(0);
+        self.parse_next_entry();
+    }
+
+    fn seek_to_last(&mut self) {
+        self.seek_to_restart_point(self.block.num_restarts - 1);
+        while self.parse_next_entry() && self.next < self.block.restarts {}
+    }
+
+    /// Positions at the first entry with a key at or after `target`.
+    fn seek(&mut self, target: &[u8]) {
+        // Binary search for the last restart point with a key before
+        // `target`, then scan forward from it.
+        let mut left = 0;
+        let mut right = self.block.num_restarts - 1;
+        while left < right {
+            let mid = (left + right).div_ceil(2);
+            self.seek_to_restart_point(mid);
+            if !self.parse_next_entry() {
+                return;
+            }
+            if self.comparator.compare(&self.key, target) == Ordering::Less {
+                left = mid;
+            } else {
+                right = mid - 1;
+            }
+        }
+
+        self.seek_to_restart_point(left);
+        while self.parse_next_entry() {
+            if self.comparator.compare(&self.key, target) != Ordering::Less {
+                return;
+            }
+        }
+    }
+
+    fn next(&mut self) {
+        debug_assert!(self.valid());
+        self.parse_next_entry();
+    }
+
+    fn prev(&mut self) {
+        debug_assert!(self.valid());
+        // Back up to the last restart point before the current entry, then
+        // scan forward to the entry just before it.
+        let original = self.current;
+        while self.block.restart_point(self.restart_index) >= original {
+            if self.restart_index == 0 {
+                self.mark_invalid();
+                return;
+            }
+            self.restart_index -= 1;
+        }
+        self.seek_to_restart_point(self.restart_index);
+        while self.parse_next_entry() && self.next < original {}
+    }
+
+    fn status(&self) -> io::Result<()> {
+        if self.corrupted {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "bad entry in block",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the entry header at `offset`. Returns the shared key length and
+/// the ranges of the unshared key bytes and the value.
+fn decode_entry(data: &[u8], offset: usize) -> Option<(usize, Range<usize>, Range<usize>)> {
+    let mut pos = offset;
+    let mut next_var = || {
+        let (value, len) = decode_var(data.get(pos..)?)?;
+        pos += len;
+        Some(value)
+    };
+    let shared = next_var()?;
+    let unshared = next_var()?;
+    let value_len = next_var()?;
+    let key_end = pos.checked_add(unshared)?;
+    let value_end = key_end.checked_add(value_len)?;
+    if value_end > data.len() {
+        return None;
+    }
+    Some((shared, pos..key_end, key_end..value_end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::BytewiseComparator;
+
+    fn build(restart_interval: usize, entries: &[(&[u8], &[u8])]) -> Block {
+        let mut builder = BlockBuilder::new(restart_interval);
+        for (key, value) in entries {
+            builder.add(key, value);
+        }
+        Block::new(builder.finish()).unwrap()
+    }
+
+    fn collect(block: &Block) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut iter = block.iter(Arc::new(BytewiseComparator::new()));
+        iter.seek_to_first();
+        let mut seen = Vec::new();
+        while iter.valid() {
+            seen.push((iter.key().to_vec(), iter.value().to_vec()));
+            iter.next();
+        }
+        seen
+    }
+
+    #[test]
+    fn iterate_and_seek() {
+        let block = build(
+            16,
+            &[(b"apple", b"1"), (b"banana", b"2"), (b"cherry", b"3")],
+        );
+        assert_eq!(
+            collect(&block),
+            vec![
+                (b"apple".to_vec(), b"1".to_vec()),
+                (b"banana".to_vec(), b"2".to_vec()),
+                (b"cherry".to_vec(), b"3".to_vec()),
+            ]
+        );
+
+        let mut iter = block.iter(Arc::new(BytewiseComparator::new()));
+        iter.seek(b"b");
+        assert_eq!(iter.key(), b"banana");
+        iter.seek(b"banana");
+        assert_eq!(iter.value(), b"2");
+        iter.seek(b"zebra");
+        assert!(!iter.valid());
+        assert!(!iter.corrupted());
+    }
+
+    #[test]
+    fn shared_prefixes_are_compressed() {
+        let keys: Vec<Vec<u8>> = (0..100)
+            .map(|i| format!("tenant-0042/table-users/{:04}", i).into_bytes())
+            .collect();
+        let entries: Vec<(&[u8], &[u8])> = keys.iter().map(|k| (k.as_slice(), &b"v"[..])).collect();
+
+        let full_keys: usize = keys.iter().map(|k| k.len()).sum();
+        let block = build(16, &entries);
+        assert!(block.size() < full_keys / 2);
+        assert_eq!(collect(&block).len(), 100);
+
+        for restart_interval in [1, 3, 16, 200] {
+            let block = build(restart_interval, &entries);
+            let mut iter = block.iter(Arc::new(BytewiseComparator::new()));
+            for key in &keys {
+                iter.seek(key);
+                assert_eq!(iter.key(), key.as_slice());
+            }
+            iter.seek(b"tenant-0042/table-users/0050x");
+            assert_eq!(iter.key(), keys[51].as_slice());
+            iter.seek(b"a");
+            assert_eq!(iter.key(), keys[0].as_slice());
+        }
+    }
+
+    #[test]
+    fn iterate_backwards() {
+        let keys: Vec<Vec<u8>> = (0..50)
+            .map(|i| format!("key{:03}", i).into_bytes())
+            .collect();
+        let entries: Vec<(&[u8], &[u8])> = keys.iter().map(|k| (k.as_slice(), &b"v"[..])).collect();
+        for restart_interval in [1, 4, 16, 100] {
+            let block = build(restart_interval, &entries);
+            let mut iter = block.iter(Arc::new(BytewiseComparator::new()));
+            iter.seek_to_last();
+            for key in keys.iter().rev() {
+                assert_eq!(iter.key(), key.as_slice());
+                iter.prev();
+            }
+            assert!(!iter.valid());
+
+            // Change direction in the middle.
+            iter.seek(b"key020");
+            iter.prev();
+            assert_eq!(iter.key(), b"key019");
+            iter.next();
+            iter.next();
+            assert_eq!(iter.key(), b"key021");
+        }
+
+        let block = build(16, &[]);
+        let mut iter = block.iter(Arc::new(BytewiseComparator::new()));
+        iter.seek_to_last();
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn empty_block() {
+        let block = build(16, &[]);
+        assert!(collect(&block).is_empty());
+        let mut iter = block.iter(Arc::new(BytewiseComparator::new()));
+        iter.seek(b"a");
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn malformed_blocks_are_detected() {
+        assert!(Block::new(vec![1, 0]).is_err());
+        assert!(Block::new(vec![0, 0, 0, 0]).is_err());
+
+        let mut builder = BlockBuilder::new(16);
+        builder.add(b"apple", b"1");
+        let mut data = builder.finish();
+        // Claim more unshared key bytes than the block holds.
+        data[1] = 0x7f;
+        let mut iter = Block::new(data)
+            .unwrap()
+            .iter(Arc::new(BytewiseComparator::new()));
+        iter.seek_to_first();
+        assert!(!iter.valid());
+        assert!(iter.corrupted());
+    }
+}
@@ -1,52 +1,121 @@
-use crate::common::Key;
+// Filter layout: a bit array followed by one byte holding the number of
+// probes per key.
+//
+// +----------------------------------+
+// | bits: [u8; n] | probes: u8       |
+// +----------------------------------+
+//
+// The probes for a key are derived from a single 64-bit hash by double
+// hashing, so only one pass over the key is needed.
+
+/// Upper bound on the number of probes. Filters claiming more are rejected.
+const MAX_PROBES: u8 = 30;
 
 pub struct BloomFilter {
-    bit_array: Vec<u8>,
-    size: usize,
+    bits: Vec<u8>,
+    num_probes: u8,
 }
 
 impl Default for BloomFilter {
     fn default() -> Self {
-        Self {
-            bit_array: vec![0; 1024],
-            size: 1024,
-        }
+        Self::new(100, 10)
     }
 }
 
 impl BloomFilter {
-    pub fn new(size: usize) -> Self {
+    /// Creates a filter for `num_keys` keys using `bits_per_key` bits for
+    /// each of them.
+    pub fn new(num_keys: usize, bits_per_key: usize) -> Self {
+        // Small filters have a high false positive rate, so use at least 64
+        // bits.
+        let num_bits = (num_keys * bits_per_key).max(64);
+        // ln(2) * bits_per_key probes minimizes the false positive rate.
+        let num_probes = (bits_per_key * 69 / 100).clamp(1, MAX_PROBES as usize) as u8;
         Self {
-            bit_array: vec![0; size],
-            size,
+            bits: vec![0; num_bits.div_ceil(8)],
+            num_probes,
         }
     }
 
-    pub fn add(&mut self, key: &Key) {
-        let hash1 = self.hash1(key) % self.size;
-        let hash2 = self.hash2(key) % self.size;
-        self.bit_array[hash1] = 1;
-        self.bit_array[hash2] = 1;
+    pub fn add(&mut self, key: &[u8]) {
+        let num_bits = self.bits.len() * 8;
+        for bit in probes(key, self.num_probes, num_bits) {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
     }
 
-    pub fn contains(&self, key: &Key) -> bool {
-        let hash1 = self.hash1(key) % self.size;
-        let hash2 = self.hash2(key) % self.size;
-        (self.bit_array[hash1] != 0) && (self.bit_array[hash2] != 0)
+    /// Returns false if `key` was definitely not added to the filter.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        let num_bits = self.bits.len() * 8;
+        probes(key, self.num_probes, num_bits).all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
     }
 
-    fn hash1(&self, key: &Key) -> usize {
-        key.iter().fold(0, |acc, &b| acc.wrapping_add(b as usize))
+    /// Serializes the filter so it can be stored in a table.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(self.bits.len() + 1);
+        encoded.extend_from_slice(&self.bits);
+        encoded.push(self.num_probes);
+        encoded
     }
 
-    fn hash2(&self, key: &Key) -> usize {
-        key.iter().fold(0, |acc, &b| acc.wrapping_mul(31).wrapping_add(b as usize))
+    /// Restores a filter serialized by `encode`.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let (&num_probes, bits) = data.split_last()?;
+        if bits.is_empty() || num_probes == 0 || num_probes > MAX_PROBES {
+            return None;
+        }
+        Some(Self {
+            bits: bits.to_vec(),
+            num_probes,
+        })
+    }
+}
+
+/// Returns the bit positions probed for `key`.
+fn probes(key: &[u8], num_probes: u8, num_bits: usize) -> impl Iterator<Item = usize> {
+    let hash = hash64(key);
+    let mut h = hash as u32;
+    let delta = (hash >> 32) as u32 | 1;
+    (0..num_probes).map(move |_| {
+        let bit = h as usize % num_bits;
+        h = h.wrapping_add(delta);
+        bit
+    })
+}
+
+/// MurmurHash64A.
+pub(crate) fn hash64(data: &[u8]) -> u64 {
+    const SEED: u64 = 0xbc9f_1d34;
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+
+    let mut h = SEED ^ (data.len() as u64).wrapping_mul(M);
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
     }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &b) in tail.iter().enumerate() {
+            h ^= (b as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::common::Key;
 
     #[test]
     fn test_bloom_filter_basic() {
@@ -67,11 +136,7 @@ mod tests {
     #[test]
     fn test_bloom_filter_add_and_contains() {
         let mut bf = BloomFilter::default();
-        let keys: Vec<Key> = vec![
-            b"apple".to_vec(),
-            b"banana".to_vec(),
-            b"cherry".to_vec(),
-        ];
+        let keys: Vec<Key> = vec![b"apple".to_vec(), b"banana".to_vec(), b"cherry".to_vec()];
 
         for key in &keys {
             bf.add(key);
@@ -81,14 +146,54 @@ mod tests {
             assert!(bf.contains(key), "Bloom Filter should contain {:?}", key);
         }
 
-        let non_keys: Vec<Key> = vec![
-            b"durian".to_vec(),
-            b"elderberry".to_vec(),
-            b"fig".to_vec(),
-        ];
+        let non_keys: Vec<Key> = vec![b"durian".to_vec(), b"elderberry".to_vec(), b"fig".to_vec()];
 
         for key in &non_keys {
-            assert!(!bf.contains(key), "Bloom Filter should not contain {:?}", key);
+            assert!(
+                !bf.contains(key),
+                "Bloom Filter should not contain {:?}",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn test_bloom_filter_encode_decode() {
+        let mut bf = BloomFilter::new(2, 10);
+        bf.add(b"alpha");
+        bf.add(b"beta");
+
+        let decoded = BloomFilter::decode(&bf.encode()).unwrap();
+        assert!(decoded.contains(b"alpha"));
+        assert!(decoded.contains(b"beta"));
+        assert_eq!(decoded.encode(), bf.encode());
+        assert!(BloomFilter::decode(&[]).is_none());
+        assert!(BloomFilter::decode(&[0xff]).is_none());
+        assert!(BloomFilter::decode(&[0xff, MAX_PROBES + 1]).is_none());
+    }
+
+    #[test]
+    fn test_bloom_filter_false_positive_rate() {
+        for num_keys in [1, 10, 100, 1000, 10_000] {
+            let mut bf = BloomFilter::new(num_keys, 10);
+            for i in 0..num_keys {
+                bf.add(format!("key{}", i).as_bytes());
+            }
+            assert!(bf.encode().len() <= (num_keys * 10).max(64) / 8 + 2);
+            for i in 0..num_keys {
+                assert!(bf.contains(format!("key{}", i).as_bytes()));
+            }
+
+            // 10 bits per key should give a false positive rate around 1%.
+            let false_positives = (0..10_000)
+                .filter(|i| bf.contains(format!("missing{}", i).as_bytes()))
+                .count();
+            assert!(
+                false_positives < 250,
+                "{} false positives with {} keys",
+                false_positives,
+                num_keys
+            );
         }
     }
 }
@@ -2,6 +2,13 @@ pub type Key = Vec<u8>;
 pub type Value = Vec<u8>;
 pub type SequenceNumber = u64;
 
+/// Returns the current time in seconds since the Unix epoch.
+pub fn unix_time_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
 #[derive(Debug, Clone)]
 pub struct SegmentHandle {
     offset: usize,
@@ -55,12 +62,36 @@ pub fn decode_var(src: &[u8]) -> Option<(usize, usize)> {
     None
 }
 
+/// Appends `n` to `dst` as a varint.
+pub fn put_var(dst: &mut Vec<u8>, n: usize) {
+    let mut buf = [0u8; 10];
+    let len = encode_var(n, &mut buf);
+    dst.extend_from_slice(&buf[..len]);
+}
+
+/// Appends `bytes` to `dst`, prefixed with its varint-encoded length.
+pub fn put_length_prefixed(dst: &mut Vec<u8>, bytes: &[u8]) {
+    put_var(dst, bytes.len());
+    dst.extend_from_slice(bytes);
+}
+
+/// Decodes a slice written by `put_length_prefixed`.
+/// Returns (slice, bytes_read) or None on failure.
+pub fn get_length_prefixed(src: &[u8]) -> Option<(&[u8], usize)> {
+    let (len, len_len) = decode_var(src)?;
+    let end = len_len.checked_add(len)?;
+    if end > src.len() {
+        return None;
+    }
+    Some((&src[len_len..end], end))
+}
+
 impl SegmentHandle {
+    /// Maximum encoded size of a handle: two 64-bit varints.
+    pub const MAX_ENCODED_LENGTH: usize = 10 + 10;
+
     pub fn new(offset: usize, length: usize) -> Self {
-        SegmentHandle {
-            offset: offset,
-            length: length,
-        }
+        SegmentHandle { offset, length }
     }
 
     pub fn offset(&self) -> usize {
@@ -0,0 +1,778 @@
+// Compaction merges tables to bound the number a read must search and to
+// reclaim the space of overwritten and deleted keys. Memtables are always
+// flushed to level 0, whose tables may overlap each other.
+//
+// Leveled: every other level holds disjoint tables and may grow to ten times
+// the size of the level above it. When level 0 holds too many tables, or a
+// level grows past its target size, tables from that level are merged with
+// the tables they overlap in the next level, and the merged output replaces
+// them there.
+//
+// Universal: every table stays in level 0 as a sorted run, ordered by age.
+// Runs of similar size are merged into one larger run, so each key is
+// rewritten about once per size tier instead of once per level.
+//
+// FIFO: every table stays in level 0 and nothing is merged. The oldest
+// tables are deleted once the tables grow past a size limit or outlive a
+// time-to-live.
+//
+// Merging drops versions of a key hidden by a newer version, and drops
+// tombstones once no older table can hold data for their key.
+
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::common::{unix_time_secs, SequenceNumber};
+use crate::comparator::Comparator;
+use crate::dbformat::{parse_internal_key, InternalKey, ValueType};
+use crate::filename;
+use crate::iterator::InternalIterator;
+use crate::options::{CompactionStyle, Options};
+use crate::sstable::SSTable;
+use crate::table_writer::TableBuilder;
+use crate::version_edit::{FileMetaData, VersionEdit};
+use crate::version_set::{Version, NUM_LEVELS};
+
+/// A set of tables to merge: `inputs[0]` from `level` and `inputs[1]` from
+/// `level + 1`. The output goes to `output_level`.
+pub struct Compaction {
+    pub level: usize,
+    pub output_level: usize,
+    pub inputs: [Vec<Arc<FileMetaData>>; 2],
+    max_output_file_size: u64,
+    // Drop the inputs without writing any output.
+    deletion: bool,
+    version: Arc<Version>,
+}
+
+impl Compaction {
+    /// Returns true if the inputs are simply dropped from the database.
+    pub fn is_deletion(&self) -> bool {
+        self.deletion
+    }
+
+    /// Returns true if the compaction can move its single input down a level
+    /// without merging anything.
+    pub fn is_trivial_move(&self) -> bool {
+        self.output_level != self.level && self.inputs[0].len() == 1 && self.inputs[1].is_empty()
+    }
+
+    /// Returns an edit that replaces the inputs with `outputs` in the output
+    /// level.
+    pub fn edit(&self, outputs: Vec<FileMetaData>) -> VersionEdit {
+        let mut edit = VersionEdit::default();
+        for (which, files) in self.inputs.iter().enumerate() {
+            for file in files {
+                edit.delete_file(self.level + which, file.number);
+            }
+        }
+        for file in outputs {
+            edit.add_file(self.output_level, file);
+        }
+        edit
+    }
+
+    /// Returns true if no table outside the compaction that is older than
+    /// its output can hold data for `user_key`, so a tombstone for it has
+    /// nothing left to hide.
+    fn is_base_level_for_key(&self, user_key: &[u8]) -> bool {
+        let is_input = |file: &FileMetaData| {
+            self.inputs
+                .iter()
+                .flatten()
+                .any(|input| input.number == file.number)
+        };
+        let deeper = self.version.files[self.output_level + 1..].iter().flatten();
+        // Level-0 tables overlap, so when the output stays in level 0 any
+        // table left there may hold older data.
+        let level0: &[Arc<FileMetaData>] = match self.output_level {
+            0 => &self.version.files[0],
+            _ => &[],
+        };
+        !deeper
+            .chain(level0.iter().filter(|f| !is_input(f)))
+            .any(|f| self.version.file_contains(f, user_key))
+    }
+}
+
+/// Chooses which tables to compact next.
+pub trait CompactionPicker: Send {
+    /// Returns the next compaction to run, or `None` if the database needs
+    /// none.
+    fn pick(&mut self, version: &Arc<Version>, options: &Options) -> Option<Compaction>;
+}
+
+/// Returns the picker implementing `style`.
+pub fn new_picker(style: CompactionStyle) -> Box<dyn CompactionPicker> {
+    match style {
+        CompactionStyle::Leveled => Box::<LeveledCompactionPicker>::default(),
+        CompactionStyle::Universal => Box::new(UniversalCompactionPicker),
+        CompactionStyle::Fifo => Box::new(FifoCompactionPicker),
+    }
+}
+
+/// Returns the size level `level` may grow to before it is compacted.
+pub fn max_bytes_for_level(options: &Options, level: usize) -> u64 {
+    let mut max_bytes = options.max_bytes_for_level_base;
+    for _ in 1..level {
+        max_bytes = max_bytes.saturating_mul(options.max_bytes_for_level_multiplier);
+    }
+    max_bytes
+}
+
+/// Estimates how many bytes compaction must rewrite to bring `version`
+/// within the targets of `options`.
+pub fn pending_compaction_bytes(version: &Version, options: &Options) -> u64 {
+    match options.compaction_style {
+        CompactionStyle::Leveled => {
+            let mut pending = 0;
+            // Bytes a level is due to push down into the next one.
+            let mut incoming = 0;
+            if version.files[0].len() >= options.level0_file_num_compaction_trigger.max(1) {
+                pending += version.level_bytes(0);
+                incoming = version.level_bytes(0);
+            }
+            for level in 1..NUM_LEVELS - 1 {
+                let bytes = version.level_bytes(level) + incoming;
+                let target = max_bytes_for_level(options, level);
+                incoming = bytes.saturating_sub(target);
+                if incoming > 0 {
+                    // The excess is rewritten along with the part of the
+                    // next level it overlaps.
+                    let next = version.level_bytes(level + 1) as f64;
+                    pending += incoming + (incoming as f64 * next / bytes as f64) as u64;
+                }
+            }
+            pending
+        }
+        CompactionStyle::Universal => {
+            // Every run but the oldest, largest one is due to be merged.
+            let runs = &version.files[0];
+            if runs.len() < options.level0_file_num_compaction_trigger.max(2) {
+                return 0;
+            }
+            runs[1..].iter().map(|f| f.file_size).sum()
+        }
+        // FIFO only ever deletes tables.
+        CompactionStyle::Fifo => 0,
+    }
+}
+
+/// Picks the next compaction of a leveled database.
+pub struct LeveledCompactionPicker {
+    // Largest key of the last compaction at each level. The next compaction
+    // at that level starts after it, so compactions cycle through the key
+    // space.
+    compact_pointers: Vec<Option<InternalKey>>,
+}
+
+impl Default for LeveledCompactionPicker {
+    fn default() -> Self {
+        Self {
+            compact_pointers: vec![None; NUM_LEVELS],
+        }
+    }
+}
+
+impl CompactionPicker for LeveledCompactionPicker {
+    /// Returns the compaction for the level furthest past its size target,
+    /// or `None` if every level is within its target.
+    fn pick(&mut self, version: &Arc<Version>, options: &Options) -> Option<Compaction> {
+        let mut best: Option<(f64, usize)> = None;
+        for level in 0..NUM_LEVELS - 1 {
+            let score = if level == 0 {
+                version.files[0].len() as f64
+                    / options.level0_file_num_compaction_trigger.max(1) as f64
+            } else {
+                version.level_bytes(level) as f64 / max_bytes_for_level(options, level) as f64
+            };
+            if score >= 1.0 && best.is_none_or(|(best_score, _)| score > best_score) {
+                best = Some((score, level));
+            }
+        }
+        let (_, level) = best?;
+
+        let files = &version.files[level];
+        let first = match &self.compact_pointers[level] {
+            Some(pointer) if level > 0 => files
+                .iter()
+                .find(|f| {
+                    version
+                        .comparator()
+                        .compare(f.largest.encode(), pointer.encode())
+                        == Ordering::Greater
+                })
+                .unwrap_or(&files[0]),
+            _ => &files[0],
+        };
+        let mut inputs = vec![first.clone()];
+        if level == 0 {
+            inputs =
+                version.overlapping_inputs(0, first.smallest.user_key(), first.largest.user_key());
+        }
+        let (smallest, largest) = key_range(version, &inputs);
+        let next_level_inputs =
+            version.overlapping_inputs(level + 1, smallest.user_key(), largest.user_key());
+        self.compact_pointers[level] = Some(largest);
+
+        Some(Compaction {
+            level,
+            output_level: level + 1,
+            inputs: [inputs, next_level_inputs],
+            max_output_file_size: options.target_file_size,
+            deletion: false,
+            version: version.clone(),
+        })
+    }
+}
+
+/// Picks the next compaction of a universal database, where every table is a
+/// sorted run in level 0.
+pub struct UniversalCompactionPicker;
+
+impl CompactionPicker for UniversalCompactionPicker {
+    /// Starting from the newest run, gathers runs while the next one is no
+    /// larger than the gathered runs combined, allowing for
+    /// `universal_size_ratio`. If that finds nothing to merge but there are
+    /// more than `universal_max_sorted_runs` runs, merges the newest runs
+    /// down to the limit.
+    fn pick(&mut self, version: &Arc<Version>, options: &Options) -> Option<Compaction> {
+        let runs: Vec<_> = version.files[0].iter().rev().collect();
+        let over_limit = runs.len() > options.universal_max_sorted_runs.max(1);
+        if runs.len() < options.level0_file_num_compaction_trigger.max(2) && !over_limit {
+            return None;
+        }
+
+        let mut count = 1;
+        let mut total = runs[0].file_size;
+        while count < runs.len() {
+            let size = runs[count].file_size;
+            if size.saturating_mul(100) > total.saturating_mul(100 + options.universal_size_ratio) {
+                break;
+            }
+            total += size;
+            count += 1;
+        }
+        if count < 2 {
+            if !over_limit {
+                return None;
+            }
+            count = runs.len() - options.universal_max_sorted_runs.max(1) + 1;
+        }
+
+        Some(Compaction {
+            level: 0,
+            output_level: 0,
+            inputs: [
+                runs[..count].iter().map(|&f| f.clone()).collect(),
+                Vec::new(),
+            ],
+            // A run is a single table.
+            max_output_file_size: u64::MAX,
+            deletion: false,
+            version: version.clone(),
+        })
+    }
+}
+
+/// Picks the tables a FIFO database should delete.
+pub struct FifoCompactionPicker;
+
+impl CompactionPicker for FifoCompactionPicker {
+    /// Returns the oldest tables that must go for the rest to fit in
+    /// `fifo_max_table_files_size`, or failing that, the tables older than
+    /// `fifo_ttl`.
+    fn pick(&mut self, version: &Arc<Version>, options: &Options) -> Option<Compaction> {
+        let files = &version.files[0];
+        let mut total = version.level_bytes(0);
+        let mut expired = Vec::new();
+        for file in files {
+            if total <= options.fifo_max_table_files_size {
+                break;
+            }
+            total -= file.file_size;
+            expired.push(file.clone());
+        }
+        if expired.is_empty() {
+            if let Some(ttl) = options.fifo_ttl {
+                let now = unix_time_secs();
+                expired = files
+                    .iter()
+                    .take_while(|f| now.saturating_sub(f.creation_time) >= ttl.as_secs())
+                    .cloned()
+                    .collect();
+            }
+        }
+        if expired.is_empty() {
+            return None;
+        }
+
+        Some(Compaction {
+            level: 0,
+            output_level: 0,
+            inputs: [expired, Vec::new()],
+            max_output_file_size: u64::MAX,
+            deletion: true,
+            version: version.clone(),
+        })
+    }
+}
+
+/// Returns the smallest and largest keys of `files`, in the order of
+/// `version`.
+fn key_range(version: &Version, files: &[Arc<FileMetaData>]) -> (InternalKey, InternalKey) {
+    let compare =
+        |a: &&InternalKey, b: &&InternalKey| version.comparator().compare(a.encode(), b.encode());
+    let smallest = files.iter().map(|f| &f.smallest).min_by(compare).unwrap();
+    let largest = files.iter().map(|f| &f.largest).max_by(compare).unwrap();
+    (smallest.clone(), largest.clone())
+}
+
+/// Merges the entries of `input`, which iterates over the compaction's input
+/// tables, into new tables in `dbname`. Each output table is numbered by
+/// `new_file_number`. Returns the metadata of the tables written.
+///
+/// A version is dropped when a newer version of its key is visible at
+/// `smallest_snapshot`, and so is a tombstone with nothing left to hide.
+pub fn merge(
+    compaction: &Compaction,
+    input: &mut dyn InternalIterator,
+    smallest_snapshot: SequenceNumber,
+    dbname: &Path,
+    options: &Options,
+    new_file_number: &mut dyn FnMut() -> u64,
+) -> io::Result<Vec<FileMetaData>> {
+    let creation_time = compaction
+        .inputs
+        .iter()
+        .flatten()
+        .map(|f| f.creation_time)
+        .min()
+        .unwrap_or_else(unix_time_secs);
+    let mut outputs = Vec::new();
+    let mut output: Option<Output> = None;
+    let mut current_user_key: Option<Vec<u8>> = None;
+    // Sequence number of the previous version of the current user key.
+    let mut last_sequence_for_key: Option<SequenceNumber> = None;
+
+    input.seek_to_first();
+    while input.valid() {
+        let key = input.key();
+        let parsed = parse_internal_key(key)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad internal key"))?;
+
+        let first_occurrence = current_user_key.as_deref().is_none_or(|current| {
+            options.comparator.compare(current, parsed.user_key) != Ordering::Equal
+        });
+        if first_occurrence {
+            // Only start a new table between user keys, so every version of
+            // a key stays in one table.
+            if output
+                .as_ref()
+                .is_some_and(|o| o.builder.file_size() >= compaction.max_output_file_size)
+            {
+                outputs.push(output.take().unwrap().finish()?);
+            }
+            current_user_key = Some(parsed.user_key.to_vec());
+            last_sequence_for_key = None;
+        }
+
+        // A newer version of the key that every reader can see hides this
+        // one.
+        let hidden = last_sequence_for_key.is_some_and(|sequence| sequence <= smallest_snapshot);
+        let obsolete_tombstone = parsed.value_type == ValueType::Deletion
+            && parsed.sequence <= smallest_snapshot
+            && compaction.is_base_level_for_key(parsed.user_key);
+        last_sequence_for_key = Some(parsed.sequence);
+
+        if !hidden && !obsolete_tombstone {
+            if output.is_none() {
+                let number = new_file_number();
+                let path = filename::table_file_name(dbname, number);
+                output = Some(Output {
+                    number,
+                    builder: SSTable::builder(&path, options)?,
+                    smallest: None,
+                    largest: Vec::new(),
+                    smallest_seqno: parsed.sequence,
+                    largest_seqno: parsed.sequence,
+                    creation_time,
+                });
+            }
+            output
+                .as_mut()
+                .unwrap()
+                .add(key, parsed.sequence, input.value())?;
+        }
+        input.next();
+    }
+    input.status()?;
+
+    if let Some(output) = output {
+        outputs.push(output.finish()?);
+    }
+    Ok(outputs)
+}
+
+/// A table being written by a compaction.
+struct Output {
+    number: u64,
+    builder: TableBuilder<BufWriter<File>>,
+    smallest: Option<InternalKey>,
+    largest: Vec<u8>,
+    smallest_seqno: SequenceNumber,
+    largest_seqno: SequenceNumber,
+    creation_time: u64,
+}
+
+impl Output {
+    fn add(&mut self, key: &[u8], sequence: SequenceNumber, value: &[u8]) -> io::Result<()> {
+        self.smallest_seqno = self.smallest_seqno.min(sequence);
+        self.largest_seqno = self.largest_seqno.max(sequence);
+        if self.smallest.is_none() {
+            self.smallest = InternalKey::decode_from(key);
+        }
+        self.largest.clear();
+        self.largest.extend_from_slice(key);
+        self.builder.add(key, value)
+    }
+
+    fn finish(self) -> io::Result<FileMetaData> {
+        let file_size = self.builder.finish()?;
+        Ok(FileMetaData {
+            number: self.number,
+            file_size,
+            smallest: self.smallest.unwrap(),
+            largest: InternalKey::decode_from(&self.largest).unwrap(),
+            smallest_seqno: self.smallest_seqno,
+            largest_seqno: self.largest_seqno,
+            creation_time: self.creation_time,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Value;
+    use crate::dbformat::{InternalKeyComparator, MAX_SEQUENCE_NUMBER};
+    use crate::memtable::MemTable;
+    use crate::merger::MergingIterator;
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!("saturn_compaction_{name}"));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn write_table(
+        dir: &Path,
+        number: u64,
+        entries: &[(&[u8], SequenceNumber, ValueType)],
+    ) -> io::Result<(SSTable, FileMetaData)> {
+        let entries: BTreeMap<InternalKey, Value> = entries
+            .iter()
+            .map(|&(key, sequence, value_type)| {
+                let value = format!("{}@{}", String::from_utf8_lossy(key), sequence);
+                (
+                    InternalKey::new(key, sequence, value_type),
+                    value.into_bytes(),
+                )
+            })
+            .collect();
+        let memtable = Arc::new(MemTable::new(usize::MAX));
+        for (key, value) in &entries {
+            let parsed = key.parse();
+            memtable.add(parsed.sequence, parsed.value_type, parsed.user_key, value);
+        }
+        let path = filename::table_file_name(dir, number);
+        let table = SSTable::write(&mut memtable.iter(), &path, &Options::default())?;
+        let file = FileMetaData {
+            number,
+            file_size: fs::metadata(&path)?.len(),
+            smallest: entries.keys().next().unwrap().clone(),
+            largest: entries.keys().next_back().unwrap().clone(),
+            smallest_seqno: entries.keys().map(|k| k.parse().sequence).min().unwrap(),
+            largest_seqno: entries.keys().map(|k| k.parse().sequence).max().unwrap(),
+            creation_time: unix_time_secs(),
+        };
+        Ok((table, file))
+    }
+
+    fn read_all(dir: &Path, file: &FileMetaData) -> io::Result<Vec<String>> {
+        let table = SSTable::open(
+            filename::table_file_name(dir, file.number),
+            &Options::default(),
+        )?;
+        let mut iter = table.iter();
+        iter.seek_to_first();
+        let mut entries = Vec::new();
+        while iter.valid() {
+            let parsed = parse_internal_key(iter.key()).unwrap();
+            entries.push(format!(
+                "{}@{}:{:?}",
+                String::from_utf8_lossy(parsed.user_key),
+                parsed.sequence,
+                parsed.value_type
+            ));
+            iter.next();
+        }
+        iter.status()?;
+        Ok(entries)
+    }
+
+    fn run(
+        dir: &Path,
+        version: Version,
+        tables: Vec<SSTable>,
+        smallest_snapshot: SequenceNumber,
+    ) -> io::Result<Vec<FileMetaData>> {
+        let version = Arc::new(version);
+        let compaction = Compaction {
+            level: 0,
+            output_level: 1,
+            inputs: [version.files[0].clone(), version.files[1].clone()],
+            max_output_file_size: u64::MAX,
+            deletion: false,
+            version,
+        };
+        let children = tables
+            .iter()
+            .map(|t| Box::new(t.iter()) as Box<dyn InternalIterator>)
+            .collect();
+        let comparator = Arc::new(InternalKeyComparator::new(Options::default().comparator));
+        let mut input = MergingIterator::new(comparator, children);
+        let mut next_number = 100;
+        merge(
+            &compaction,
+            &mut input,
+            smallest_snapshot,
+            dir,
+            &Options::default(),
+            &mut || {
+                next_number += 1;
+                next_number
+            },
+        )
+    }
+
+    #[test]
+    fn merge_drops_hidden_versions_and_tombstones() -> io::Result<()> {
+        let dir = temp_dir("merge");
+        let (newer, newer_file) = write_table(
+            &dir,
+            2,
+            &[(b"a", 5, ValueType::Value), (b"b", 6, ValueType::Deletion)],
+        )?;
+        let (older, older_file) = write_table(
+            &dir,
+            1,
+            &[
+                (b"a", 1, ValueType::Value),
+                (b"b", 2, ValueType::Value),
+                (b"c", 3, ValueType::Value),
+            ],
+        )?;
+
+        let mut version = Version::default();
+        version.files[0].push(Arc::new(newer_file.clone()));
+        version.files[1].push(Arc::new(older_file.clone()));
+        let outputs = run(&dir, version, vec![newer, older], MAX_SEQUENCE_NUMBER)?;
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(read_all(&dir, &outputs[0])?, ["a@5:Value", "c@3:Value"]);
+        assert_eq!(outputs[0].smallest.user_key(), b"a");
+        assert_eq!(outputs[0].largest.user_key(), b"c");
+
+        // A deeper level may still hold "b", so its tombstone must stay, and
+        // versions newer than the snapshot keep the one below them alive.
+        let (newer, _) = write_table(
+            &dir,
+            2,
+            &[(b"a", 5, ValueType::Value), (b"b", 6, ValueType::Deletion)],
+        )?;
+        let (older, _) = write_table(
+            &dir,
+            1,
+            &[
+                (b"a", 1, ValueType::Value),
+                (b"b", 2, ValueType::Value),
+                (b"c", 3, ValueType::Value),
+            ],
+        )?;
+        let mut version = Version::default();
+        version.files[0].push(Arc::new(newer_file));
+        version.files[1].push(Arc::new(older_file.clone()));
+        version.files[2].push(Arc::new(older_file));
+        let outputs = run(&dir, version, vec![newer, older], 4)?;
+        assert_eq!(
+            read_all(&dir, &outputs[0])?,
+            [
+                "a@5:Value",
+                "a@1:Value",
+                "b@6:Deletion",
+                "b@2:Value",
+                "c@3:Value"
+            ]
+        );
+        Ok(())
+    }
+
+    /// Returns a version whose level 0 holds a table of each (size, creation
+    /// time) in `tables`, from oldest to newest.
+    fn level0_version(tables: &[(u64, u64)]) -> Arc<Version> {
+        let mut version = Version::default();
+        for (i, &(size, creation_time)) in tables.iter().enumerate() {
+            let sequence = i as u64 + 1;
+            version.files[0].push(Arc::new(FileMetaData {
+                number: sequence,
+                file_size: size,
+                smallest: InternalKey::new(b"a", sequence, ValueType::Value),
+                largest: InternalKey::new(b"z", sequence, ValueType::Value),
+                smallest_seqno: sequence,
+                largest_seqno: sequence,
+                creation_time,
+            }));
+        }
+        Arc::new(version)
+    }
+
+    #[test]
+    fn universal_merges_runs_of_similar_size() {
+        let version_with = |sizes: &[u64]| {
+            let runs: Vec<_> = sizes.iter().map(|&size| (size, unix_time_secs())).collect();
+            level0_version(&runs)
+        };
+        let options = Options {
+            compaction_style: CompactionStyle::Universal,
+            level0_file_num_compaction_trigger: 3,
+            universal_max_sorted_runs: 4,
+            ..Options::default()
+        };
+        let mut picker = new_picker(options.compaction_style);
+        let picked = |picker: &mut Box<dyn CompactionPicker>, sizes: &[u64]| {
+            picker.pick(&version_with(sizes), &options).map(|c| {
+                assert_eq!(c.output_level, 0);
+                c.inputs[0].iter().map(|f| f.number).collect::<Vec<_>>()
+            })
+        };
+
+        // Too few runs.
+        assert_eq!(picked(&mut picker, &[100, 10]), None);
+        // The three newest runs are within the size ratio; the oldest is not.
+        assert_eq!(
+            picked(&mut picker, &[1000, 20, 10, 10]),
+            Some(vec![4, 3, 2])
+        );
+        // No similar sizes, and within the run limit.
+        assert_eq!(picked(&mut picker, &[1000, 100, 10]), None);
+        // Over the run limit: merge the newest runs down to it.
+        assert_eq!(
+            picked(&mut picker, &[100000, 10000, 1000, 100, 10]),
+            Some(vec![5, 4])
+        );
+    }
+
+    #[test]
+    fn fifo_drops_oldest_tables() {
+        let now = unix_time_secs();
+        let options = Options {
+            compaction_style: CompactionStyle::Fifo,
+            fifo_max_table_files_size: 250,
+            ..Options::default()
+        };
+        let mut picker = new_picker(options.compaction_style);
+        let dropped =
+            |picker: &mut Box<dyn CompactionPicker>, options: &Options, tables: &[(u64, u64)]| {
+                picker.pick(&level0_version(tables), options).map(|c| {
+                    assert!(c.is_deletion());
+                    c.inputs[0].iter().map(|f| f.number).collect::<Vec<_>>()
+                })
+            };
+
+        assert_eq!(
+            dropped(&mut picker, &options, &[(100, now), (100, now)]),
+            None
+        );
+        assert_eq!(
+            dropped(
+                &mut picker,
+                &options,
+                &[(100, now), (100, now), (100, now), (100, now)]
+            ),
+            Some(vec![1, 2])
+        );
+
+        let options = Options {
+            fifo_ttl: Some(Duration::from_secs(3600)),
+            ..options
+        };
+        assert_eq!(
+            dropped(
+                &mut picker,
+                &options,
+                &[(10, now - 7200), (10, now - 3600), (10, now - 60)]
+            ),
+            Some(vec![1, 2])
+        );
+        assert_eq!(dropped(&mut picker, &options, &[(10, now - 60)]), None);
+    }
+
+    #[test]
+    fn level_targets_grow_by_the_multiplier() {
+        let options = Options::default();
+        assert_eq!(max_bytes_for_level(&options, 1), 10 * 1024 * 1024);
+        assert_eq!(max_bytes_for_level(&options, 3), 1000 * 1024 * 1024);
+    }
+
+    #[test]
+    fn estimates_pending_compaction_bytes() {
+        let now = unix_time_secs();
+        let options = Options {
+            level0_file_num_compaction_trigger: 2,
+            max_bytes_for_level_base: 1000,
+            ..Options::default()
+        };
+        assert_eq!(
+            pending_compaction_bytes(&level0_version(&[(100, now)]), &options),
+            0
+        );
+        assert_eq!(
+            pending_compaction_bytes(&level0_version(&[(100, now), (100, now)]), &options),
+            200
+        );
+
+        // Level 1 is 500 bytes past its target once level 0 is pushed into
+        // it, and level 2 holds as much data as level 1.
+        let table = level0_version(&[(250, now)]).files[0][0].clone();
+        let mut version = Version::default();
+        for (level, count) in [(0, 2), (1, 4), (2, 6)] {
+            version.files[level] = vec![table.clone(); count];
+        }
+        assert_eq!(
+            pending_compaction_bytes(&version, &options),
+            500 + 500 + 500 * 1500 / 1500
+        );
+
+        let universal = Options {
+            compaction_style: CompactionStyle::Universal,
+            level0_file_num_compaction_trigger: 3,
+            ..Options::default()
+        };
+        let runs = level0_version(&[(1000, now), (100, now), (10, now)]);
+        assert_eq!(pending_compaction_bytes(&runs, &universal), 110);
+        let fifo = Options {
+            compaction_style: CompactionStyle::Fifo,
+            ..Options::default()
+        };
+        assert_eq!(pending_compaction_bytes(&runs, &fifo), 0);
+    }
+}
@@ -67,6 +67,22 @@ impl Comparator for BytewiseComparator {
     // so we don't need to override them here.
 }
 
+/// Orders keys bytewise under another name, for testing that a database
+/// or table refuses a comparator other than the one it was written with.
+#[cfg(test)]
+pub(crate) struct RenamedComparator;
+
+#[cfg(test)]
+impl Comparator for RenamedComparator {
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+
+    fn name(&self) -> &'static str {
+        "RenamedComparator"
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -36,14 +36,14 @@ pub mod crc32c {
     // Same masking LevelDB uses: rotate-right by 15, add a constant.
     #[inline]
     pub fn mask(crc: u32) -> u32 {
-        ((crc >> 15) | (crc << 17)).wrapping_add(0xA282_EAD8)
+        crc.rotate_right(15).wrapping_add(0xA282_EAD8)
     }
 
     #[allow(dead_code)]
     #[inline]
     pub fn unmask(masked: u32) -> u32 {
         let rot = masked.wrapping_sub(0xA282_EAD8);
-        (rot << 15) | (rot >> 17)
+        rot.rotate_left(15)
     }
 
     #[inline]
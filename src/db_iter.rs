@@ -0,0 +1,332 @@
+// A DBIterator turns the stream of internal keys produced by merging the
+// memtable and every table into the user's view of the database at one
+// sequence number: for each user key only the newest visible version is
+// shown, and keys whose newest visible version is a deletion are skipped.
+//
+// Moving forward, the merged iterator sits on the entry being shown. Moving
+// backward, it sits just before every entry for the shown key, which is
+// copied out into `saved_key` and `saved_value`.
+
+use std::cmp::Ordering;
+use std::io;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use crate::common::SequenceNumber;
+use crate::comparator::Comparator;
+use crate::dbformat::{
+    extract_user_key, parse_internal_key, InternalKey, ValueType, VALUE_TYPE_FOR_SEEK,
+};
+use crate::iterator::InternalIterator;
+use crate::merger::MergingIterator;
+use crate::version_set::Version;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Reverse,
+}
+
+/// Iterates over the live keys of a database in comparator order, as of
+/// the moment the iterator was created. Created by `SaturnDB::iter`,
+/// `SaturnDB::range` and `SaturnDB::prefix`, which may limit the keys seen
+/// to a range.
+pub struct DBIterator {
+    iter: MergingIterator,
+    user_comparator: Arc<dyn Comparator>,
+    sequence: SequenceNumber,
+    lower: Bound<Vec<u8>>,
+    upper: Bound<Vec<u8>>,
+    direction: Direction,
+    valid: bool,
+    saved_key: Vec<u8>,
+    saved_value: Vec<u8>,
+    error: Option<io::Error>,
+    // Keeps the tables being read from being deleted by compactions.
+    _version: Arc<Version>,
+}
+
+impl DBIterator {
+    pub(crate) fn new(
+        iter: MergingIterator,
+        user_comparator: Arc<dyn Comparator>,
+        sequence: SequenceNumber,
+        (lower, upper): (Bound<Vec<u8>>, Bound<Vec<u8>>),
+        version: Arc<Version>,
+    ) -> Self {
+        Self {
+            iter,
+            user_comparator,
+            sequence,
+            lower,
+            upper,
+            direction: Direction::Forward,
+            valid: false,
+            saved_key: Vec::new(),
+            saved_value: Vec::new(),
+            error: None,
+            _version: version,
+        }
+    }
+
+    /// Returns true if the iterator is positioned at a key within its range.
+    pub fn valid(&self) -> bool {
+        self.valid && self.above_lower(self.key()) && self.below_upper(self.key())
+    }
+
+    pub fn key(&self) -> &[u8] {
+        debug_assert!(self.valid);
+        match self.direction {
+            Direction::Forward => extract_user_key(self.iter.key()),
+            Direction::Reverse => &self.saved_key,
+        }
+    }
+
+    pub fn value(&self) -> &[u8] {
+        debug_assert!(self.valid);
+        match self.direction {
+            Direction::Forward => self.iter.value(),
+            Direction::Reverse => &self.saved_value,
+        }
+    }
+
+    /// Returns the first error hit while iterating, such as a corrupted
+    /// table. Iteration stops early when one is hit.
+    pub fn status(&self) -> io::Result<()> {
+        if let Some(err) = &self.error {
+            return Err(io::Error::new(err.kind(), err.to_string()));
+        }
+        self.iter.status()
+    }
+
+    pub fn seek_to_first(&mut self) {
+        match self.lower.clone() {
+            Bound::Included(start) => self.seek_unbounded(&start),
+            Bound::Excluded(start) => {
+                self.seek_unbounded(&start);
+                if self.valid && self.compare(self.key(), &start) == Ordering::Equal {
+                    self.next();
+                }
+            }
+            Bound::Unbounded => {
+                self.direction = Direction::Forward;
+                self.saved_value.clear();
+                self.iter.seek_to_first();
+                if self.iter.valid() {
+                    self.find_next_user_entry(false);
+                } else {
+                    self.valid = false;
+                }
+            }
+        }
+    }
+
+    pub fn seek_to_last(&mut self) {
+        match self.upper.clone() {
+            Bound::Included(end) => {
+                self.seek_unbounded(&end);
+                if !self.valid {
+                    self.seek_to_last_unbounded();
+                } else if self.compare(self.key(), &end) == Ordering::Greater {
+                    self.prev();
+                }
+            }
+            Bound::Excluded(end) => {
+                self.seek_unbounded(&end);
+                if self.valid {
+                    self.prev();
+                } else {
+                    self.seek_to_last_unbounded();
+                }
+            }
+            Bound::Unbounded => self.seek_to_last_unbounded(),
+        }
+    }
+
+    /// Positions at the first key at or after `target`.
+    pub fn seek(&mut self, target: &[u8]) {
+        if self.above_lower(target) {
+            self.seek_unbounded(target);
+        } else {
+            self.seek_to_first();
+        }
+    }
+
+    pub fn next(&mut self) {
+        debug_assert!(self.valid);
+        if self.direction == Direction::Reverse {
+            // The merged iterator is just before the entries for the current
+            // key, whose user key is in `saved_key`; step into them and let
+            // the scan below skip past them.
+            self.direction = Direction::Forward;
+            if self.iter.valid() {
+                self.iter.next();
+            } else {
+                self.iter.seek_to_first();
+            }
+        } else {
+            // Remember the current key so its older versions are skipped.
+            self.saved_key = extract_user_key(self.iter.key()).to_vec();
+            self.iter.next();
+        }
+        if !self.iter.valid() {
+            self.valid = false;
+            self.saved_key.clear();
+            return;
+        }
+        self.find_next_user_entry(true);
+    }
+
+    pub fn prev(&mut self) {
+        debug_assert!(self.valid);
+        if self.direction == Direction::Forward {
+            // Back up past every entry for the current key, so the scan
+            // below starts at the entries of an earlier key.
+            self.saved_key = extract_user_key(self.iter.key()).to_vec();
+            loop {
+                self.iter.prev();
+                if !self.iter.valid() {
+                    self.valid = false;
+                    self.saved_key.clear();
+                    self.saved_value.clear();
+                    return;
+                }
+                if self.compare(extract_user_key(self.iter.key()), &self.saved_key)
+                    == Ordering::Less
+                {
+                    break;
+                }
+            }
+            self.direction = Direction::Reverse;
+        }
+        self.find_prev_user_entry();
+    }
+
+    fn seek_unbounded(&mut self, target: &[u8]) {
+        self.direction = Direction::Forward;
+        self.saved_key.clear();
+        self.saved_value.clear();
+        self.iter
+            .seek(InternalKey::new(target, self.sequence, VALUE_TYPE_FOR_SEEK).encode());
+        if self.iter.valid() {
+            self.find_next_user_entry(false);
+        } else {
+            self.valid = false;
+        }
+    }
+
+    fn seek_to_last_unbounded(&mut self) {
+        self.direction = Direction::Reverse;
+        self.saved_value.clear();
+        self.iter.seek_to_last();
+        self.find_prev_user_entry();
+    }
+
+    /// Moves forward to the newest visible version of the next live user
+    /// key. If `skipping`, entries for user keys up to `saved_key` are
+    /// hidden.
+    fn find_next_user_entry(&mut self, mut skipping: bool) {
+        debug_assert!(self.iter.valid());
+        debug_assert_eq!(self.direction, Direction::Forward);
+        loop {
+            match parse_internal_key(self.iter.key()) {
+                Some(parsed) if parsed.sequence <= self.sequence => match parsed.value_type {
+                    ValueType::Deletion => {
+                        // Hide every older version of the deleted key.
+                        self.saved_key = parsed.user_key.to_vec();
+                        skipping = true;
+                    }
+                    ValueType::Value => {
+                        if !skipping
+                            || self.compare(parsed.user_key, &self.saved_key) == Ordering::Greater
+                        {
+                            self.valid = true;
+                            self.saved_key.clear();
+                            return;
+                        }
+                    }
+                },
+                Some(_) => {}
+                None => self.record_corruption(),
+            }
+            self.iter.next();
+            if !self.iter.valid() {
+                break;
+            }
+        }
+        self.saved_key.clear();
+        self.valid = false;
+    }
+
+    /// Moves backward to the previous live user key, leaving its newest
+    /// visible version in `saved_key` and `saved_value`.
+    fn find_prev_user_entry(&mut self) {
+        debug_assert_eq!(self.direction, Direction::Reverse);
+        let mut value_type = ValueType::Deletion;
+        while self.iter.valid() {
+            match parse_internal_key(self.iter.key()) {
+                Some(parsed) if parsed.sequence <= self.sequence => {
+                    if value_type != ValueType::Deletion
+                        && self.compare(parsed.user_key, &self.saved_key) == Ordering::Less
+                    {
+                        // Every version of the saved key has been seen.
+                        break;
+                    }
+                    value_type = parsed.value_type;
+                    match value_type {
+                        ValueType::Deletion => {
+                            self.saved_key.clear();
+                            self.saved_value.clear();
+                        }
+                        ValueType::Value => {
+                            self.saved_key = parsed.user_key.to_vec();
+                            self.saved_value = self.iter.value().to_vec();
+                        }
+                    }
+                }
+                Some(_) => {}
+                None => self.record_corruption(),
+            }
+            self.iter.prev();
+        }
+
+        if value_type == ValueType::Deletion {
+            // Ran off the beginning without finding a live key.
+            self.valid = false;
+            self.saved_key.clear();
+            self.saved_value.clear();
+            self.direction = Direction::Forward;
+        } else {
+            self.valid = true;
+        }
+    }
+
+    fn above_lower(&self, key: &[u8]) -> bool {
+        match &self.lower {
+            Bound::Included(start) => self.compare(key, start) != Ordering::Less,
+            Bound::Excluded(start) => self.compare(key, start) == Ordering::Greater,
+            Bound::Unbounded => true,
+        }
+    }
+
+    fn below_upper(&self, key: &[u8]) -> bool {
+        match &self.upper {
+            Bound::Included(end) => self.compare(key, end) != Ordering::Greater,
+            Bound::Excluded(end) => self.compare(key, end) == Ordering::Less,
+            Bound::Unbounded => true,
+        }
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        self.user_comparator.compare(a, b)
+    }
+
+    fn record_corruption(&mut self) {
+        if self.error.is_none() {
+            self.error = Some(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "bad internal key in database iterator",
+            ));
+        }
+    }
+}
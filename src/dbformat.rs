@@ -0,0 +1,325 @@
+// Internal keys tag every user key with the sequence number of the write that
+// produced it and the kind of write:
+//
+// +----------------------------------------------------+
+// | user key | (sequence << 8 | value type): u64 LE     |
+// +----------------------------------------------------+
+//
+// Internal keys order by user key, then by decreasing sequence number, so the
+// newest version of a key is the first one a seek finds.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::common::{SequenceNumber, Value};
+use crate::comparator::Comparator;
+
+/// Largest sequence number that fits in the 56 bits of a trailer.
+pub const MAX_SEQUENCE_NUMBER: SequenceNumber = (1 << 56) - 1;
+
+/// Kind of write recorded under an internal key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    Deletion = 0,
+    Value = 1,
+}
+
+/// Value type used when building a key to seek to. Internal keys with the
+/// same user key and sequence number sort by decreasing type, so this must
+/// be the largest type.
+pub const VALUE_TYPE_FOR_SEEK: ValueType = ValueType::Value;
+
+impl ValueType {
+    fn from_u8(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(ValueType::Deletion),
+            1 => Some(ValueType::Value),
+            _ => None,
+        }
+    }
+}
+
+fn pack_sequence_and_type(sequence: SequenceNumber, value_type: ValueType) -> u64 {
+    debug_assert!(sequence <= MAX_SEQUENCE_NUMBER);
+    (sequence << 8) | value_type as u64
+}
+
+/// Outcome of looking a user key up in a memtable or table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LookupResult {
+    /// The newest visible write is a put of this value.
+    Found(Value),
+    /// The newest visible write is a deletion.
+    Deleted,
+    /// There is no visible write to the key.
+    NotFound,
+}
+
+impl LookupResult {
+    pub fn from_entry(value_type: ValueType, value: Value) -> Self {
+        match value_type {
+            ValueType::Value => LookupResult::Found(value),
+            ValueType::Deletion => LookupResult::Deleted,
+        }
+    }
+}
+
+/// The components of an encoded internal key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParsedInternalKey<'a> {
+    pub user_key: &'a [u8],
+    pub sequence: SequenceNumber,
+    pub value_type: ValueType,
+}
+
+/// Splits an encoded internal key into its components.
+pub fn parse_internal_key(internal_key: &[u8]) -> Option<ParsedInternalKey<'_>> {
+    if internal_key.len() < 8 {
+        return None;
+    }
+    let (user_key, trailer) = internal_key.split_at(internal_key.len() - 8);
+    let trailer = u64::from_le_bytes(trailer.try_into().unwrap());
+    Some(ParsedInternalKey {
+        user_key,
+        sequence: trailer >> 8,
+        value_type: ValueType::from_u8(trailer as u8)?,
+    })
+}
+
+/// Returns the user key part of an encoded internal key.
+pub fn extract_user_key(internal_key: &[u8]) -> &[u8] {
+    debug_assert!(internal_key.len() >= 8);
+    &internal_key[..internal_key.len() - 8]
+}
+
+fn extract_trailer(internal_key: &[u8]) -> u64 {
+    u64::from_le_bytes(internal_key[internal_key.len() - 8..].try_into().unwrap())
+}
+
+/// An encoded internal key.
+///
+/// `Ord` compares user keys bytewise; use `InternalKeyComparator` when keys
+/// are ordered by a custom `Comparator`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct InternalKey(Vec<u8>);
+
+impl InternalKey {
+    pub fn new(user_key: &[u8], sequence: SequenceNumber, value_type: ValueType) -> Self {
+        let mut encoded = Vec::with_capacity(user_key.len() + 8);
+        encoded.extend_from_slice(user_key);
+        encoded.extend(pack_sequence_and_type(sequence, value_type).to_le_bytes());
+        InternalKey(encoded)
+    }
+
+    /// Wraps an encoded internal key, checking that it is well formed.
+    pub fn decode_from(encoded: &[u8]) -> Option<Self> {
+        parse_internal_key(encoded)?;
+        Some(InternalKey(encoded.to_vec()))
+    }
+
+    pub fn encode(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn user_key(&self) -> &[u8] {
+        extract_user_key(&self.0)
+    }
+
+    pub fn parse(&self) -> ParsedInternalKey<'_> {
+        parse_internal_key(&self.0).unwrap()
+    }
+}
+
+/// Orders user keys bytewise. The keys of a database are ordered by its
+/// comparator instead, through an `InternalKeyComparator`.
+impl Ord for InternalKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.user_key()
+            .cmp(other.user_key())
+            .then_with(|| extract_trailer(&other.0).cmp(&extract_trailer(&self.0)))
+    }
+}
+
+impl PartialOrd for InternalKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Debug for InternalKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parsed = self.parse();
+        write!(
+            f,
+            "{:?} @ {} : {:?}",
+            String::from_utf8_lossy(parsed.user_key),
+            parsed.sequence,
+            parsed.value_type
+        )
+    }
+}
+
+/// Orders encoded internal keys by user key, using the wrapped comparator,
+/// then by decreasing sequence number.
+pub struct InternalKeyComparator {
+    user_comparator: Arc<dyn Comparator>,
+}
+
+impl InternalKeyComparator {
+    pub fn new(user_comparator: Arc<dyn Comparator>) -> Self {
+        Self { user_comparator }
+    }
+
+    pub fn user_comparator(&self) -> &Arc<dyn Comparator> {
+        &self.user_comparator
+    }
+}
+
+impl Comparator for InternalKeyComparator {
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        self.user_comparator
+            .compare(extract_user_key(a), extract_user_key(b))
+            .then_with(|| extract_trailer(b).cmp(&extract_trailer(a)))
+    }
+
+    /// Named after the user comparator, which is what decides the order of
+    /// a table of internal keys.
+    fn name(&self) -> &'static str {
+        self.user_comparator.name()
+    }
+
+    fn find_shortest_separator(&self, start: &[u8], limit: &[u8]) -> Vec<u8> {
+        // Shorten the user key part, then give the result the earliest
+        // possible position among keys sharing that user key.
+        let user_start = extract_user_key(start);
+        let user_limit = extract_user_key(limit);
+        let mut separator = self
+            .user_comparator
+            .find_shortest_separator(user_start, user_limit);
+        if separator.len() < user_start.len()
+            && self.user_comparator.compare(user_start, &separator) == Ordering::Less
+        {
+            separator.extend(
+                pack_sequence_and_type(MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK).to_le_bytes(),
+            );
+            debug_assert_eq!(self.compare(start, &separator), Ordering::Less);
+            debug_assert_eq!(self.compare(&separator, limit), Ordering::Less);
+            return separator;
+        }
+        start.to_vec()
+    }
+
+    fn find_short_successor(&self, key: &[u8]) -> Vec<u8> {
+        let user_key = extract_user_key(key);
+        let mut successor = self.user_comparator.find_short_successor(user_key);
+        if successor.len() < user_key.len()
+            && self.user_comparator.compare(user_key, &successor) == Ordering::Less
+        {
+            successor.extend(
+                pack_sequence_and_type(MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK).to_le_bytes(),
+            );
+            debug_assert_eq!(self.compare(key, &successor), Ordering::Less);
+            return successor;
+        }
+        key.to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::BytewiseComparator;
+
+    fn ikey(user_key: &[u8], sequence: SequenceNumber, value_type: ValueType) -> Vec<u8> {
+        InternalKey::new(user_key, sequence, value_type)
+            .encode()
+            .to_vec()
+    }
+
+    #[test]
+    fn encode_and_parse() {
+        for user_key in [&b""[..], b"k", b"hello", b"longggggggggggggggggggggg"] {
+            for sequence in [
+                1,
+                2,
+                3,
+                (1 << 8) - 1,
+                1 << 8,
+                (1 << 32) + 1,
+                MAX_SEQUENCE_NUMBER,
+            ] {
+                for value_type in [ValueType::Value, ValueType::Deletion] {
+                    let encoded = ikey(user_key, sequence, value_type);
+                    let parsed = parse_internal_key(&encoded).unwrap();
+                    assert_eq!(parsed.user_key, user_key);
+                    assert_eq!(parsed.sequence, sequence);
+                    assert_eq!(parsed.value_type, value_type);
+                }
+            }
+        }
+        assert!(parse_internal_key(b"bar").is_none());
+        let mut bad_type = ikey(b"foo", 1, ValueType::Value);
+        bad_type[3] = 7;
+        assert!(InternalKey::decode_from(&bad_type).is_none());
+    }
+
+    #[test]
+    fn ordering() {
+        let comparator = InternalKeyComparator::new(Arc::new(BytewiseComparator::new()));
+        let keys = [
+            InternalKey::new(b"a", 5, ValueType::Value),
+            InternalKey::new(b"a", 3, ValueType::Value),
+            InternalKey::new(b"a", 3, ValueType::Deletion),
+            InternalKey::new(b"b", 9, ValueType::Deletion),
+            InternalKey::new(b"b", 1, ValueType::Value),
+        ];
+        for pair in keys.windows(2) {
+            assert!(pair[0] < pair[1]);
+            assert_eq!(
+                comparator.compare(pair[0].encode(), pair[1].encode()),
+                Ordering::Less
+            );
+        }
+    }
+
+    #[test]
+    fn shortest_separator_and_successor() {
+        let comparator = InternalKeyComparator::new(Arc::new(BytewiseComparator::new()));
+        let max = |user_key: &[u8]| ikey(user_key, MAX_SEQUENCE_NUMBER, VALUE_TYPE_FOR_SEEK);
+
+        // Same user key: cannot shorten.
+        assert_eq!(
+            comparator.find_shortest_separator(
+                &ikey(b"foo", 100, ValueType::Value),
+                &ikey(b"foo", 99, ValueType::Value)
+            ),
+            ikey(b"foo", 100, ValueType::Value)
+        );
+        // Misordered or prefix user keys are left alone.
+        assert_eq!(
+            comparator.find_shortest_separator(
+                &ikey(b"foo", 100, ValueType::Value),
+                &ikey(b"foobar", 200, ValueType::Value)
+            ),
+            ikey(b"foo", 100, ValueType::Value)
+        );
+        // Shortened user key gets the maximal trailer.
+        assert_eq!(
+            comparator.find_shortest_separator(
+                &ikey(b"foo", 100, ValueType::Value),
+                &ikey(b"hello", 200, ValueType::Value)
+            ),
+            max(b"g")
+        );
+
+        assert_eq!(
+            comparator.find_short_successor(&ikey(b"foo", 100, ValueType::Value)),
+            max(b"g")
+        );
+        assert_eq!(
+            comparator.find_short_successor(&ikey(b"\xff\xff", 100, ValueType::Value)),
+            ikey(b"\xff\xff", 100, ValueType::Value)
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const CURRENT_FILE_NAME: &str = "CURRENT";
+const MANIFEST_PREFIX: &str = "MANIFEST-";
+const LOG_SUFFIX: &str = ".log";
+const TABLE_SUFFIX: &str = ".sst";
+const TEMP_SUFFIX: &str = ".dbtmp";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    WriteAheadLog,
+    Table,
+    Manifest,
+    Current,
+    Temp,
+}
+
+/// Each memtable has its own write-ahead log, numbered from the same
+/// sequence as tables and manifests.
+pub fn log_file_name(dbname: &Path, number: u64) -> PathBuf {
+    dbname.join(format!("{:06}{}", number, LOG_SUFFIX))
+}
+
+pub fn table_file_name(dbname: &Path, number: u64) -> PathBuf {
+    dbname.join(format!("{:06}{}", number, TABLE_SUFFIX))
+}
+
+pub fn manifest_file_name(dbname: &Path, number: u64) -> PathBuf {
+    dbname.join(format!("{}{:06}", MANIFEST_PREFIX, number))
+}
+
+/// The CURRENT file holds the name of the live manifest.
+pub fn current_file_name(dbname: &Path) -> PathBuf {
+    dbname.join(CURRENT_FILE_NAME)
+}
+
+pub fn temp_file_name(dbname: &Path, number: u64) -> PathBuf {
+    dbname.join(format!("{:06}{}", number, TEMP_SUFFIX))
+}
+
+/// Atomically points CURRENT at the manifest numbered `manifest_number`.
+pub fn set_current_file(dbname: &Path, manifest_number: u64) -> io::Result<()> {
+    let manifest = manifest_file_name(Path::new(""), manifest_number);
+    let tmp = temp_file_name(dbname, manifest_number);
+    let result = (|| {
+        let mut file = fs::File::create(&tmp)?;
+        writeln!(file, "{}", manifest.display())?;
+        file.sync_all()?;
+        fs::rename(&tmp, current_file_name(dbname))
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+fn parse_number(digits: &str) -> Option<u64> {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// Parses a file name found in a database directory.
+/// Returns the file number (0 if the file has none) and its type.
+pub fn parse_file_name(name: &str) -> Option<(u64, FileType)> {
+    if name == CURRENT_FILE_NAME {
+        return Some((0, FileType::Current));
+    }
+    if let Some(number) = name.strip_prefix(MANIFEST_PREFIX) {
+        return Some((parse_number(number)?, FileType::Manifest));
+    }
+    if let Some(number) = name.strip_suffix(LOG_SUFFIX) {
+        return Some((parse_number(number)?, FileType::WriteAheadLog));
+    }
+    if let Some(number) = name.strip_suffix(TABLE_SUFFIX) {
+        return Some((parse_number(number)?, FileType::Table));
+    }
+    if let Some(number) = name.strip_suffix(TEMP_SUFFIX) {
+        return Some((parse_number(number)?, FileType::Temp));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(path: &Path) -> Option<(u64, FileType)> {
+        parse_file_name(path.file_name().unwrap().to_str().unwrap())
+    }
+
+    #[test]
+    fn parse_round_trip() {
+        let dir = Path::new("/db");
+        let table = table_file_name(dir, 42);
+        assert_eq!(table, Path::new("/db/000042.sst"));
+        assert_eq!(parse(&table), Some((42, FileType::Table)));
+        assert_eq!(
+            parse(&log_file_name(dir, 3)),
+            Some((3, FileType::WriteAheadLog))
+        );
+        assert_eq!(
+            parse(&manifest_file_name(dir, 7)),
+            Some((7, FileType::Manifest))
+        );
+        assert_eq!(parse(&current_file_name(dir)), Some((0, FileType::Current)));
+        assert_eq!(parse(&temp_file_name(dir, 9)), Some((9, FileType::Temp)));
+
+        assert_eq!(parse_file_name("foo.sst"), None);
+        assert_eq!(parse_file_name(".sst"), None);
+        assert_eq!(parse_file_name("MANIFEST-"), None);
+        assert_eq!(parse_file_name("000001.txt"), None);
+        assert_eq!(parse_file_name("wal.log"), None);
+    }
+}
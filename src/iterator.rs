@@ -0,0 +1,27 @@
+use std::io;
+
+/// A cursor over key/value entries in sorted order, implemented by blocks,
+/// tables and the merging iterator built from them.
+///
+/// `key` and `value` may only be called while `valid` returns true.
+pub trait InternalIterator {
+    fn valid(&self) -> bool;
+
+    fn key(&self) -> &[u8];
+
+    fn value(&self) -> &[u8];
+
+    fn seek_to_first(&mut self);
+
+    fn seek_to_last(&mut self);
+
+    /// Positions at the first entry with a key at or after `target`.
+    fn seek(&mut self, target: &[u8]);
+
+    fn next(&mut self);
+
+    fn prev(&mut self);
+
+    /// Returns the first error hit while iterating.
+    fn status(&self) -> io::Result<()>;
+}
@@ -1,14 +1,36 @@
 pub mod common;
-// pub mod saturndb;
+pub mod saturndb;
 pub mod memtable;
-mod wal_reader;
+// The reader's tests predate the lint gate and are kept as written.
+#[cfg_attr(
+    test,
+    allow(clippy::manual_rotate, clippy::manual_repeat_n, clippy::useless_vec)
+)]
+pub mod wal_reader;
 pub mod wal;
 pub mod wal_writer;
 pub mod wal_format;
 pub mod crc;
-// pub mod sstable;
+pub mod sstable;
 pub mod bloom_filter;
 pub mod skiplist;
-mod table_writer;
-mod comparator;
+pub mod table_writer;
+pub mod comparator;
 mod compaction;
+pub mod options;
+mod filename;
+mod table_cache;
+pub mod version_edit;
+pub mod version_set;
+pub mod block;
+pub mod table_reader;
+pub mod dbformat;
+pub mod iterator;
+pub mod merger;
+pub mod db_iter;
+pub mod snapshot;
+pub mod write_batch;
+mod write_queue;
+pub mod write_stall;
+pub mod arena;
+pub mod prefix_extractor;
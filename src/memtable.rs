@@ -1,52 +1,837 @@
+use std::cmp;
 use std::collections::BTreeMap;
+use std::io;
+use std::mem;
+use std::ops::Bound;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock, RwLockReadGuard};
 
-use crate::common::{Key, Value, SequenceNumber};
+use crate::bloom_filter::hash64;
+use crate::common::{SequenceNumber, Value};
+use crate::comparator::{BytewiseComparator, Comparator};
+use crate::dbformat::{
+    extract_user_key, parse_internal_key, InternalKey, InternalKeyComparator, LookupResult,
+    ValueType, VALUE_TYPE_FOR_SEEK,
+};
+use crate::iterator::InternalIterator;
+use crate::options::MemTableBackendKind;
+use crate::prefix_extractor::PrefixExtractor;
+use crate::skiplist::{SkipList, SkipListIterator};
 
-pub trait MemTableBackend {
-    fn insert(&mut self, key: Key, val: (Value, SequenceNumber));
-    fn remove(&mut self, key: &Key) -> Option<(Value, SequenceNumber)>;
-    fn get(&self, key: &Key) -> Option<&(Value, SequenceNumber)>;
+/// The reads a memtable backend can serve efficiently. Every backend can be
+/// iterated in order, which is how it is flushed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemTableCapabilities {
+    /// Looking up a key without going through every entry.
+    pub point_lookups: bool,
+    /// Iterating in order without first copying and sorting every entry. A
+    /// flush pays for the copy once per memtable, but a database iterator
+    /// would pay for it each time one is created, so the database flushes
+    /// such a memtable before creating one.
+    pub total_order_iteration: bool,
+}
+
+/// Storage for the entries of a memtable, keyed by encoded internal key.
+/// Each key is inserted at most once. Reads may run concurrently with an
+/// insert.
+pub trait MemTableBackend: Send + Sync {
+    fn insert(&self, key: &[u8], value: &[u8]);
     fn len(&self) -> usize;
-    fn flush(&mut self);
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Returns the bytes of memory taken by the entries.
+    fn approximate_memory_usage(&self) -> usize;
+    fn capabilities(&self) -> MemTableCapabilities;
+    /// Returns the first entry at or after `target`. Backends that group
+    /// keys by prefix only search the keys with the prefix of `target`'s
+    /// user key, so the entry is only meaningful if it has that user key.
+    fn seek(&self, target: &[u8]) -> Option<(Vec<u8>, Value)>;
+    /// Returns an iterator over the entries in order, which keeps the
+    /// backend alive. Entries inserted while iterating may or may not be
+    /// seen.
+    fn iter(self: Arc<Self>) -> Box<dyn InternalIterator>;
+}
+
+/// Fails if a backend of the given kind cannot work with these options.
+pub fn check_options(
+    kind: MemTableBackendKind,
+    prefix_extractor: Option<&Arc<dyn PrefixExtractor>>,
+) -> io::Result<()> {
+    let invalid = |reason| Err(io::Error::new(io::ErrorKind::InvalidInput, reason));
+    match kind {
+        MemTableBackendKind::HashSkipList { .. } if prefix_extractor.is_none() => {
+            invalid("the hash skip list memtable needs a prefix extractor")
+        }
+        MemTableBackendKind::HashSkipList { bucket_count: 0 } => {
+            invalid("the hash skip list memtable needs at least one bucket")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Creates an empty backend of the given kind, ordering internal keys with
+/// `comparator`. Fails if `check_options` does.
+pub fn new_backend(
+    kind: MemTableBackendKind,
+    comparator: Arc<InternalKeyComparator>,
+    prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+) -> io::Result<Arc<dyn MemTableBackend>> {
+    check_options(kind, prefix_extractor.as_ref())?;
+    Ok(match kind {
+        MemTableBackendKind::BTree => Arc::new(BTreeBackend::new(comparator)),
+        MemTableBackendKind::SkipList => Arc::new(SkipList::new(comparator)),
+        MemTableBackendKind::HashSkipList { bucket_count } => Arc::new(HashSkipListBackend::new(
+            comparator,
+            prefix_extractor.expect("checked by check_options"),
+            bucket_count,
+        )),
+        MemTableBackendKind::Vector => Arc::new(VectorBackend::new(comparator)),
+    })
+}
+
+/// Keeps entries in a `BTreeMap` ordered by the database comparator.
+/// Inserts block reads.
+pub struct BTreeBackend {
+    comparator: Arc<InternalKeyComparator>,
+    map: RwLock<BTreeMap<OrderedKey, Value>>,
+    memory_usage: AtomicUsize,
+}
+
+/// An encoded internal key, ordered by the comparator it carries.
+#[derive(Clone)]
+struct OrderedKey {
+    key: Vec<u8>,
+    comparator: Arc<InternalKeyComparator>,
+}
+
+impl PartialEq for OrderedKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == cmp::Ordering::Equal
+    }
+}
+
+impl Eq for OrderedKey {}
+
+impl PartialOrd for OrderedKey {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OrderedKey {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self.comparator.compare(&self.key, &other.key)
+    }
+}
+
+impl BTreeBackend {
+    pub fn new(comparator: Arc<InternalKeyComparator>) -> Self {
+        Self {
+            comparator,
+            map: RwLock::new(BTreeMap::new()),
+            memory_usage: AtomicUsize::new(0),
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, BTreeMap<OrderedKey, Value>> {
+        self.map.read().unwrap()
+    }
+
+    fn ordered_key(&self, key: &[u8]) -> OrderedKey {
+        OrderedKey {
+            key: key.to_vec(),
+            comparator: self.comparator.clone(),
+        }
+    }
+}
+
+impl MemTableBackend for BTreeBackend {
+    fn insert(&self, key: &[u8], value: &[u8]) {
+        debug_assert!(parse_internal_key(key).is_some());
+        // The map's own overhead per entry is not known; count the entry.
+        let usage = mem::size_of::<(OrderedKey, Value)>() + key.len() + value.len();
+        let key = self.ordered_key(key);
+        self.map.write().unwrap().insert(key, value.to_vec());
+        self.memory_usage.fetch_add(usage, Ordering::Relaxed);
+    }
+
+    fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    fn approximate_memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+
+    fn capabilities(&self) -> MemTableCapabilities {
+        MemTableCapabilities {
+            point_lookups: true,
+            total_order_iteration: true,
+        }
+    }
+
+    fn seek(&self, target: &[u8]) -> Option<(Vec<u8>, Value)> {
+        let map = self.read();
+        let (key, value) = map.range(self.ordered_key(target)..).next()?;
+        Some((key.key.clone(), value.clone()))
+    }
+
+    fn iter(self: Arc<Self>) -> Box<dyn InternalIterator> {
+        Box::new(BTreeBackendIterator {
+            backend: self,
+            current: None,
+        })
+    }
+}
+
+/// Iterates over a `BTreeBackend`. Each step looks up the neighbour of the
+/// current key, so the map is not locked between steps.
+struct BTreeBackendIterator {
+    backend: Arc<BTreeBackend>,
+    current: Option<(OrderedKey, Value)>,
+}
+
+impl BTreeBackendIterator {
+    fn position(&mut self, entry: Option<(&OrderedKey, &Value)>) {
+        self.current = entry.map(|(key, value)| (key.clone(), value.clone()));
+    }
+}
+
+impl InternalIterator for BTreeBackendIterator {
+    fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.current.as_ref().unwrap().0.key
+    }
+
+    fn value(&self) -> &[u8] {
+        &self.current.as_ref().unwrap().1
+    }
+
+    fn seek_to_first(&mut self) {
+        let backend = self.backend.clone();
+        self.position(backend.read().iter().next());
+    }
+
+    fn seek_to_last(&mut self) {
+        let backend = self.backend.clone();
+        self.position(backend.read().iter().next_back());
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        let backend = self.backend.clone();
+        let target = backend.ordered_key(target);
+        self.position(backend.read().range(target..).next());
+    }
+
+    fn next(&mut self) {
+        let (key, _) = self.current.take().unwrap();
+        let backend = self.backend.clone();
+        let map = backend.read();
+        self.position(map.range((Bound::Excluded(key), Bound::Unbounded)).next());
+    }
+
+    fn prev(&mut self) {
+        let (key, _) = self.current.take().unwrap();
+        let backend = self.backend.clone();
+        self.position(backend.read().range(..key).next_back());
+    }
+
+    fn status(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads never wait for inserts.
+impl MemTableBackend for SkipList {
+    fn insert(&self, key: &[u8], value: &[u8]) {
+        self.insert(key, value);
+    }
+
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn approximate_memory_usage(&self) -> usize {
+        self.approximate_memory_usage()
+    }
+
+    fn capabilities(&self) -> MemTableCapabilities {
+        MemTableCapabilities {
+            point_lookups: true,
+            total_order_iteration: true,
+        }
+    }
+
+    fn seek(&self, target: &[u8]) -> Option<(Vec<u8>, Value)> {
+        let mut iter = self.iter();
+        iter.seek(target);
+        iter.valid()
+            .then(|| (iter.key().to_vec(), iter.value().to_vec()))
+    }
+
+    fn iter(self: Arc<Self>) -> Box<dyn InternalIterator> {
+        Box::new(SkipListIterator::new(self))
+    }
+}
+
+/// Hashes the prefix of each user key to one of a fixed number of buckets,
+/// each a skip list created on its first insert. Lookups only search one
+/// bucket, but iterating in order means copying and sorting every entry.
+pub struct HashSkipListBackend {
+    comparator: Arc<InternalKeyComparator>,
+    prefix_extractor: Arc<dyn PrefixExtractor>,
+    buckets: Box<[OnceLock<SkipList>]>,
+    len: AtomicUsize,
+}
+
+impl HashSkipListBackend {
+    pub fn new(
+        comparator: Arc<InternalKeyComparator>,
+        prefix_extractor: Arc<dyn PrefixExtractor>,
+        bucket_count: usize,
+    ) -> Self {
+        Self {
+            comparator,
+            prefix_extractor,
+            buckets: (0..bucket_count).map(|_| OnceLock::new()).collect(),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the bucket for the user key of the internal key `key`.
+    fn bucket(&self, key: &[u8]) -> &OnceLock<SkipList> {
+        let prefix = self.prefix_extractor.prefix(extract_user_key(key));
+        &self.buckets[(hash64(prefix) % self.buckets.len() as u64) as usize]
+    }
+}
+
+impl MemTableBackend for HashSkipListBackend {
+    fn insert(&self, key: &[u8], value: &[u8]) {
+        self.bucket(key)
+            .get_or_init(|| SkipList::new(self.comparator.clone()))
+            .insert(key, value);
+        self.len.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    fn approximate_memory_usage(&self) -> usize {
+        let buckets = self.buckets.len() * mem::size_of::<OnceLock<SkipList>>();
+        let lists: usize = self
+            .buckets
+            .iter()
+            .filter_map(OnceLock::get)
+            .map(SkipList::approximate_memory_usage)
+            .sum();
+        buckets + lists
+    }
+
+    fn capabilities(&self) -> MemTableCapabilities {
+        // `iter` merges the buckets by copying and sorting every entry. That
+        // is fine for the one flush of each memtable, not for every iterator.
+        MemTableCapabilities {
+            point_lookups: true,
+            total_order_iteration: false,
+        }
+    }
+
+    fn seek(&self, target: &[u8]) -> Option<(Vec<u8>, Value)> {
+        MemTableBackend::seek(self.bucket(target).get()?, target)
+    }
+
+    fn iter(self: Arc<Self>) -> Box<dyn InternalIterator> {
+        let mut entries = Vec::with_capacity(self.len());
+        for list in self.buckets.iter().filter_map(OnceLock::get) {
+            let mut iter = list.iter();
+            iter.seek_to_first();
+            while iter.valid() {
+                entries.push((iter.key().to_vec(), iter.value().to_vec()));
+                iter.next();
+            }
+        }
+        entries.sort_by(|a, b| self.comparator.compare(&a.0, &b.0));
+        Box::new(SortedEntriesIterator::new(
+            Arc::new(entries),
+            self.comparator.clone(),
+        ))
+    }
+}
+
+/// Appends entries to a vector, which is only sorted when iterated. Meant
+/// for bulk loads that read nothing until the memtable is flushed: a lookup
+/// goes through every entry.
+pub struct VectorBackend {
+    comparator: Arc<InternalKeyComparator>,
+    entries: Mutex<VectorEntries>,
+    memory_usage: AtomicUsize,
+}
+
+struct VectorEntries {
+    // Shared with the iterators created since it was last sorted.
+    entries: Arc<Vec<(Vec<u8>, Value)>>,
+    sorted: bool,
+}
+
+impl VectorBackend {
+    pub fn new(comparator: Arc<InternalKeyComparator>) -> Self {
+        Self {
+            comparator,
+            entries: Mutex::new(VectorEntries {
+                entries: Arc::new(Vec::new()),
+                sorted: true,
+            }),
+            memory_usage: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl MemTableBackend for VectorBackend {
+    fn insert(&self, key: &[u8], value: &[u8]) {
+        let usage = mem::size_of::<(Vec<u8>, Value)>() + key.len() + value.len();
+        let mut state = self.entries.lock().unwrap();
+        // Copies the entries if an iterator still holds them.
+        Arc::make_mut(&mut state.entries).push((key.to_vec(), value.to_vec()));
+        state.sorted = false;
+        self.memory_usage.fetch_add(usage, Ordering::Relaxed);
+    }
+
+    fn len(&self) -> usize {
+        self.entries.lock().unwrap().entries.len()
+    }
+
+    fn approximate_memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::Relaxed)
+    }
+
+    fn capabilities(&self) -> MemTableCapabilities {
+        MemTableCapabilities {
+            point_lookups: false,
+            total_order_iteration: false,
+        }
+    }
+
+    fn seek(&self, target: &[u8]) -> Option<(Vec<u8>, Value)> {
+        let state = self.entries.lock().unwrap();
+        state
+            .entries
+            .iter()
+            .filter(|(key, _)| self.comparator.compare(key, target) != cmp::Ordering::Less)
+            .min_by(|a, b| self.comparator.compare(&a.0, &b.0))
+            .cloned()
+    }
+
+    fn iter(self: Arc<Self>) -> Box<dyn InternalIterator> {
+        let mut state = self.entries.lock().unwrap();
+        if !state.sorted {
+            Arc::make_mut(&mut state.entries).sort_by(|a, b| self.comparator.compare(&a.0, &b.0));
+            state.sorted = true;
+        }
+        Box::new(SortedEntriesIterator::new(
+            state.entries.clone(),
+            self.comparator.clone(),
+        ))
+    }
+}
+
+/// Iterates over a sorted copy of the entries of a backend.
+struct SortedEntriesIterator {
+    entries: Arc<Vec<(Vec<u8>, Value)>>,
+    comparator: Arc<InternalKeyComparator>,
+    // `entries.len()` when not positioned at an entry.
+    index: usize,
+}
+
+impl SortedEntriesIterator {
+    fn new(entries: Arc<Vec<(Vec<u8>, Value)>>, comparator: Arc<InternalKeyComparator>) -> Self {
+        let index = entries.len();
+        Self {
+            entries,
+            comparator,
+            index,
+        }
+    }
+}
+
+impl InternalIterator for SortedEntriesIterator {
+    fn valid(&self) -> bool {
+        self.index < self.entries.len()
+    }
+
+    fn key(&self) -> &[u8] {
+        &self.entries[self.index].0
+    }
+
+    fn value(&self) -> &[u8] {
+        &self.entries[self.index].1
+    }
+
+    fn seek_to_first(&mut self) {
+        self.index = 0;
+    }
+
+    fn seek_to_last(&mut self) {
+        // An empty vector leaves the index at its length.
+        self.index = self.entries.len().saturating_sub(1);
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        self.index = self.entries.partition_point(|(key, _)| {
+            self.comparator.compare(key, target) == cmp::Ordering::Less
+        });
+    }
+
+    fn next(&mut self) {
+        assert!(self.valid());
+        self.index += 1;
+    }
+
+    fn prev(&mut self) {
+        assert!(self.valid());
+        self.index = self.index.checked_sub(1).unwrap_or(self.entries.len());
+    }
+
+    fn status(&self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
+/// Buffers recent writes in memory, keyed by internal key. Every put and
+/// delete adds a new entry; older versions of a key are kept until flush.
+///
+/// A memtable is shared through an `Arc`, so iterators can keep reading it
+/// after the database has moved on to a new one.
 pub struct MemTable {
-    map: BTreeMap<Key, (Value, SequenceNumber)>,
-    pub tombstones: BTreeMap<Key, SequenceNumber>,
-    pub current_sequence_number: SequenceNumber,
+    backend: Arc<dyn MemTableBackend>,
+    comparator: Arc<InternalKeyComparator>,
+    write_buffer_size: usize,
 }
 
 impl MemTable {
-    pub fn new() -> Self {
+    /// Creates an empty memtable backed by a `BTreeMap` ordered bytewise
+    /// that reports itself full once its entries take `write_buffer_size`
+    /// bytes.
+    pub fn new(write_buffer_size: usize) -> Self {
+        let comparator = Arc::new(InternalKeyComparator::new(Arc::new(
+            BytewiseComparator::new(),
+        )));
+        let backend = BTreeBackend::new(comparator.clone());
+        Self::with_backend(Arc::new(backend), comparator, write_buffer_size)
+    }
+
+    /// Creates a memtable keeping its entries in `backend`, which must be
+    /// empty and ordered by `comparator`.
+    pub fn with_backend(
+        backend: Arc<dyn MemTableBackend>,
+        comparator: Arc<InternalKeyComparator>,
+        write_buffer_size: usize,
+    ) -> Self {
+        debug_assert!(backend.is_empty());
         Self {
-            map: BTreeMap::new(),
-            tombstones: BTreeMap::new(),
-            current_sequence_number: 0,
+            backend,
+            comparator,
+            write_buffer_size,
+        }
+    }
+
+    /// Records a write with sequence number `sequence`. The value of a
+    /// deletion is ignored.
+    pub fn add(&self, sequence: SequenceNumber, value_type: ValueType, key: &[u8], value: &[u8]) {
+        let value = match value_type {
+            ValueType::Value => value,
+            ValueType::Deletion => &[],
+        };
+        let key = InternalKey::new(key, sequence, value_type);
+        self.backend.insert(key.encode(), value);
+    }
+
+    /// Looks up the newest write to `key` with a sequence number at or below
+    /// `sequence`.
+    pub fn get(&self, key: &[u8], sequence: SequenceNumber) -> LookupResult {
+        let lookup = InternalKey::new(key, sequence, VALUE_TYPE_FOR_SEEK);
+        let Some((found_key, value)) = self.backend.seek(lookup.encode()) else {
+            return LookupResult::NotFound;
+        };
+        match parse_internal_key(&found_key) {
+            Some(found)
+                if self
+                    .comparator
+                    .user_comparator()
+                    .compare(found.user_key, key)
+                    == cmp::Ordering::Equal =>
+            {
+                LookupResult::from_entry(found.value_type, value)
+            }
+            _ => LookupResult::NotFound,
         }
     }
 
-    pub fn insert(&mut self, key: Key, value: Value) {
-        self.current_sequence_number += 1;
-        self.map.insert(key, (value, self.current_sequence_number));
+    pub fn len(&self) -> usize {
+        self.backend.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
-    pub fn delete(&mut self, key: Key) {
-        self.current_sequence_number += 1;
-        self.map.remove(&key);
-        self.tombstones.insert(key, self.current_sequence_number);
+    /// Returns the bytes of memory taken by the entries.
+    pub fn approximate_memory_usage(&self) -> usize {
+        self.backend.approximate_memory_usage()
     }
 
-    pub fn get(&self, key: &Key) -> Option<&(Value, SequenceNumber)> {
-        self.map.get(key)
+    pub fn capabilities(&self) -> MemTableCapabilities {
+        self.backend.capabilities()
     }
 
     pub fn is_full(&self) -> bool {
-        self.map.len() + self.tombstones.len() >= 1000
+        self.approximate_memory_usage() >= self.write_buffer_size
     }
 
-    pub fn flush(&mut self) -> (BTreeMap<Key, (Value, SequenceNumber)>, BTreeMap<Key, SequenceNumber>) {
-        let data = std::mem::replace(&mut self.map, BTreeMap::new());
-        let tombstones = std::mem::replace(&mut self.tombstones, BTreeMap::new());
-        (data, tombstones)
+    /// Returns an iterator over every entry, keyed by encoded internal key.
+    /// Entries added while iterating may or may not be seen.
+    pub fn iter(&self) -> MemTableIterator {
+        MemTableIterator {
+            iter: self.backend.clone().iter(),
+        }
+    }
+}
+
+/// Iterates over a `MemTable`, keeping its entries alive.
+pub struct MemTableIterator {
+    iter: Box<dyn InternalIterator>,
+}
+
+impl InternalIterator for MemTableIterator {
+    fn valid(&self) -> bool {
+        self.iter.valid()
+    }
+
+    fn key(&self) -> &[u8] {
+        self.iter.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn seek_to_first(&mut self) {
+        self.iter.seek_to_first();
+    }
+
+    fn seek_to_last(&mut self) {
+        self.iter.seek_to_last();
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        self.iter.seek(target);
+    }
+
+    fn next(&mut self) {
+        self.iter.next();
+    }
+
+    fn prev(&mut self) {
+        self.iter.prev();
+    }
+
+    fn status(&self) -> io::Result<()> {
+        self.iter.status()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prefix_extractor::FixedPrefix;
+
+    fn comparator() -> Arc<InternalKeyComparator> {
+        Arc::new(InternalKeyComparator::new(Arc::new(
+            BytewiseComparator::new(),
+        )))
+    }
+
+    /// Returns an empty memtable of each kind of backend.
+    fn memtables() -> Vec<Arc<MemTable>> {
+        [
+            MemTableBackendKind::BTree,
+            MemTableBackendKind::SkipList,
+            MemTableBackendKind::HashSkipList { bucket_count: 16 },
+            MemTableBackendKind::Vector,
+        ]
+        .into_iter()
+        .map(|kind| {
+            let prefix_extractor = Arc::new(FixedPrefix::new(1));
+            let backend = new_backend(kind, comparator(), Some(prefix_extractor)).unwrap();
+            Arc::new(MemTable::with_backend(backend, comparator(), 4096))
+        })
+        .collect()
+    }
+
+    #[test]
+    fn newest_visible_version_wins() {
+        for memtable in memtables() {
+            newest_visible_version_wins_in(memtable);
+        }
+    }
+
+    fn newest_visible_version_wins_in(memtable: Arc<MemTable>) {
+        memtable.add(1, ValueType::Value, b"k", b"v1");
+        memtable.add(2, ValueType::Deletion, b"k", b"");
+        memtable.add(3, ValueType::Value, b"k", b"v3");
+        memtable.add(4, ValueType::Value, b"other", b"x");
+
+        assert_eq!(memtable.get(b"k", 10), LookupResult::Found(b"v3".to_vec()));
+        assert_eq!(memtable.get(b"k", 2), LookupResult::Deleted);
+        assert_eq!(memtable.get(b"k", 1), LookupResult::Found(b"v1".to_vec()));
+        assert_eq!(memtable.get(b"k", 0), LookupResult::NotFound);
+        assert_eq!(memtable.get(b"j", 10), LookupResult::NotFound);
+        assert_eq!(memtable.len(), 4);
+
+        let mut iter = memtable.iter();
+        iter.seek_to_first();
+        let mut keys = Vec::new();
+        while iter.valid() {
+            keys.push(InternalKey::decode_from(iter.key()).unwrap());
+            iter.next();
+        }
+        assert_eq!(keys[0], InternalKey::new(b"k", 3, ValueType::Value));
+        assert_eq!(keys[2], InternalKey::new(b"k", 1, ValueType::Value));
+        assert_eq!(keys.len(), 4);
+    }
+
+    #[test]
+    fn iterate_in_both_directions() {
+        for memtable in memtables() {
+            if memtable.capabilities().total_order_iteration {
+                iterate_in_both_directions_in(memtable);
+            }
+        }
+    }
+
+    fn iterate_in_both_directions_in(memtable: Arc<MemTable>) {
+        for (i, key) in [b"c", b"a", b"d", b"b"].iter().enumerate() {
+            memtable.add(i as SequenceNumber + 1, ValueType::Value, *key, *key);
+        }
+        let mut iter = memtable.iter();
+        iter.seek(InternalKey::new(b"b", 10, VALUE_TYPE_FOR_SEEK).encode());
+        assert_eq!(iter.value(), b"b");
+        iter.prev();
+        assert_eq!(iter.value(), b"a");
+        iter.prev();
+        assert!(!iter.valid());
+
+        iter.seek_to_last();
+        assert_eq!(iter.value(), b"d");
+        iter.prev();
+        assert_eq!(iter.value(), b"c");
+
+        // Entries added after the iterator was created are found by later
+        // steps.
+        memtable.add(5, ValueType::Value, b"e", b"e");
+        iter.next();
+        iter.next();
+        assert_eq!(iter.value(), b"e");
+    }
+
+    #[test]
+    fn put_after_delete_is_visible() {
+        for memtable in memtables() {
+            memtable.add(1, ValueType::Value, b"k", b"v1");
+            memtable.add(2, ValueType::Deletion, b"k", b"");
+            assert_eq!(memtable.get(b"k", 2), LookupResult::Deleted);
+            memtable.add(3, ValueType::Value, b"k", b"v3");
+            assert_eq!(memtable.get(b"k", 3), LookupResult::Found(b"v3".to_vec()));
+        }
+    }
+
+    #[test]
+    fn fills_up_by_memory_usage() {
+        for memtable in memtables() {
+            let empty = memtable.approximate_memory_usage();
+            memtable.add(1, ValueType::Value, b"k", &[0; 1000]);
+            assert!(memtable.approximate_memory_usage() >= empty + 1000);
+            assert!(!memtable.is_full());
+            for sequence in 2..5 {
+                memtable.add(sequence, ValueType::Value, b"k", &[0; 1000]);
+            }
+            assert!(memtable.is_full());
+        }
+    }
+
+    #[test]
+    fn hash_skip_list_groups_keys_by_prefix() {
+        let kind = MemTableBackendKind::HashSkipList { bucket_count: 4 };
+        let prefix_extractor: Arc<dyn PrefixExtractor> = Arc::new(FixedPrefix::new(2));
+        let err = new_backend(kind, comparator(), None).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let no_buckets = MemTableBackendKind::HashSkipList { bucket_count: 0 };
+        let err = check_options(no_buckets, Some(&prefix_extractor))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let backend = new_backend(kind, comparator(), Some(prefix_extractor)).unwrap();
+        assert!(!backend.capabilities().total_order_iteration);
+        let memtable = MemTable::with_backend(backend, comparator(), 1 << 20);
+        // More prefixes than buckets, so some share a bucket.
+        let keys: Vec<String> = (0..20).map(|i| format!("{}{}", i % 8, i)).collect();
+        for (i, key) in keys.iter().enumerate() {
+            memtable.add(
+                i as SequenceNumber + 1,
+                ValueType::Value,
+                key.as_bytes(),
+                &[i as u8],
+            );
+        }
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(
+                memtable.get(key.as_bytes(), 100),
+                LookupResult::Found(vec![i as u8])
+            );
+        }
+        assert_eq!(memtable.get(b"09", 100), LookupResult::NotFound);
+
+        let mut iter = memtable.iter();
+        iter.seek_to_first();
+        let mut found = Vec::new();
+        while iter.valid() {
+            found.push(extract_user_key(iter.key()).to_vec());
+            iter.next();
+        }
+        let mut expected: Vec<Vec<u8>> = keys.into_iter().map(String::into_bytes).collect();
+        expected.sort();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn vector_sorts_when_iterated() {
+        let backend = new_backend(MemTableBackendKind::Vector, comparator(), None).unwrap();
+        assert!(!backend.capabilities().point_lookups);
+        let memtable = MemTable::with_backend(backend, comparator(), 1 << 20);
+        for (i, key) in [b"c", b"a", b"b"].iter().enumerate() {
+            memtable.add(i as SequenceNumber + 1, ValueType::Value, *key, *key);
+        }
+        let mut iter = memtable.iter();
+        iter.seek_to_last();
+        assert_eq!(iter.value(), b"c");
+        iter.seek(InternalKey::new(b"b", 10, VALUE_TYPE_FOR_SEEK).encode());
+        assert_eq!(iter.value(), b"b");
+        iter.prev();
+        assert_eq!(iter.value(), b"a");
+        iter.prev();
+        assert!(!iter.valid());
+
+        // An iterator keeps the entries it was created with.
+        memtable.add(4, ValueType::Value, b"0", b"0");
+        iter.seek_to_first();
+        assert_eq!(iter.value(), b"a");
+        let mut iter = memtable.iter();
+        iter.seek_to_first();
+        assert_eq!(iter.value(), b"0");
     }
 }
@@ -0,0 +1,232 @@
+use std::cmp::Ordering;
+use std::io;
+use std::sync::Arc;
+
+use crate::comparator::Comparator;
+use crate::iterator::InternalIterator;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Reverse,
+}
+
+/// Merges several sorted iterators into one sorted stream. Entries with
+/// equal keys are all yielded, in the order of the children when moving
+/// forward. Changing direction assumes keys are distinct across children,
+/// which holds for internal keys.
+pub struct MergingIterator {
+    comparator: Arc<dyn Comparator>,
+    children: Vec<Box<dyn InternalIterator>>,
+    current: Option<usize>,
+    direction: Direction,
+}
+
+impl MergingIterator {
+    pub fn new(comparator: Arc<dyn Comparator>, children: Vec<Box<dyn InternalIterator>>) -> Self {
+        Self {
+            comparator,
+            children,
+            current: None,
+            direction: Direction::Forward,
+        }
+    }
+
+    fn find_smallest(&mut self) {
+        let mut smallest: Option<usize> = None;
+        for (i, child) in self.children.iter().enumerate() {
+            if !child.valid() {
+                continue;
+            }
+            smallest = match smallest {
+                Some(s)
+                    if self.comparator.compare(child.key(), self.children[s].key())
+                        != Ordering::Less =>
+                {
+                    Some(s)
+                }
+                _ => Some(i),
+            };
+        }
+        self.current = smallest;
+    }
+
+    fn find_largest(&mut self) {
+        let mut largest: Option<usize> = None;
+        for (i, child) in self.children.iter().enumerate().rev() {
+            if !child.valid() {
+                continue;
+            }
+            largest = match largest {
+                Some(l)
+                    if self.comparator.compare(child.key(), self.children[l].key())
+                        != Ordering::Greater =>
+                {
+                    Some(l)
+                }
+                _ => Some(i),
+            };
+        }
+        self.current = largest;
+    }
+}
+
+impl InternalIterator for MergingIterator {
+    fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn key(&self) -> &[u8] {
+        self.children[self.current.unwrap()].key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.children[self.current.unwrap()].value()
+    }
+
+    fn seek_to_first(&mut self) {
+        for child in &mut self.children {
+            child.seek_to_first();
+        }
+        self.direction = Direction::Forward;
+        self.find_smallest();
+    }
+
+    fn seek_to_last(&mut self) {
+        for child in &mut self.children {
+            child.seek_to_last();
+        }
+        self.direction = Direction::Reverse;
+        self.find_largest();
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        for child in &mut self.children {
+            child.seek(target);
+        }
+        self.direction = Direction::Forward;
+        self.find_smallest();
+    }
+
+    fn next(&mut self) {
+        let current = self.current.unwrap();
+        if self.direction != Direction::Forward {
+            // The other children are positioned before the current key;
+            // move each of them to the first entry after it.
+            let key = self.key().to_vec();
+            for (i, child) in self.children.iter_mut().enumerate() {
+                if i == current {
+                    continue;
+                }
+                child.seek(&key);
+                if child.valid() && self.comparator.compare(&key, child.key()) == Ordering::Equal {
+                    child.next();
+                }
+            }
+            self.direction = Direction::Forward;
+        }
+        self.children[current].next();
+        self.find_smallest();
+    }
+
+    fn prev(&mut self) {
+        let current = self.current.unwrap();
+        if self.direction != Direction::Reverse {
+            // The other children are positioned after the current key; move
+            // each of them to the last entry before it.
+            let key = self.key().to_vec();
+            for (i, child) in self.children.iter_mut().enumerate() {
+                if i == current {
+                    continue;
+                }
+                child.seek(&key);
+                if child.valid() {
+                    child.prev();
+                } else {
+                    child.seek_to_last();
+                }
+            }
+            self.direction = Direction::Reverse;
+        }
+        self.children[current].prev();
+        self.find_largest();
+    }
+
+    fn status(&self) -> io::Result<()> {
+        for child in &self.children {
+            child.status()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{Block, BlockBuilder};
+    use crate::comparator::BytewiseComparator;
+
+    fn block_iter(keys: &[&str]) -> Box<dyn InternalIterator> {
+        let mut builder = BlockBuilder::new(16);
+        for key in keys {
+            builder.add(key.as_bytes(), b"");
+        }
+        let block = Block::new(builder.finish()).unwrap();
+        Box::new(block.iter(Arc::new(BytewiseComparator::new())))
+    }
+
+    #[test]
+    fn merges_in_order() -> io::Result<()> {
+        let mut iter = MergingIterator::new(
+            Arc::new(BytewiseComparator::new()),
+            vec![
+                block_iter(&["b", "d", "f"]),
+                block_iter(&[]),
+                block_iter(&["a", "d", "g"]),
+            ],
+        );
+        iter.seek_to_first();
+        let mut keys = Vec::new();
+        while iter.valid() {
+            keys.push(String::from_utf8(iter.key().to_vec()).unwrap());
+            iter.next();
+        }
+        assert_eq!(keys, ["a", "b", "d", "d", "f", "g"]);
+        iter.status()?;
+
+        iter.seek("e".as_bytes());
+        assert_eq!(iter.key(), b"f");
+        Ok(())
+    }
+
+    #[test]
+    fn changes_direction() {
+        let mut iter = MergingIterator::new(
+            Arc::new(BytewiseComparator::new()),
+            vec![
+                block_iter(&["b", "e", "f"]),
+                block_iter(&["a", "c", "g"]),
+                block_iter(&["d"]),
+            ],
+        );
+        iter.seek_to_last();
+        let mut keys = Vec::new();
+        while iter.valid() {
+            keys.push(String::from_utf8(iter.key().to_vec()).unwrap());
+            iter.prev();
+        }
+        assert_eq!(keys, ["g", "f", "e", "d", "c", "b", "a"]);
+
+        iter.seek(b"d");
+        iter.prev();
+        assert_eq!(iter.key(), b"c");
+        iter.prev();
+        assert_eq!(iter.key(), b"b");
+        iter.next();
+        assert_eq!(iter.key(), b"c");
+        iter.next();
+        assert_eq!(iter.key(), b"d");
+        iter.next();
+        assert_eq!(iter.key(), b"e");
+    }
+}
@@ -0,0 +1,190 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::comparator::{BytewiseComparator, Comparator};
+use crate::prefix_extractor::PrefixExtractor;
+use crate::snapshot::Snapshot;
+
+/// How tables are merged as the database grows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompactionStyle {
+    /// Keep each level past level 0 a set of disjoint tables, ten times
+    /// larger than the level above. Favors reads and space.
+    #[default]
+    Leveled,
+    /// Keep every table in level 0 as a sorted run and merge runs of
+    /// similar size. Favors writes.
+    Universal,
+    /// Keep every table in level 0 and never merge them; delete the oldest
+    /// tables once the database grows too large or they grow too old, even
+    /// if snapshots still read them. For caches whose data may be lost.
+    Fifo,
+}
+
+/// The data structure memtables keep their entries in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MemTableBackendKind {
+    /// A `BTreeMap` behind a lock, so reads of the active memtable wait
+    /// for writes.
+    BTree,
+    /// A skip list, ordered by the database comparator, that reads never
+    /// wait on.
+    #[default]
+    SkipList,
+    /// A hash table of skip lists, one per key prefix as given by
+    /// `Options::prefix_extractor`, which must be set. Lookups only search
+    /// the keys with the same prefix; iterating over the database first
+    /// flushes the memtables holding entries.
+    HashSkipList { bucket_count: usize },
+    /// An unsorted vector that is sorted when flushed, for bulk loads. Reads
+    /// first flush the memtables holding entries, so each read while
+    /// loading writes a small table.
+    Vector,
+}
+
+/// How the write-ahead log is forced to disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncMethod {
+    /// `fsync`: the file data and all of its metadata.
+    Fsync,
+    /// `fdatasync`: the file data, and the metadata needed to read it back.
+    #[default]
+    Fdatasync,
+}
+
+/// When the write-ahead log is synced, besides for writes that ask for it
+/// and `SaturnDB::sync_wal`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WalSyncPolicy {
+    /// Sync before every write returns.
+    EveryWrite,
+    /// Sync in the background at this interval, so a machine crash loses at
+    /// most the writes of the last interval.
+    Interval(Duration),
+    /// Leave it to the OS. Writes survive a crash of the process but not
+    /// necessarily of the machine.
+    #[default]
+    Never,
+}
+
+/// Options used when opening a `SaturnDB`.
+#[derive(Clone)]
+pub struct Options {
+    /// Create the database directory if it does not exist yet.
+    pub create_if_missing: bool,
+    /// Fail to open if the database already exists.
+    pub error_if_exists: bool,
+    /// Defines the order of keys in the database. Its name is recorded in
+    /// the manifest and in every table, and a database can only be opened
+    /// with a comparator of the same name.
+    pub comparator: Arc<dyn Comparator>,
+    /// Bytes of memory the memtable may take before it is flushed to a
+    /// table.
+    pub write_buffer_size: usize,
+    pub memtable_backend: MemTableBackendKind,
+    /// Groups keys by prefix for the hash skip list memtable.
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    /// Number of full memtables that may wait to be flushed. Writes are
+    /// delayed once this many wait, and stop when the active memtable fills
+    /// up as well.
+    pub max_immutable_memtables: usize,
+    /// Number of tables kept open at once. The least recently used table
+    /// is closed to open another.
+    pub max_open_files: usize,
+    /// Approximate size of the data blocks in a table.
+    pub block_size: usize,
+    /// Number of keys between restart points, where a full key is stored
+    /// instead of a suffix of the previous one.
+    pub block_restart_interval: usize,
+    /// Bloom filter bits allotted to each key of a table. Zero disables the
+    /// filter. The filter hashes the bytes of each key, so it must be
+    /// disabled for a comparator that treats different bytes as equal keys.
+    pub bloom_bits_per_key: usize,
+    pub wal_sync_policy: WalSyncPolicy,
+    pub wal_sync_method: SyncMethod,
+    pub compaction_style: CompactionStyle,
+    /// Number of level-0 tables that triggers a compaction. With universal
+    /// compaction, every table is in level 0.
+    pub level0_file_num_compaction_trigger: usize,
+    /// Number of level-0 tables at which each write is delayed a little, to
+    /// let compaction catch up. Not applied with FIFO compaction.
+    pub level0_slowdown_writes_trigger: usize,
+    /// Number of level-0 tables at which writes stop until compaction brings
+    /// the count down. Not applied with FIFO compaction.
+    pub level0_stop_writes_trigger: usize,
+    /// Estimated bytes left for compaction to rewrite at which each write is
+    /// delayed a little. Zero disables the limit.
+    pub soft_pending_compaction_bytes_limit: u64,
+    /// Estimated bytes left for compaction to rewrite at which writes stop.
+    /// Zero disables the limit.
+    pub hard_pending_compaction_bytes_limit: u64,
+    /// Total size of the tables in level 1 above which level 1 is compacted.
+    pub max_bytes_for_level_base: u64,
+    /// Each level past level 1 may hold this many times more data than the
+    /// one before it.
+    pub max_bytes_for_level_multiplier: u64,
+    /// Size at which a compaction starts a new output table.
+    pub target_file_size: u64,
+    /// Universal compaction merges a run into the newer runs before it when
+    /// its size is at most this many percent larger than theirs combined.
+    pub universal_size_ratio: u64,
+    /// Universal compaction merges the newest runs regardless of their sizes
+    /// once there are more sorted runs than this.
+    pub universal_max_sorted_runs: usize,
+    /// FIFO compaction deletes the oldest tables once the tables take up
+    /// more than this many bytes.
+    pub fifo_max_table_files_size: u64,
+    /// FIFO compaction deletes tables whose data is older than this.
+    pub fifo_ttl: Option<Duration>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            create_if_missing: true,
+            error_if_exists: false,
+            comparator: Arc::new(BytewiseComparator::new()),
+            write_buffer_size: 4 * 1024 * 1024,
+            memtable_backend: MemTableBackendKind::SkipList,
+            prefix_extractor: None,
+            max_immutable_memtables: 2,
+            max_open_files: 1000,
+            block_size: 4096,
+            block_restart_interval: 16,
+            bloom_bits_per_key: 10,
+            wal_sync_policy: WalSyncPolicy::Never,
+            wal_sync_method: SyncMethod::Fdatasync,
+            compaction_style: CompactionStyle::Leveled,
+            level0_file_num_compaction_trigger: 4,
+            level0_slowdown_writes_trigger: 20,
+            level0_stop_writes_trigger: 36,
+            soft_pending_compaction_bytes_limit: 64 * 1024 * 1024 * 1024,
+            hard_pending_compaction_bytes_limit: 256 * 1024 * 1024 * 1024,
+            max_bytes_for_level_base: 10 * 1024 * 1024,
+            max_bytes_for_level_multiplier: 10,
+            target_file_size: 2 * 1024 * 1024,
+            universal_size_ratio: 1,
+            universal_max_sorted_runs: 8,
+            fifo_max_table_files_size: 1024 * 1024 * 1024,
+            fifo_ttl: None,
+        }
+    }
+}
+
+/// Options for a single write.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Sync the write-ahead log before the write returns.
+    pub sync: bool,
+    /// Skip the write-ahead log. The write is lost if the database is
+    /// closed or crashes before the memtable holding it is flushed to a
+    /// table.
+    pub disable_wal: bool,
+}
+
+/// Options for a single read.
+#[derive(Default, Clone, Copy)]
+pub struct ReadOptions<'a> {
+    /// Read the database as of this snapshot instead of its latest state.
+    pub snapshot: Option<&'a Snapshot>,
+}
@@ -0,0 +1,31 @@
+/// Maps a user key to the prefix it is grouped under. Memtables that hash
+/// keys by prefix keep the keys of each prefix together.
+pub trait PrefixExtractor: Send + Sync + 'static {
+    /// Returns the prefix of `key`, which must be a prefix of it.
+    fn prefix<'a>(&self, key: &'a [u8]) -> &'a [u8];
+
+    fn name(&self) -> &'static str;
+}
+
+/// Takes the first `len` bytes of a key as its prefix; a shorter key is its
+/// own prefix.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedPrefix {
+    len: usize,
+}
+
+impl FixedPrefix {
+    pub fn new(len: usize) -> Self {
+        Self { len }
+    }
+}
+
+impl PrefixExtractor for FixedPrefix {
+    fn prefix<'a>(&self, key: &'a [u8]) -> &'a [u8] {
+        &key[..key.len().min(self.len)]
+    }
+
+    fn name(&self) -> &'static str {
+        "FixedPrefix"
+    }
+}
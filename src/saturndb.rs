@@ -1,124 +1,933 @@
-use std::sync::{Arc, Mutex, RwLock};
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::io;
+use std::mem;
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-use crate::memtable::MemTable;
+use crate::common::{unix_time_secs, Entry, Key, SequenceNumber, Value};
+use crate::compaction::{self, Compaction, CompactionPicker};
+use crate::comparator::{BytewiseComparator, Comparator};
+use crate::db_iter::DBIterator;
+use crate::dbformat::{
+    parse_internal_key, InternalKey, InternalKeyComparator, LookupResult, ValueType,
+    MAX_SEQUENCE_NUMBER,
+};
+use crate::filename::{self, FileType};
+use crate::iterator::InternalIterator;
+use crate::memtable::{self, MemTable, MemTableCapabilities};
+use crate::merger::MergingIterator;
+use crate::options::{Options, ReadOptions, WalSyncPolicy, WriteOptions};
+use crate::snapshot::{Snapshot, SnapshotList};
 use crate::sstable::SSTable;
-use crate::wal::WriteAheadLog;
-use crate::common::{Key, Value, Entry};
+use crate::table_cache::TableCache;
+use crate::version_edit::{FileMetaData, VersionEdit};
+use crate::version_set::{Version, VersionSet};
+use crate::wal::{WriteAheadLog, WriteAheadLogIter};
+use crate::write_batch::WriteBatch;
+use crate::write_queue::WriteQueue;
+use crate::write_stall::{self, WriteStallCondition, WriteStallStats};
 
 pub struct SaturnDB {
-    pub memtable: Arc<Mutex<MemTable>>,
-    pub wal: Arc<Mutex<WriteAheadLog>>,
-    pub sstables: Arc<RwLock<Vec<SSTable>>>,
+    inner: Arc<DBInner>,
+    // Flushes full memtables and runs the compactions they call for.
+    background_thread: Option<JoinHandle<()>>,
+    // Syncs the log under `WalSyncPolicy::Interval`.
+    sync_thread: Option<JoinHandle<()>>,
+}
+
+// The state shared by a `SaturnDB` and its background thread.
+struct DBInner {
+    dbname: PathBuf,
+    options: Options,
+    memtables: Mutex<MemTables>,
+    // Signalled when a memtable fills up, when the background thread
+    // finishes a flush or goes idle, and on shutdown, which the log sync
+    // thread also waits for.
+    background_cond: Condvar,
+    // The log of the active memtable. Switched to a new log along with the
+    // memtable.
+    wal: Arc<Mutex<WriteAheadLog>>,
+    write_queue: WriteQueue,
+    versions: Arc<Mutex<VersionSet>>,
+    table_cache: Arc<TableCache>,
+    snapshots: Arc<SnapshotList>,
+    // Numbers of tables being written that are not in the table set yet.
+    pending_outputs: Mutex<HashSet<u64>>,
+    // Held for the duration of a round of compactions.
+    compaction_picker: Mutex<Box<dyn CompactionPicker>>,
+    // Time writes spent held back; the condition field is unused.
+    stall_stats: Mutex<WriteStallStats>,
+}
+
+struct MemTables {
+    // Takes new writes.
+    active: Arc<MemTable>,
+    // Full memtables waiting to be flushed, oldest first, each with the
+    // number of the log started after it. A memtable is only dropped once
+    // its table is in the table set, so readers always find its entries in
+    // one or the other.
+    immutable: VecDeque<(Arc<MemTable>, u64)>,
+    // Whether the background thread is flushing or compacting.
+    background_busy: bool,
+    // The error that stopped background work or a write to the log. Writes
+    // fail once it is set, until the database is reopened.
+    background_error: Option<io::Error>,
+    shutting_down: bool,
 }
 
 impl SaturnDB {
-    pub fn new(wal_path: &str) -> std::io::Result<Self> {
+    /// Opens the database stored in directory `dir`, rebuilding its table
+    /// set from the manifest and replaying its write-ahead logs.
+    pub fn open<P: AsRef<Path>>(dir: P, options: Options) -> io::Result<Self> {
+        memtable::check_options(options.memtable_backend, options.prefix_extractor.as_ref())?;
+        let dbname = dir.as_ref().to_path_buf();
+        let memtable = new_memtable(&options)?;
+        let mut versions = VersionSet::new(&dbname, options.comparator.clone());
+        if filename::current_file_name(&dbname).exists() {
+            if options.error_if_exists {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("database {} already exists", dbname.display()),
+                ));
+            }
+            versions.recover()?;
+        } else if !options.create_if_missing {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("database {} does not exist", dbname.display()),
+            ));
+        } else {
+            fs::create_dir_all(&dbname)?;
+        }
+        // Logs started after the manifest was last written are missing from
+        // its next file number.
+        for number in log_numbers(&dbname)? {
+            versions.mark_file_number_used(number);
+        }
+        versions.log_and_apply(&mut VersionEdit::default())?;
+        let log_number = versions.new_file_number();
+        let wal = WriteAheadLog::new(filename::log_file_name(&dbname, log_number))?;
+
+        let inner = Arc::new(DBInner {
+            memtables: Mutex::new(MemTables {
+                active: memtable,
+                immutable: VecDeque::new(),
+                background_busy: false,
+                background_error: None,
+                shutting_down: false,
+            }),
+            background_cond: Condvar::new(),
+            wal: Arc::new(Mutex::new(wal)),
+            write_queue: WriteQueue::new(),
+            versions: Arc::new(Mutex::new(versions)),
+            table_cache: Arc::new(TableCache::new(&dbname, options.clone())),
+            snapshots: Arc::new(SnapshotList::new()),
+            pending_outputs: Mutex::new(HashSet::new()),
+            compaction_picker: Mutex::new(compaction::new_picker(options.compaction_style)),
+            stall_stats: Mutex::new(WriteStallStats::default()),
+            dbname,
+            options,
+        });
+        inner.recover(log_number)?;
+        inner.delete_obsolete_files()?;
+        let sync_thread = match inner.options.wal_sync_policy {
+            WalSyncPolicy::Interval(interval) => {
+                let inner = inner.clone();
+                Some(thread::spawn(move || inner.sync_wal_periodically(interval)))
+            }
+            _ => None,
+        };
+        let background_thread = {
+            let inner = inner.clone();
+            thread::spawn(move || inner.background_work())
+        };
         Ok(Self {
-            memtable: Arc::new(Mutex::new(MemTable::new())),
-            wal: Arc::new(Mutex::new(WriteAheadLog::new(wal_path)?)),
-            sstables: Arc::new(RwLock::new(Vec::new())),
+            inner,
+            background_thread: Some(background_thread),
+            sync_thread,
         })
     }
 
-    pub fn put(&self, key: Key, value: Value) -> std::io::Result<()> {
-        {
-            let mut wal = self.wal.lock().unwrap();
-            wal.append(&Entry::Put {
-                key: key.clone(),
-                value: value.clone(),
-            })?;
+    pub fn put(&self, key: Key, value: Value) -> io::Result<()> {
+        self.put_opt(key, value, &WriteOptions::default())
+    }
+
+    pub fn put_opt(&self, key: Key, value: Value, write_options: &WriteOptions) -> io::Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.put(&key, &value);
+        self.write_opt(batch, write_options)
+    }
+
+    pub fn delete(&self, key: Key) -> io::Result<()> {
+        self.delete_opt(key, &WriteOptions::default())
+    }
+
+    pub fn delete_opt(&self, key: Key, write_options: &WriteOptions) -> io::Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete(&key);
+        self.write_opt(batch, write_options)
+    }
+
+    pub fn get(&self, key: &Key) -> io::Result<Option<Value>> {
+        self.get_opt(key, &ReadOptions::default())
+    }
+
+    pub fn get_opt(&self, key: &Key, read_options: &ReadOptions) -> io::Result<Option<Value>> {
+        let (memtables, version, sequence) = self
+            .inner
+            .capable_read_state(read_options, |c| c.point_lookups)?;
+        for memtable in &memtables {
+            if let Some(value) = resolve(memtable.get(key, sequence)) {
+                return Ok(value);
+            }
         }
 
-        let mut memtable = self.memtable.lock().unwrap();
-        memtable.insert(key, value);
+        for file in version.files_for_key(key) {
+            let sstable = self.inner.table_cache.find_table(file.number)?;
+            if let Some(value) = resolve(sstable.get(key, sequence)?) {
+                return Ok(value);
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns a snapshot of the current state of the database, for use in
+    /// `ReadOptions`.
+    pub fn snapshot(&self) -> Snapshot {
+        // Holding the lock until the snapshot is registered keeps a
+        // compaction from picking a later sequence number as the oldest one
+        // still read in the meantime.
+        let versions = self.inner.versions.lock().unwrap();
+        self.inner.snapshots.acquire(versions.last_sequence())
+    }
 
-        if memtable.is_full() {
-            self.flush_memtable()?;
+    /// Returns an iterator over every live key of the database.
+    pub fn iter(&self) -> io::Result<DBIterator> {
+        self.iter_opt(&ReadOptions::default())
+    }
+
+    pub fn iter_opt(&self, read_options: &ReadOptions) -> io::Result<DBIterator> {
+        self.new_iterator(read_options, (Bound::Unbounded, Bound::Unbounded))
+    }
+
+    /// Returns an iterator over the live keys within `range`.
+    pub fn range<K, R>(&self, range: R) -> io::Result<DBIterator>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        self.range_opt(range, &ReadOptions::default())
+    }
+
+    pub fn range_opt<K, R>(&self, range: R, read_options: &ReadOptions) -> io::Result<DBIterator>
+    where
+        K: AsRef<[u8]> + ?Sized,
+        R: RangeBounds<K>,
+    {
+        let bound = |bound: Bound<&K>| match bound {
+            Bound::Included(key) => Bound::Included(key.as_ref().to_vec()),
+            Bound::Excluded(key) => Bound::Excluded(key.as_ref().to_vec()),
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        self.new_iterator(
+            read_options,
+            (bound(range.start_bound()), bound(range.end_bound())),
+        )
+    }
+
+    /// Returns an iterator over the live keys starting with `prefix`. Only
+    /// supported under bytewise ordering, where keys sharing a prefix are
+    /// adjacent.
+    pub fn prefix(&self, prefix: &[u8]) -> io::Result<DBIterator> {
+        self.prefix_opt(prefix, &ReadOptions::default())
+    }
+
+    pub fn prefix_opt(&self, prefix: &[u8], read_options: &ReadOptions) -> io::Result<DBIterator> {
+        if self.inner.options.comparator.name() != BytewiseComparator.name() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "prefix iteration needs the bytewise comparator",
+            ));
         }
-        Ok(())
+        // The first key past every key with the prefix is the prefix with
+        // its last byte that can be incremented incremented.
+        let mut end = prefix.to_vec();
+        while end.last() == Some(&0xff) {
+            end.pop();
+        }
+        let upper = match end.last_mut() {
+            Some(last) => {
+                *last += 1;
+                Bound::Excluded(end)
+            }
+            None => Bound::Unbounded,
+        };
+        self.new_iterator(read_options, (Bound::Included(prefix.to_vec()), upper))
     }
 
-    pub fn delete(&self, key: Key) -> std::io::Result<()> {
-        {
-            let mut wal = self.wal.lock().unwrap();
-            wal.append(&Entry::Delete { key: key.clone() })?;
+    fn new_iterator(
+        &self,
+        read_options: &ReadOptions,
+        bounds: (Bound<Vec<u8>>, Bound<Vec<u8>>),
+    ) -> io::Result<DBIterator> {
+        let (memtables, version, sequence) = self
+            .inner
+            .capable_read_state(read_options, |c| c.total_order_iteration)?;
+        let mut children: Vec<Box<dyn InternalIterator>> = Vec::new();
+        for memtable in &memtables {
+            children.push(Box::new(memtable.iter()));
         }
+        for file in version.files.iter().flatten() {
+            children.push(Box::new(
+                self.inner.table_cache.find_table(file.number)?.iter(),
+            ));
+        }
+        let comparator = self.inner.options.comparator.clone();
+        Ok(DBIterator::new(
+            MergingIterator::new(
+                Arc::new(InternalKeyComparator::new(comparator.clone())),
+                children,
+            ),
+            comparator,
+            sequence,
+            bounds,
+            version,
+        ))
+    }
 
-        let mut memtable = self.memtable.lock().unwrap();
-        memtable.delete(key);
+    /// Applies every operation of `batch` atomically: it is logged as one
+    /// record, and readers see either all of it or none of it.
+    ///
+    /// Batches of concurrent writers are committed together, with a single
+    /// log append. A full memtable is handed to the background thread to
+    /// flush. Writes are delayed or stopped while the background thread
+    /// falls behind; see `write_stall_stats`.
+    pub fn write(&self, batch: WriteBatch) -> io::Result<()> {
+        self.write_opt(batch, &WriteOptions::default())
+    }
 
-        if memtable.is_full() {
-            self.flush_memtable()?;
+    pub fn write_opt(&self, batch: WriteBatch, write_options: &WriteOptions) -> io::Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let inner = &self.inner;
+        let mut write_options = *write_options;
+        if inner.options.wal_sync_policy == WalSyncPolicy::EveryWrite {
+            write_options.sync = true;
+        }
+        inner
+            .write_queue
+            .write(batch, write_options, |mut group, write_options| {
+                // Only one group is committed at a time, which keeps
+                // sequence numbers in log order.
+                let mut wal = inner.make_room_for_write()?;
+                group.set_sequence(inner.versions.lock().unwrap().last_sequence() + 1);
+                let result = inner.commit(&mut wal, &group, write_options);
+                if let Err(err) = &result {
+                    // The group may be in the log, whole or torn, without its
+                    // sequence numbers having been used. Another write would
+                    // log different data under the same sequence numbers, or
+                    // after the torn record, so none is taken until reopen.
+                    let mut memtables = inner.memtables.lock().unwrap();
+                    if memtables.background_error.is_none() {
+                        memtables.background_error =
+                            Some(io::Error::new(err.kind(), err.to_string()));
+                    }
+                    inner.background_cond.notify_all();
+                }
+                result
+            })
+    }
+
+    /// Forces every write logged so far to stable storage.
+    pub fn sync_wal(&self) -> io::Result<()> {
+        let method = self.inner.options.wal_sync_method;
+        self.inner.wal.lock().unwrap().sync(method)
+    }
+
+    /// Returns the number of live tables in `level`.
+    pub fn num_files_at_level(&self, level: usize) -> usize {
+        self.inner.versions.lock().unwrap().current().files[level].len()
+    }
+
+    /// Returns whether writes are currently held back, and for how long
+    /// they have been.
+    pub fn write_stall_stats(&self) -> WriteStallStats {
+        let condition = {
+            let memtables = self.inner.memtables.lock().unwrap();
+            self.inner.write_stall_condition(&memtables)
+        };
+        WriteStallStats {
+            condition,
+            ..*self.inner.stall_stats.lock().unwrap()
         }
-        Ok(())
     }
 
-    pub fn get(&self, key: &Key) -> std::io::Result<Option<Value>> {
+    /// Flushes the active memtable and waits for the background thread to
+    /// finish.
+    #[cfg(test)]
+    fn flush_memtable(&self) -> io::Result<()> {
         {
-            let memtable = self.memtable.lock().unwrap();
-            if let Some((value, _)) = memtable.get(key) {
-                return Ok(Some(value.clone()));
-            }
-            if memtable.tombstones.contains_key(key) {
-                return Ok(None);
+            let mut wal = self.inner.wal.lock().unwrap();
+            let mut memtables = self.inner.memtables.lock().unwrap();
+            if !memtables.active.is_empty() {
+                self.inner.switch_memtable(&mut wal, &mut memtables)?;
             }
         }
+        self.wait_for_background_work()
+    }
+
+    /// Waits until every full memtable is flushed and the compactions they
+    /// called for are done.
+    #[cfg(test)]
+    fn wait_for_background_work(&self) -> io::Result<()> {
+        let mut memtables = self.inner.memtables.lock().unwrap();
+        while memtables.background_error.is_none()
+            && (memtables.background_busy || !memtables.immutable.is_empty())
+        {
+            memtables = self.inner.background_cond.wait(memtables).unwrap();
+        }
+        memtables.check_background_error()
+    }
+}
+
+impl Drop for SaturnDB {
+    fn drop(&mut self) {
+        // Memtables not flushed yet are recovered from their logs.
+        self.inner.memtables.lock().unwrap().shutting_down = true;
+        self.inner.background_cond.notify_all();
+        for thread in [self.background_thread.take(), self.sync_thread.take()]
+            .into_iter()
+            .flatten()
+        {
+            let _ = thread.join();
+        }
+    }
+}
 
-        let sstables = self.sstables.read().unwrap();
-        for sstable in sstables.iter().rev() {
-            if let Some((value, _)) = sstable.get(key)? {
-                return Ok(Some(value));
+impl MemTables {
+    fn check_background_error(&self) -> io::Result<()> {
+        match &self.background_error {
+            Some(err) => Err(io::Error::new(err.kind(), err.to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl DBInner {
+    /// Returns the memtables, newest first, and version to read from, and
+    /// the sequence number of the last write the read sees.
+    fn read_state(
+        &self,
+        read_options: &ReadOptions,
+    ) -> (Vec<Arc<MemTable>>, Arc<Version>, SequenceNumber) {
+        let memtables = self.memtables.lock().unwrap();
+        let versions = self.versions.lock().unwrap();
+        let sequence = match read_options.snapshot {
+            Some(snapshot) => snapshot.sequence(),
+            None => versions.last_sequence(),
+        };
+        let mut all = vec![memtables.active.clone()];
+        all.extend(
+            memtables
+                .immutable
+                .iter()
+                .rev()
+                .map(|(memtable, _)| memtable.clone()),
+        );
+        (all, versions.current(), sequence)
+    }
+
+    /// Like `read_state`, but first flushes the memtables holding entries
+    /// whose backend lacks the capability the read needs, so the read finds
+    /// those entries in tables instead.
+    fn capable_read_state(
+        &self,
+        read_options: &ReadOptions,
+        has_capability: impl Fn(MemTableCapabilities) -> bool,
+    ) -> io::Result<(Vec<Arc<MemTable>>, Arc<Version>, SequenceNumber)> {
+        let lacking =
+            |memtable: &MemTable| !memtable.is_empty() && !has_capability(memtable.capabilities());
+        loop {
+            let state = self.read_state(read_options);
+            if !state.0.iter().any(|memtable| lacking(memtable)) {
+                return Ok(state);
+            }
+            drop(state);
+
+            let mut wal = self.wal.lock().unwrap();
+            let mut memtables = self.memtables.lock().unwrap();
+            if lacking(&memtables.active) {
+                self.switch_memtable(&mut wal, &mut memtables)?;
+            }
+            drop(wal);
+            while memtables
+                .immutable
+                .iter()
+                .any(|(memtable, _)| lacking(memtable))
+            {
+                memtables.check_background_error()?;
+                memtables = self.background_cond.wait(memtables).unwrap();
             }
-            // Check for tombstone
-            if sstable.index.contains_key(key) {
-                // Since we read the index, we need to check if it's a tombstone
-                if let Some(entry) = sstable.get(key)? {
-                    return Ok(Some(entry.0));
-                } else {
-                    return Ok(None);
+        }
+    }
+
+    /// Makes sure the active memtable has room for a write, switching to a
+    /// new one if it is full, and returns the log the write goes to. Delays
+    /// or stops the write while the background thread is falling behind.
+    fn make_room_for_write(&self) -> io::Result<MutexGuard<'_, WriteAheadLog>> {
+        let mut delayed = false;
+        let mut stopped = false;
+        let mut wal = self.wal.lock().unwrap();
+        let mut memtables = self.memtables.lock().unwrap();
+        loop {
+            memtables.check_background_error()?;
+            match self.write_stall_condition(&memtables) {
+                WriteStallCondition::Delayed(_) if !delayed => {
+                    // Slow every write down a little, rather than stopping
+                    // one for long once the hard limit is hit.
+                    drop(memtables);
+                    drop(wal);
+                    let start = Instant::now();
+                    thread::sleep(Duration::from_millis(1));
+                    let mut stats = self.stall_stats.lock().unwrap();
+                    stats.delayed_writes += 1;
+                    stats.delay_time += start.elapsed();
+                    drop(stats);
+                    delayed = true;
+                    wal = self.wal.lock().unwrap();
+                    memtables = self.memtables.lock().unwrap();
+                }
+                // Waiting only helps if the background thread has work.
+                WriteStallCondition::Stopped(_)
+                    if memtables.background_busy || !memtables.immutable.is_empty() =>
+                {
+                    let start = Instant::now();
+                    memtables = self.background_cond.wait(memtables).unwrap();
+                    let mut stats = self.stall_stats.lock().unwrap();
+                    if !stopped {
+                        stats.stopped_writes += 1;
+                        stopped = true;
+                    }
+                    stats.stop_time += start.elapsed();
                 }
+                _ if memtables.active.is_full() => {
+                    self.switch_memtable(&mut wal, &mut memtables)?;
+                }
+                _ => return Ok(wal),
             }
         }
+    }
 
-        Ok(None)
+    fn write_stall_condition(&self, memtables: &MemTables) -> WriteStallCondition {
+        let version = self.versions.lock().unwrap().current();
+        write_stall::condition(
+            &version,
+            memtables.immutable.len(),
+            memtables.active.is_full(),
+            &self.options,
+        )
     }
 
-    fn flush_memtable(&self) -> std::io::Result<()> {
-        let (data, tombstones) = {
-            let mut memtable = self.memtable.lock().unwrap();
-            memtable.flush()
-        };
-        let sstable_path = format!("sstable_{}.db", self.sstables.read().unwrap().len());
-        let sstable = SSTable::write(data, tombstones, &sstable_path)?;
-        self.sstables.write().unwrap().push(sstable);
+    /// Starts a new log and retires the active memtable, whose log it
+    /// follows.
+    fn switch_memtable(
+        &self,
+        wal: &mut WriteAheadLog,
+        memtables: &mut MemTables,
+    ) -> io::Result<()> {
+        let log_number = self.versions.lock().unwrap().new_file_number();
+        *wal = WriteAheadLog::new(filename::log_file_name(&self.dbname, log_number))?;
+        self.retire_memtable(memtables, log_number)
+    }
+
+    /// Queues the active memtable for the background thread to flush and
+    /// replaces it with an empty one. Once flushed, the logs numbered below
+    /// `log_number` are obsolete.
+    fn retire_memtable(&self, memtables: &mut MemTables, log_number: u64) -> io::Result<()> {
+        let memtable = mem::replace(&mut memtables.active, new_memtable(&self.options)?);
+        memtables.immutable.push_back((memtable, log_number));
+        self.background_cond.notify_all();
         Ok(())
     }
 
-    pub fn recover(&self, wal_path: &str) -> std::io::Result<()> {
-        let mut wal = WriteAheadLog::new(wal_path)?;
-        let mut memtable = self.memtable.lock().unwrap();
-        for entry in wal.iter() {
+    /// Logs `group`, unless the options skip the log, and adds it to the
+    /// memtable.
+    fn commit(
+        &self,
+        wal: &mut WriteAheadLog,
+        group: &WriteBatch,
+        write_options: WriteOptions,
+    ) -> io::Result<()> {
+        if !write_options.disable_wal {
+            wal.append(group)?;
+            if write_options.sync {
+                wal.sync(self.options.wal_sync_method)?;
+            }
+        }
+        self.apply(group).map(|_| ())
+    }
+
+    /// Adds the operations of `batch` to the memtable under the sequence
+    /// numbers starting at the batch's. Returns true if the memtable is full.
+    fn apply(&self, batch: &WriteBatch) -> io::Result<bool> {
+        let entries = batch.iter().collect::<io::Result<Vec<_>>>()?;
+        // Only the writer switches memtables, so the active one stays put
+        // without holding the lock, and readers are not held up by the
+        // inserts.
+        let memtable = self.memtables.lock().unwrap().active.clone();
+        let mut sequence = batch.sequence();
+        for entry in entries {
             match entry {
-                Entry::Put { key, value } => {
-                    memtable.insert(key, value);
+                Entry::Put { key, value } => memtable.add(sequence, ValueType::Value, &key, &value),
+                Entry::Delete { key } => memtable.add(sequence, ValueType::Deletion, &key, &[]),
+            }
+            sequence += 1;
+        }
+        // The sequence numbers are only published once the whole batch is in
+        // the memtable, so readers see all of it or none of it.
+        let last_sequence = sequence - 1;
+        let mut versions = self.versions.lock().unwrap();
+        if last_sequence > versions.last_sequence() {
+            versions.set_last_sequence(last_sequence);
+        }
+        Ok(memtable.is_full())
+    }
+
+    /// Runs on its own thread until shutdown: syncs the log every
+    /// `interval` if it has writes that are not synced yet.
+    fn sync_wal_periodically(&self, interval: Duration) {
+        let mut next_sync = Instant::now() + interval;
+        let mut memtables = self.memtables.lock().unwrap();
+        while !memtables.shutting_down {
+            let now = Instant::now();
+            if now < next_sync {
+                memtables = self
+                    .background_cond
+                    .wait_timeout(memtables, next_sync - now)
+                    .unwrap()
+                    .0;
+                continue;
+            }
+            // The log is locked before the memtables.
+            drop(memtables);
+            let mut wal = self.wal.lock().unwrap();
+            if wal.needs_sync() {
+                // A failed sync is retried at the next tick; writes that need
+                // the error sync themselves.
+                let _ = wal.sync(self.options.wal_sync_method);
+            }
+            drop(wal);
+            next_sync = Instant::now() + interval;
+            memtables = self.memtables.lock().unwrap();
+        }
+    }
+
+    /// Runs on the background thread until shutdown: flushes full memtables
+    /// and runs the compactions they call for. Stops at the first error.
+    fn background_work(&self) {
+        let mut memtables = self.memtables.lock().unwrap();
+        while !memtables.shutting_down {
+            if memtables.immutable.is_empty() || memtables.background_error.is_some() {
+                memtables = self.background_cond.wait(memtables).unwrap();
+                continue;
+            }
+            memtables.background_busy = true;
+            drop(memtables);
+            let result = self.flush_immutable().and_then(|()| self.maybe_compact());
+            memtables = self.memtables.lock().unwrap();
+            memtables.background_busy = false;
+            if let Err(err) = result {
+                memtables.background_error = Some(err);
+            }
+            self.background_cond.notify_all();
+        }
+    }
+
+    /// Writes the oldest full memtable out to a level-0 table, and drops it
+    /// once the table is in the table set.
+    fn flush_immutable(&self) -> io::Result<()> {
+        let (memtable, log_number) = self.memtables.lock().unwrap().immutable[0].clone();
+        let number = self.new_output_number();
+        let result = self.write_level0_table(&memtable, number).and_then(|file| {
+            let mut edit = VersionEdit::default();
+            edit.add_file(0, file);
+            // Once the table is in the manifest, the memtable's log is
+            // obsolete.
+            edit.set_log_number(log_number);
+            let mut memtables = self.memtables.lock().unwrap();
+            self.versions.lock().unwrap().log_and_apply(&mut edit)?;
+            memtables.immutable.pop_front();
+            self.background_cond.notify_all();
+            Ok(())
+        });
+        self.pending_outputs.lock().unwrap().remove(&number);
+        result
+    }
+
+    fn write_level0_table(
+        &self,
+        memtable: &Arc<MemTable>,
+        number: u64,
+    ) -> io::Result<FileMetaData> {
+        let path = filename::table_file_name(&self.dbname, number);
+        let mut iter = memtable.iter();
+        let sstable = SSTable::write(&mut iter, &path, &self.options)?;
+
+        iter.seek_to_last();
+        let largest = InternalKey::decode_from(iter.key()).unwrap();
+        iter.seek_to_first();
+        let smallest = InternalKey::decode_from(iter.key()).unwrap();
+        let (mut smallest_seqno, mut largest_seqno) = (MAX_SEQUENCE_NUMBER, 0);
+        while iter.valid() {
+            let sequence = parse_internal_key(iter.key()).unwrap().sequence;
+            smallest_seqno = smallest_seqno.min(sequence);
+            largest_seqno = largest_seqno.max(sequence);
+            iter.next();
+        }
+        let file = FileMetaData {
+            number,
+            file_size: fs::metadata(&path)?.len(),
+            smallest,
+            largest,
+            smallest_seqno,
+            largest_seqno,
+            creation_time: unix_time_secs(),
+        };
+        self.table_cache.insert(number, sstable);
+        Ok(file)
+    }
+
+    /// Allocates a number for a new table and protects it from
+    /// `delete_obsolete_files` until it is part of the table set.
+    fn new_output_number(&self) -> u64 {
+        let number = self.versions.lock().unwrap().new_file_number();
+        self.pending_outputs.lock().unwrap().insert(number);
+        number
+    }
+
+    /// Runs compactions until the picker finds nothing left to do, a full
+    /// memtable needs flushing first, or the database is shutting down.
+    fn maybe_compact(&self) -> io::Result<()> {
+        let mut picker = self.compaction_picker.lock().unwrap();
+        loop {
+            {
+                let memtables = self.memtables.lock().unwrap();
+                if memtables.shutting_down || !memtables.immutable.is_empty() {
+                    break;
                 }
-                Entry::Delete { key } => {
-                    memtable.delete(key);
+            }
+            let (version, smallest_snapshot) = {
+                let versions = self.versions.lock().unwrap();
+                let smallest_snapshot = self.snapshots.oldest().unwrap_or(versions.last_sequence());
+                (versions.current(), smallest_snapshot)
+            };
+            match picker.pick(&version, &self.options) {
+                Some(compaction) => {
+                    self.run_compaction(&compaction, smallest_snapshot)?;
+                    // Writers stopped on level 0 may go ahead.
+                    self.background_cond.notify_all();
                 }
+                None => break,
+            }
+        }
+        self.delete_obsolete_files()
+    }
+
+    fn run_compaction(
+        &self,
+        compaction: &Compaction,
+        smallest_snapshot: SequenceNumber,
+    ) -> io::Result<()> {
+        if compaction.is_deletion() {
+            let mut edit = compaction.edit(Vec::new());
+            return self.versions.lock().unwrap().log_and_apply(&mut edit);
+        }
+        if compaction.is_trivial_move() {
+            let file = compaction.inputs[0][0].as_ref().clone();
+            let mut edit = compaction.edit(vec![file]);
+            return self.versions.lock().unwrap().log_and_apply(&mut edit);
+        }
+
+        let mut children: Vec<Box<dyn InternalIterator>> = Vec::new();
+        for file in compaction.inputs.iter().flatten() {
+            children.push(Box::new(self.table_cache.find_table(file.number)?.iter()));
+        }
+        let comparator = Arc::new(InternalKeyComparator::new(self.options.comparator.clone()));
+        let mut input = MergingIterator::new(comparator, children);
+
+        let mut numbers = Vec::new();
+        let result = compaction::merge(
+            compaction,
+            &mut input,
+            smallest_snapshot,
+            &self.dbname,
+            &self.options,
+            &mut || {
+                let number = self.new_output_number();
+                numbers.push(number);
+                number
+            },
+        )
+        .and_then(|outputs| {
+            let mut edit = compaction.edit(outputs);
+            self.versions.lock().unwrap().log_and_apply(&mut edit)
+        });
+        let mut pending_outputs = self.pending_outputs.lock().unwrap();
+        for number in numbers {
+            pending_outputs.remove(&number);
+        }
+        result
+    }
+
+    /// Deletes the tables that are neither part of a live version nor being
+    /// written, and the logs whose data is all in tables.
+    fn delete_obsolete_files(&self) -> io::Result<()> {
+        // Holding the lock keeps a table from leaving `pending_outputs` for
+        // the table set between the two snapshots below.
+        let pending_outputs = self.pending_outputs.lock().unwrap();
+        let (live, log_number) = {
+            let mut versions = self.versions.lock().unwrap();
+            (versions.live_files(), versions.log_number())
+        };
+        for entry in fs::read_dir(&self.dbname)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            match name.to_str().and_then(filename::parse_file_name) {
+                Some((number, FileType::Table))
+                    if !live.contains(&number) && !pending_outputs.contains(&number) =>
+                {
+                    self.table_cache.evict(number);
+                    fs::remove_file(entry.path())?;
+                }
+                Some((number, FileType::WriteAheadLog)) if number < log_number => {
+                    fs::remove_file(entry.path())?;
+                }
+                _ => {}
             }
         }
         Ok(())
     }
+
+    /// Replays the logs that may hold data missing from the tables, oldest
+    /// first, and writes their data out to tables. Each batch keeps the
+    /// sequence numbers it was first written with. Afterwards only the log
+    /// numbered `log_number`, which new writes go to, is live.
+    fn recover(&self, log_number: u64) -> io::Result<()> {
+        let min_log_number = self.versions.lock().unwrap().log_number();
+        let logs = log_numbers(&self.dbname)?
+            .into_iter()
+            .filter(|&number| number >= min_log_number && number != log_number);
+
+        // Tables written while replaying leave the log number alone, so the
+        // logs not replayed yet survive a crash. The background thread is
+        // not running yet; the memtables are flushed here.
+        for number in logs {
+            let path = filename::log_file_name(&self.dbname, number);
+            for batch in WriteAheadLogIter::new(&path)? {
+                if self.apply(&batch?)? {
+                    self.retire_memtable(&mut self.memtables.lock().unwrap(), min_log_number)?;
+                    self.flush_immutable()?;
+                    self.maybe_compact()?;
+                }
+            }
+        }
+
+        let mut memtables = self.memtables.lock().unwrap();
+        if memtables.active.is_empty() {
+            drop(memtables);
+            let mut edit = VersionEdit::default();
+            edit.set_log_number(log_number);
+            return self.versions.lock().unwrap().log_and_apply(&mut edit);
+        }
+        self.retire_memtable(&mut memtables, log_number)?;
+        drop(memtables);
+        self.flush_immutable()?;
+        self.maybe_compact()
+    }
+}
+
+/// Returns the numbers of the write-ahead logs in `dbname`, in order.
+fn log_numbers(dbname: &Path) -> io::Result<Vec<u64>> {
+    let mut numbers = Vec::new();
+    for entry in fs::read_dir(dbname)? {
+        let name = entry?.file_name();
+        if let Some((number, FileType::WriteAheadLog)) =
+            name.to_str().and_then(filename::parse_file_name)
+        {
+            numbers.push(number);
+        }
+    }
+    numbers.sort_unstable();
+    Ok(numbers)
+}
+
+/// Creates an empty memtable with the backend and size set in `options`.
+/// Fails if the options do not suit the backend.
+fn new_memtable(options: &Options) -> io::Result<Arc<MemTable>> {
+    let comparator = Arc::new(InternalKeyComparator::new(options.comparator.clone()));
+    let backend = memtable::new_backend(
+        options.memtable_backend,
+        comparator.clone(),
+        options.prefix_extractor.clone(),
+    )?;
+    Ok(Arc::new(MemTable::with_backend(
+        backend,
+        comparator,
+        options.write_buffer_size,
+    )))
+}
+
+/// Returns the answer to a read if `result` settles it: the value for a put,
+/// `None` for a deletion. Returns `None` if older data must be searched.
+fn resolve(result: LookupResult) -> Option<Option<Value>> {
+    match result {
+        LookupResult::Found(value) => Some(Some(value)),
+        LookupResult::Deleted => Some(None),
+        LookupResult::NotFound => None,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::options::{CompactionStyle, MemTableBackendKind, SyncMethod};
+    use crate::prefix_extractor::FixedPrefix;
+    use crate::version_set::NUM_LEVELS;
+
+    fn temp_db(name: &str) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!("saturn_db_{name}"));
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+
+    /// A skip list memtable of this many bytes fills up on exactly the
+    /// tenth entry with a one-byte key and a `big_value`, whatever the
+    /// heights of its nodes.
+    const TEN_ENTRIES: usize = 10_400;
+
+    fn big_value(i: u8) -> Vec<u8> {
+        vec![i; 1000]
+    }
+
+    /// Deletes every write-ahead log, losing the writes not yet in a table.
+    fn remove_logs(path: &Path) -> io::Result<()> {
+        for number in log_numbers(path)? {
+            fs::remove_file(filename::log_file_name(path, number))?;
+        }
+        Ok(())
+    }
 
     #[test]
-    fn test_sdb_put_get() -> std::io::Result<()> {
-        let path = "/tmp/test_sdb_put_get";
-        let db = SaturnDB::new(path)?;
+    fn test_sdb_put_get() -> io::Result<()> {
+        let path = temp_db("put_get");
+        let db = SaturnDB::open(&path, Options::default())?;
 
         db.put(b"key1".to_vec(), b"value1".to_vec())?;
         db.put(b"key2".to_vec(), b"value2".to_vec())?;
@@ -134,9 +943,9 @@ mod tests {
     }
 
     #[test]
-    fn test_sdb_delete() -> std::io::Result<()> {
-        let path = "/tmp/test_sdb_delete";
-        let db = SaturnDB::new(path)?;
+    fn test_sdb_delete() -> io::Result<()> {
+        let path = temp_db("delete");
+        let db = SaturnDB::open(&path, Options::default())?;
 
         db.put(b"key1".to_vec(), b"value1".to_vec())?;
         db.delete(b"key1".to_vec())?;
@@ -147,18 +956,17 @@ mod tests {
     }
 
     #[test]
-    fn test_sdb_recovery() -> std::io::Result<()> {
-        let path = "/tmp/test_sdb_recovery";
+    fn test_sdb_recovery() -> io::Result<()> {
+        let path = temp_db("recovery");
 
         {
-            let db = SaturnDB::new(path)?;
+            let db = SaturnDB::open(&path, Options::default())?;
             db.put(b"key1".to_vec(), b"value1".to_vec())?;
             db.put(b"key2".to_vec(), b"value2".to_vec())?;
             db.delete(b"key1".to_vec())?;
         }
 
-        let db = SaturnDB::new(path)?;
-        db.recover(path)?;
+        let db = SaturnDB::open(&path, Options::default())?;
 
         let val1 = db.get(&b"key1".to_vec())?;
         let val2 = db.get(&b"key2".to_vec())?;
@@ -167,4 +975,1176 @@ mod tests {
         assert_eq!(val1, None);
         Ok(())
     }
+
+    #[test]
+    fn test_sdb_btree_memtable() -> io::Result<()> {
+        let path = temp_db("btree_memtable");
+        let options = Options {
+            write_buffer_size: 256,
+            memtable_backend: MemTableBackendKind::BTree,
+            ..Options::default()
+        };
+
+        {
+            let db = SaturnDB::open(&path, options.clone())?;
+            for i in (0..20).rev() {
+                db.put(format!("k{i:02}").into_bytes(), vec![i])?;
+            }
+            db.delete(b"k05".to_vec())?;
+            db.put(b"k07".to_vec(), b"new".to_vec())?;
+            db.wait_for_background_work()?;
+            assert!(db.num_files_at_level(0) > 0);
+            assert_eq!(db.get(&b"k07".to_vec())?, Some(b"new".to_vec()));
+            assert_eq!(db.get(&b"k05".to_vec())?, None);
+        }
+
+        let db = SaturnDB::open(&path, options)?;
+        assert_eq!(db.get(&b"k07".to_vec())?, Some(b"new".to_vec()));
+        assert_eq!(db.get(&b"k12".to_vec())?, Some(vec![12]));
+        assert_eq!(db.get(&b"k05".to_vec())?, None);
+        let forward = keys_forward(&mut db.iter()?);
+        assert_eq!(forward.len(), 19);
+        assert_eq!(forward[0], "k00");
+        assert_eq!(forward[5], "k06");
+        let mut backward = keys_backward(&mut db.iter()?);
+        backward.reverse();
+        assert_eq!(backward, forward);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_hash_skip_list_memtable() -> io::Result<()> {
+        let path = temp_db("hash_skip_list_memtable");
+        let mut options = Options {
+            write_buffer_size: 8 * 1024,
+            memtable_backend: MemTableBackendKind::HashSkipList { bucket_count: 16 },
+            ..Options::default()
+        };
+        let err = SaturnDB::open(&path, options.clone()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+
+        options.prefix_extractor = Some(Arc::new(FixedPrefix::new(4)));
+        let no_buckets = Options {
+            memtable_backend: MemTableBackendKind::HashSkipList { bucket_count: 0 },
+            ..options.clone()
+        };
+        let err = SaturnDB::open(&path, no_buckets).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+
+        let db = SaturnDB::open(&path, options)?;
+        for i in 0..200 {
+            db.put(format!("user{}:{i}", i % 10).into_bytes(), vec![i as u8])?;
+        }
+        db.delete(b"user3:13".to_vec())?;
+        assert_eq!(db.get(&b"user7:57".to_vec())?, Some(vec![57]));
+        assert_eq!(db.get(&b"user3:13".to_vec())?, None);
+        assert_eq!(db.get(&b"user3:14".to_vec())?, None);
+        assert!(!db.inner.memtables.lock().unwrap().active.is_empty());
+        // Iterating flushes the memtables holding entries first.
+        assert_eq!(keys_forward(&mut db.iter()?).len(), 199);
+        assert!(db.inner.memtables.lock().unwrap().active.is_empty());
+        assert_eq!(db.get(&b"user0:0".to_vec())?, Some(vec![0]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_vector_memtable_bulk_load() -> io::Result<()> {
+        let path = temp_db("vector_memtable");
+        let options = Options {
+            write_buffer_size: 4 * 1024,
+            memtable_backend: MemTableBackendKind::Vector,
+            ..Options::default()
+        };
+        {
+            let db = SaturnDB::open(&path, options.clone())?;
+            for i in (0..100).rev() {
+                db.put(format!("k{i:02}").into_bytes(), vec![i])?;
+            }
+            // A read flushes the memtables holding entries first.
+            assert_eq!(db.get(&b"k00".to_vec())?, Some(vec![0]));
+            assert!(db.inner.memtables.lock().unwrap().active.is_empty());
+            assert_eq!(db.get(&b"k42".to_vec())?, Some(vec![42]));
+            assert_eq!(keys_forward(&mut db.iter()?).len(), 100);
+            db.put(b"k100".to_vec(), vec![100])?;
+        }
+
+        // Reopening flushes the entry left in the log.
+        let db = SaturnDB::open(&path, options)?;
+        let keys = keys_forward(&mut db.iter()?);
+        assert_eq!(keys.len(), 101);
+        assert_eq!(keys[0], "k00");
+        assert_eq!(db.get(&b"k100".to_vec())?, Some(vec![100]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_open_flags() -> io::Result<()> {
+        let path = temp_db("open_flags");
+
+        let missing = Options {
+            create_if_missing: false,
+            ..Options::default()
+        };
+        let err = SaturnDB::open(&path, missing).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        drop(SaturnDB::open(&path, Options::default())?);
+
+        let exclusive = Options {
+            error_if_exists: true,
+            ..Options::default()
+        };
+        let err = SaturnDB::open(&path, exclusive).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_tables_stay_in_their_directory() -> io::Result<()> {
+        let first = SaturnDB::open(temp_db("dir_a"), Options::default())?;
+        let second = SaturnDB::open(temp_db("dir_b"), Options::default())?;
+
+        for i in 0..8u8 {
+            first.put(vec![b'a', i], vec![i])?;
+            second.put(vec![b'a', i], vec![i + 100])?;
+            if i % 4 == 3 {
+                first.flush_memtable()?;
+                second.flush_memtable()?;
+            }
+        }
+        assert_eq!(first.num_files_at_level(0), 2);
+        assert_eq!(second.num_files_at_level(0), 2);
+
+        for i in 0..8u8 {
+            assert_eq!(first.get(&vec![b'a', i])?, Some(vec![i]));
+            assert_eq!(second.get(&vec![b'a', i])?, Some(vec![i + 100]));
+        }
+        for file in &first.inner.versions.lock().unwrap().current().files[0] {
+            assert!(filename::table_file_name(&first.inner.dbname, file.number).exists());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_newer_tables_shadow_older_ones() -> io::Result<()> {
+        let path = temp_db("shadowing");
+        let db = SaturnDB::open(&path, Options::default())?;
+
+        db.put(b"a".to_vec(), b"1".to_vec())?;
+        db.put(b"b".to_vec(), b"1".to_vec())?;
+        db.flush_memtable()?;
+        db.put(b"a".to_vec(), b"2".to_vec())?;
+        db.delete(b"b".to_vec())?;
+        db.flush_memtable()?;
+        assert_eq!(db.num_files_at_level(0), 2);
+
+        assert_eq!(db.get(&b"a".to_vec())?, Some(b"2".to_vec()));
+        assert_eq!(db.get(&b"b".to_vec())?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_delete_in_newer_table_shadows_put() -> io::Result<()> {
+        let path = temp_db("delete_shadows_put");
+        let db = SaturnDB::open(&path, Options::default())?;
+
+        db.put(b"key1".to_vec(), b"value1".to_vec())?;
+        db.put(b"key2".to_vec(), b"value2".to_vec())?;
+        db.flush_memtable()?;
+        db.delete(b"key1".to_vec())?;
+        db.flush_memtable()?;
+        assert_eq!(db.num_files_at_level(0), 2);
+
+        assert_eq!(db.get(&b"key1".to_vec())?, None);
+        assert_eq!(db.get(&b"key2".to_vec())?, Some(b"value2".to_vec()));
+
+        // A put after the delete brings the key back.
+        db.put(b"key1".to_vec(), b"value1b".to_vec())?;
+        assert_eq!(db.get(&b"key1".to_vec())?, Some(b"value1b".to_vec()));
+        db.flush_memtable()?;
+        assert_eq!(db.get(&b"key1".to_vec())?, Some(b"value1b".to_vec()));
+        drop(db);
+
+        let db = SaturnDB::open(&path, Options::default())?;
+        assert_eq!(db.get(&b"key1".to_vec())?, Some(b"value1b".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_compaction_merges_levels() -> io::Result<()> {
+        let path = temp_db("compaction");
+        let options = Options {
+            write_buffer_size: 4 * 1024,
+            level0_file_num_compaction_trigger: 2,
+            max_bytes_for_level_base: 8 * 1024,
+            max_bytes_for_level_multiplier: 4,
+            target_file_size: 2 * 1024,
+            ..Options::default()
+        };
+        let key = |i: u32| format!("key{:05}", i).into_bytes();
+
+        {
+            let db = SaturnDB::open(&path, options.clone())?;
+            for round in 0..5u32 {
+                for i in 0..400 {
+                    db.put(key(i), format!("value{}-{}", i, round).into_bytes())?;
+                }
+            }
+            for i in (0..400).step_by(2) {
+                db.delete(key(i))?;
+            }
+            db.flush_memtable()?;
+            assert!(db.num_files_at_level(0) < 2);
+            assert!(
+                (1..NUM_LEVELS)
+                    .map(|level| db.num_files_at_level(level))
+                    .sum::<usize>()
+                    > 1
+            );
+
+            for i in 0..400 {
+                let expected = (i % 2 == 1).then(|| format!("value{}-4", i).into_bytes());
+                assert_eq!(db.get(&key(i))?, expected);
+            }
+
+            // Merged away tables are deleted from disk.
+            let live: usize = (0..NUM_LEVELS)
+                .map(|level| db.num_files_at_level(level))
+                .sum();
+            let on_disk = fs::read_dir(&path)?
+                .filter(|entry| {
+                    let name = entry.as_ref().unwrap().file_name();
+                    matches!(
+                        filename::parse_file_name(name.to_str().unwrap()),
+                        Some((_, FileType::Table))
+                    )
+                })
+                .count();
+            assert_eq!(on_disk, live);
+        }
+
+        remove_logs(&path)?;
+        let db = SaturnDB::open(&path, options)?;
+        for i in 0..400 {
+            let expected = (i % 2 == 1).then(|| format!("value{}-4", i).into_bytes());
+            assert_eq!(db.get(&key(i))?, expected);
+        }
+        Ok(())
+    }
+
+    /// Orders keys in reverse bytewise order.
+    struct ReverseComparator;
+
+    impl Comparator for ReverseComparator {
+        fn compare(&self, a: &[u8], b: &[u8]) -> std::cmp::Ordering {
+            b.cmp(a)
+        }
+
+        fn name(&self) -> &'static str {
+            "ReverseComparator"
+        }
+
+        fn find_shortest_separator(&self, start: &[u8], _limit: &[u8]) -> Vec<u8> {
+            start.to_vec()
+        }
+
+        fn find_short_successor(&self, key: &[u8]) -> Vec<u8> {
+            key.to_vec()
+        }
+    }
+
+    #[test]
+    fn test_sdb_custom_comparator() -> io::Result<()> {
+        let path = temp_db("custom_comparator");
+        let options = Options {
+            comparator: Arc::new(ReverseComparator),
+            write_buffer_size: 4 * 1024,
+            level0_file_num_compaction_trigger: 2,
+            max_bytes_for_level_base: 8 * 1024,
+            target_file_size: 2 * 1024,
+            ..Options::default()
+        };
+        let key = |i: u32| format!("key{:03}", i);
+
+        for memtable_backend in [MemTableBackendKind::SkipList, MemTableBackendKind::BTree] {
+            let _ = fs::remove_dir_all(&path);
+            let options = Options {
+                memtable_backend,
+                ..options.clone()
+            };
+            {
+                let db = SaturnDB::open(&path, options.clone())?;
+                for round in 0..3 {
+                    for i in 0..300 {
+                        db.put(key(i).into_bytes(), vec![round])?;
+                    }
+                }
+                db.delete(key(7).into_bytes())?;
+                db.flush_memtable()?;
+                assert!((1..NUM_LEVELS).any(|level| db.num_files_at_level(level) > 0));
+
+                let forward = keys_forward(&mut db.iter()?);
+                assert_eq!(forward.len(), 299);
+                assert_eq!(forward[0], key(299));
+                assert_eq!(forward[298], key(0));
+                let mut backward = keys_backward(&mut db.iter()?);
+                backward.reverse();
+                assert_eq!(backward, forward);
+                let range = keys_forward(&mut db.range(key(20)..key(10))?);
+                assert_eq!(range, (11..=20).rev().map(key).collect::<Vec<_>>());
+                assert_eq!(db.get(&key(42).into_bytes())?, Some(vec![2]));
+                assert_eq!(db.get(&key(7).into_bytes())?, None);
+            }
+
+            let err = SaturnDB::open(&path, Options::default()).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            let db = SaturnDB::open(&path, options)?;
+            assert_eq!(db.get(&key(299).into_bytes())?, Some(vec![2]));
+            assert_eq!(keys_forward(&mut db.iter()?).len(), 299);
+        }
+        Ok(())
+    }
+
+    /// Orders keys bytewise, ignoring ASCII case.
+    struct CaseFoldingComparator;
+
+    impl Comparator for CaseFoldingComparator {
+        fn compare(&self, a: &[u8], b: &[u8]) -> std::cmp::Ordering {
+            a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase())
+        }
+
+        fn name(&self) -> &'static str {
+            "CaseFoldingComparator"
+        }
+
+        fn find_shortest_separator(&self, start: &[u8], _limit: &[u8]) -> Vec<u8> {
+            start.to_vec()
+        }
+
+        fn find_short_successor(&self, key: &[u8]) -> Vec<u8> {
+            key.to_vec()
+        }
+    }
+
+    #[test]
+    fn test_sdb_comparator_equal_keys() -> io::Result<()> {
+        let path = temp_db("comparator_equal_keys");
+        let options = Options {
+            comparator: Arc::new(CaseFoldingComparator),
+            bloom_bits_per_key: 0,
+            level0_file_num_compaction_trigger: 2,
+            ..Options::default()
+        };
+        let db = SaturnDB::open(&path, options)?;
+        db.put(b"Apple".to_vec(), vec![1])?;
+        db.put(b"banana".to_vec(), vec![1])?;
+        assert_eq!(db.get(&b"APPLE".to_vec())?, Some(vec![1]));
+        db.put(b"apple".to_vec(), vec![2])?;
+        assert_eq!(db.get(&b"aPpLe".to_vec())?, Some(vec![2]));
+
+        db.flush_memtable()?;
+        assert_eq!(db.get(&b"APPLE".to_vec())?, Some(vec![2]));
+        db.delete(b"BANANA".to_vec())?;
+        assert_eq!(db.get(&b"banana".to_vec())?, None);
+
+        // Compaction keeps only the newest version of each key.
+        db.put(b"APPLE".to_vec(), vec![3])?;
+        db.flush_memtable()?;
+        assert!(db.num_files_at_level(1) > 0);
+        assert_eq!(db.get(&b"apple".to_vec())?, Some(vec![3]));
+        assert_eq!(db.get(&b"Banana".to_vec())?, None);
+        assert_eq!(keys_forward(&mut db.iter()?), ["APPLE"]);
+
+        // Keys sharing a prefix need not be adjacent under this ordering.
+        let err = db.prefix(b"a").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_universal_compaction() -> io::Result<()> {
+        let path = temp_db("universal");
+        let options = Options {
+            write_buffer_size: 4 * 1024,
+            compaction_style: CompactionStyle::Universal,
+            level0_file_num_compaction_trigger: 3,
+            universal_max_sorted_runs: 5,
+            ..Options::default()
+        };
+        let key = |i: u32| format!("key{:05}", i).into_bytes();
+
+        let db = SaturnDB::open(&path, options.clone())?;
+        for round in 0..5u32 {
+            for i in 0..200 {
+                db.put(key(i), format!("value{}-{}", i, round).into_bytes())?;
+            }
+        }
+        for i in (0..200).step_by(2) {
+            db.delete(key(i))?;
+        }
+        db.flush_memtable()?;
+        assert!(db.num_files_at_level(0) <= 5);
+        assert!((1..NUM_LEVELS).all(|level| db.num_files_at_level(level) == 0));
+        for i in 0..200 {
+            let expected = (i % 2 == 1).then(|| format!("value{}-4", i).into_bytes());
+            assert_eq!(db.get(&key(i))?, expected);
+        }
+        drop(db);
+
+        remove_logs(&path)?;
+        let db = SaturnDB::open(&path, options)?;
+        for i in 0..200 {
+            let expected = (i % 2 == 1).then(|| format!("value{}-4", i).into_bytes());
+            assert_eq!(db.get(&key(i))?, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_fifo_compaction() -> io::Result<()> {
+        let path = temp_db("fifo");
+        let options = Options {
+            write_buffer_size: 4 * 1024,
+            compaction_style: CompactionStyle::Fifo,
+            fifo_max_table_files_size: 16 * 1024,
+            ..Options::default()
+        };
+        let key = |i: u32| format!("key{:05}", i).into_bytes();
+
+        let db = SaturnDB::open(&path, options)?;
+        for i in 0..2000 {
+            db.put(key(i), vec![b'x'; 100])?;
+        }
+        db.wait_for_background_work()?;
+        let version = db.inner.versions.lock().unwrap().current();
+        assert!(version.level_bytes(0) <= 16 * 1024);
+        assert!((1..NUM_LEVELS).all(|level| version.files[level].is_empty()));
+        // The oldest keys were dropped along with their tables; the newest
+        // survive.
+        assert_eq!(db.get(&key(0))?, None);
+        assert_eq!(db.get(&key(1999))?, Some(vec![b'x'; 100]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_tables_survive_restart() -> io::Result<()> {
+        let path = temp_db("tables_restart");
+
+        {
+            let db = SaturnDB::open(&path, Options::default())?;
+            for i in 0..25u8 {
+                db.put(vec![i], vec![i])?;
+                if i % 10 == 9 {
+                    db.flush_memtable()?;
+                }
+            }
+            assert_eq!(db.num_files_at_level(0), 2);
+        }
+        // Only the manifest knows about the flushed tables.
+        remove_logs(&path)?;
+
+        let db = SaturnDB::open(&path, Options::default())?;
+        assert_eq!(db.num_files_at_level(0), 2);
+        for i in 0..20u8 {
+            assert_eq!(db.get(&vec![i])?, Some(vec![i]));
+        }
+        assert_eq!(db.get(&vec![20])?, None);
+        assert!(db.inner.versions.lock().unwrap().last_sequence() >= 20);
+        Ok(())
+    }
+
+    fn keys_forward(iter: &mut DBIterator) -> Vec<String> {
+        let mut keys = Vec::new();
+        iter.seek_to_first();
+        while iter.valid() {
+            keys.push(String::from_utf8_lossy(iter.key()).into_owned());
+            iter.next();
+        }
+        keys
+    }
+
+    fn keys_backward(iter: &mut DBIterator) -> Vec<String> {
+        let mut keys = Vec::new();
+        iter.seek_to_last();
+        while iter.valid() {
+            keys.push(String::from_utf8_lossy(iter.key()).into_owned());
+            iter.prev();
+        }
+        keys
+    }
+
+    #[test]
+    fn test_sdb_iterator_merges_memtable_and_tables() -> io::Result<()> {
+        let path = temp_db("iterator");
+        let db = SaturnDB::open(&path, Options::default())?;
+
+        for key in ["a", "b", "c", "d"] {
+            db.put(key.into(), format!("{key}1").into_bytes())?;
+        }
+        db.flush_memtable()?;
+        db.put(b"b".to_vec(), b"b2".to_vec())?;
+        db.delete(b"c".to_vec())?;
+        db.flush_memtable()?;
+        db.put(b"e".to_vec(), b"e1".to_vec())?;
+        db.delete(b"a".to_vec())?;
+        db.put(b"c".to_vec(), b"c3".to_vec())?;
+        assert_eq!(db.num_files_at_level(0), 2);
+
+        let mut iter = db.iter()?;
+        assert_eq!(keys_forward(&mut iter), ["b", "c", "d", "e"]);
+        assert_eq!(keys_backward(&mut iter), ["e", "d", "c", "b"]);
+        iter.status()?;
+
+        iter.seek(b"bb");
+        assert_eq!(iter.key(), b"c");
+        assert_eq!(iter.value(), b"c3");
+        iter.prev();
+        assert_eq!((iter.key(), iter.value()), (&b"b"[..], &b"b2"[..]));
+        iter.prev();
+        assert!(!iter.valid());
+        iter.seek(b"d");
+        iter.prev();
+        iter.next();
+        assert_eq!((iter.key(), iter.value()), (&b"d"[..], &b"d1"[..]));
+
+        // The iterator keeps its view while the database changes under it.
+        db.put(b"f".to_vec(), b"f1".to_vec())?;
+        db.delete(b"d".to_vec())?;
+        db.flush_memtable()?;
+        assert_eq!(keys_forward(&mut iter), ["b", "c", "d", "e"]);
+        assert_eq!(keys_forward(&mut db.iter()?), ["b", "c", "e", "f"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_range_and_prefix() -> io::Result<()> {
+        let path = temp_db("range_prefix");
+        let db = SaturnDB::open(&path, Options::default())?;
+        for key in ["a", "ab", "abc", "abd", "b", "ba", "c"] {
+            db.put(key.into(), key.into())?;
+            if key == "b" {
+                db.flush_memtable()?;
+            }
+        }
+        db.delete(b"abc".to_vec())?;
+
+        let mut iter = db.range(&b"ab"[..]..&b"b"[..])?;
+        assert_eq!(keys_forward(&mut iter), ["ab", "abd"]);
+        assert_eq!(keys_backward(&mut iter), ["abd", "ab"]);
+        iter.seek(b"a");
+        assert_eq!(iter.key(), b"ab");
+
+        let mut iter = db.range(&b"ab"[..]..=&b"b"[..])?;
+        assert_eq!(keys_forward(&mut iter), ["ab", "abd", "b"]);
+        assert_eq!(keys_backward(&mut iter), ["b", "abd", "ab"]);
+
+        let mut iter = db.range((Bound::Excluded(b"ab".to_vec()), Bound::Unbounded))?;
+        assert_eq!(keys_forward(&mut iter), ["abd", "b", "ba", "c"]);
+
+        let mut iter = db.range(..&b"a"[..])?;
+        assert!(keys_forward(&mut iter).is_empty());
+        assert!(keys_backward(&mut iter).is_empty());
+
+        let mut iter = db.prefix(b"ab")?;
+        assert_eq!(keys_forward(&mut iter), ["ab", "abd"]);
+        assert_eq!(keys_backward(&mut iter), ["abd", "ab"]);
+
+        db.put(vec![b'b', 0xff, 0xff], b"x".to_vec())?;
+        let mut iter = db.prefix(b"b")?;
+        assert_eq!(keys_forward(&mut iter), ["b", "ba", "b\u{fffd}\u{fffd}"]);
+        let mut iter = db.prefix(&[b'b', 0xff])?;
+        assert_eq!(keys_backward(&mut iter), ["b\u{fffd}\u{fffd}"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_snapshot_reads() -> io::Result<()> {
+        let path = temp_db("snapshots");
+        let options = Options {
+            level0_file_num_compaction_trigger: 2,
+            ..Options::default()
+        };
+        let db = SaturnDB::open(&path, options)?;
+        db.put(b"a".to_vec(), b"a1".to_vec())?;
+        db.put(b"b".to_vec(), b"b1".to_vec())?;
+        let snapshot = db.snapshot();
+
+        db.put(b"a".to_vec(), b"a2".to_vec())?;
+        db.delete(b"b".to_vec())?;
+        db.put(b"c".to_vec(), b"c2".to_vec())?;
+        let at_snapshot = ReadOptions {
+            snapshot: Some(&snapshot),
+        };
+        let check = |db: &SaturnDB| -> io::Result<()> {
+            assert_eq!(
+                db.get_opt(&b"a".to_vec(), &at_snapshot)?,
+                Some(b"a1".to_vec())
+            );
+            assert_eq!(
+                db.get_opt(&b"b".to_vec(), &at_snapshot)?,
+                Some(b"b1".to_vec())
+            );
+            assert_eq!(db.get_opt(&b"c".to_vec(), &at_snapshot)?, None);
+            assert_eq!(keys_forward(&mut db.iter_opt(&at_snapshot)?), ["a", "b"]);
+            assert_eq!(
+                keys_backward(&mut db.prefix_opt(b"b", &at_snapshot)?),
+                ["b"]
+            );
+
+            assert_eq!(db.get(&b"a".to_vec())?, Some(b"a2".to_vec()));
+            assert_eq!(db.get(&b"b".to_vec())?, None);
+            assert_eq!(keys_forward(&mut db.iter()?), ["a", "c"]);
+            Ok(())
+        };
+        check(&db)?;
+
+        // Flushing and compacting keeps the versions the snapshot reads.
+        db.flush_memtable()?;
+        db.delete(b"a0".to_vec())?;
+        db.flush_memtable()?;
+        assert_eq!(db.num_files_at_level(0), 0);
+        check(&db)?;
+
+        drop(snapshot);
+        db.put(b"e".to_vec(), b"e4".to_vec())?;
+        db.flush_memtable()?;
+        db.put(b"f".to_vec(), b"f4".to_vec())?;
+        db.flush_memtable()?;
+        assert_eq!(keys_forward(&mut db.iter()?), ["a", "c", "e", "f"]);
+        assert_eq!(db.get(&b"a".to_vec())?, Some(b"a2".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_snapshots_race_overwrites() -> io::Result<()> {
+        let path = temp_db("snapshot_race");
+        let options = Options {
+            write_buffer_size: 4 * 1024,
+            level0_file_num_compaction_trigger: 2,
+            ..Options::default()
+        };
+        let db = Arc::new(SaturnDB::open(&path, options)?);
+        let value = |i: usize| format!("{i:0100}").into_bytes();
+        db.put(b"k".to_vec(), value(0))?;
+
+        let writer = {
+            let db = db.clone();
+            thread::spawn(move || -> io::Result<()> {
+                for i in 1..1000 {
+                    db.put(b"k".to_vec(), value(i))?;
+                }
+                Ok(())
+            })
+        };
+        let mut snapshots = Vec::new();
+        while !writer.is_finished() {
+            let snapshot = db.snapshot();
+            let at_snapshot = ReadOptions {
+                snapshot: Some(&snapshot),
+            };
+            let seen = db.get_opt(&b"k".to_vec(), &at_snapshot)?;
+            assert!(seen.is_some());
+            snapshots.push((snapshot, seen));
+            thread::sleep(Duration::from_micros(200));
+        }
+        writer.join().unwrap()?;
+        db.flush_memtable()?;
+
+        // Every snapshot still reads what it read when it was taken, after
+        // the overwrites were flushed and compacted.
+        for (snapshot, seen) in &snapshots {
+            let at_snapshot = ReadOptions {
+                snapshot: Some(snapshot),
+            };
+            assert_eq!(&db.get_opt(&b"k".to_vec(), &at_snapshot)?, seen);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_write_batch_is_atomic() -> io::Result<()> {
+        let path = temp_db("write_batch");
+        {
+            let db = SaturnDB::open(&path, Options::default())?;
+            db.put(b"a".to_vec(), b"a0".to_vec())?;
+            let mut batch = WriteBatch::new();
+            batch.put(b"a", b"a1");
+            batch.put(b"b", b"b1");
+            batch.delete(b"a");
+            batch.put(b"c", b"c1");
+            let snapshot = db.snapshot();
+            db.write(batch)?;
+
+            assert_eq!(db.get(&b"a".to_vec())?, None);
+            assert_eq!(keys_forward(&mut db.iter()?), ["b", "c"]);
+            let before = ReadOptions {
+                snapshot: Some(&snapshot),
+            };
+            assert_eq!(keys_forward(&mut db.iter_opt(&before)?), ["a"]);
+            assert_eq!(db.snapshot().sequence(), snapshot.sequence() + 4);
+        }
+
+        // Replay applies the batch whole.
+        let db = SaturnDB::open(&path, Options::default())?;
+        assert_eq!(keys_forward(&mut db.iter()?), ["b", "c"]);
+        let mut batch = WriteBatch::new();
+        batch.put(b"d", b"d1");
+        batch.put(b"e", b"e1");
+        db.write(batch)?;
+        drop(db);
+
+        // A batch cut short by a crash is dropped whole.
+        let wal = filename::log_file_name(&path, *log_numbers(&path)?.last().unwrap());
+        let len = fs::metadata(&wal)?.len();
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&wal)?
+            .set_len(len - 3)?;
+        let db = SaturnDB::open(&path, Options::default())?;
+        assert_eq!(keys_forward(&mut db.iter()?), ["b", "c"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_failed_log_write_stops_writes() -> io::Result<()> {
+        let path = temp_db("failed_log_write");
+        let synced = WriteOptions {
+            sync: true,
+            ..WriteOptions::default()
+        };
+        {
+            let db = SaturnDB::open(&path, Options::default())?;
+            db.put(b"a".to_vec(), b"a1".to_vec())?;
+            db.inner.wal.lock().unwrap().fail_next_sync = true;
+            let err = db
+                .put_opt(b"b".to_vec(), b"b1".to_vec(), &synced)
+                .unwrap_err();
+            assert_eq!(err.to_string(), "injected sync failure");
+
+            // Later writes would reuse the sequence numbers of the failed
+            // one, so they are refused; reads go on.
+            let err = db.put(b"c".to_vec(), b"c1".to_vec()).unwrap_err();
+            assert_eq!(err.to_string(), "injected sync failure");
+            assert!(db.write(WriteBatch::new()).is_ok());
+            assert_eq!(db.get(&b"a".to_vec())?, Some(b"a1".to_vec()));
+            assert_eq!(db.get(&b"c".to_vec())?, None);
+        }
+
+        let db = SaturnDB::open(&path, Options::default())?;
+        let sequence = db.snapshot().sequence();
+        db.put_opt(b"c".to_vec(), b"c2".to_vec(), &synced)?;
+        assert_eq!(db.snapshot().sequence(), sequence + 1);
+        drop(db);
+        let db = SaturnDB::open(&path, Options::default())?;
+        assert_eq!(db.get(&b"a".to_vec())?, Some(b"a1".to_vec()));
+        assert_eq!(db.get(&b"c".to_vec())?, Some(b"c2".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_concurrent_writers() -> io::Result<()> {
+        let path = temp_db("concurrent_writers");
+        let options = Options {
+            write_buffer_size: 8 * 1024,
+            ..Options::default()
+        };
+        let key = |t: usize, i: usize| format!("key{t}-{i:03}").into_bytes();
+        {
+            let db = Arc::new(SaturnDB::open(&path, options.clone())?);
+            let threads: Vec<_> = (0..8)
+                .map(|t| {
+                    let db = db.clone();
+                    std::thread::spawn(move || -> io::Result<()> {
+                        for i in 0..200 {
+                            db.put(key(t, i), vec![t as u8])?;
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap()?;
+            }
+            assert_eq!(db.snapshot().sequence(), 8 * 200);
+            for t in 0..8 {
+                for i in 0..200 {
+                    assert_eq!(db.get(&key(t, i))?, Some(vec![t as u8]));
+                }
+            }
+        }
+
+        let db = SaturnDB::open(&path, options)?;
+        let mut iter = db.iter()?;
+        assert_eq!(keys_forward(&mut iter).len(), 8 * 200);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_log_per_memtable() -> io::Result<()> {
+        let path = temp_db("log_per_memtable");
+        let options = Options {
+            write_buffer_size: TEN_ENTRIES,
+            ..Options::default()
+        };
+        let first_log;
+        {
+            let db = SaturnDB::open(&path, options.clone())?;
+            for i in 0..5u8 {
+                db.put(vec![i], big_value(i))?;
+            }
+            let logs = log_numbers(&path)?;
+            assert_eq!(logs.len(), 1);
+            first_log = logs[0];
+
+            // The flush switches to a new log and deletes the old one.
+            for i in 5..15u8 {
+                db.put(vec![i], big_value(i))?;
+            }
+            db.wait_for_background_work()?;
+            assert_eq!(db.num_files_at_level(0), 1);
+            let logs = log_numbers(&path)?;
+            assert_eq!(logs.len(), 1);
+            assert!(logs[0] > first_log);
+            assert_eq!(db.inner.versions.lock().unwrap().log_number(), logs[0]);
+        }
+
+        // Recovery writes the replayed log out to a table and drops it.
+        let db = SaturnDB::open(&path, options)?;
+        assert_eq!(db.num_files_at_level(0), 2);
+        let logs = log_numbers(&path)?;
+        assert_eq!(logs.len(), 1);
+        assert_eq!(db.inner.versions.lock().unwrap().log_number(), logs[0]);
+        for i in 0..15u8 {
+            assert_eq!(db.get(&vec![i])?, Some(big_value(i)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_recover_unflushed_memtables() -> io::Result<()> {
+        let path = temp_db("recover_unflushed_memtables");
+        let crashed = temp_db("recover_unflushed_memtables_crashed");
+        let options = Options {
+            write_buffer_size: TEN_ENTRIES,
+            max_immutable_memtables: 4,
+            level0_file_num_compaction_trigger: 8,
+            ..Options::default()
+        };
+        {
+            let db = SaturnDB::open(&path, options.clone())?;
+            // Keep the background thread from flushing anything.
+            let pending_outputs = db.inner.pending_outputs.lock().unwrap();
+            for i in 0..35u8 {
+                db.put(vec![i], big_value(i))?;
+            }
+            db.sync_wal()?;
+            assert_eq!(db.inner.memtables.lock().unwrap().immutable.len(), 3);
+            assert_eq!(log_numbers(&path)?.len(), 4);
+
+            // Copy the files as a crash would leave them.
+            fs::create_dir_all(&crashed)?;
+            for entry in fs::read_dir(&path)? {
+                let entry = entry?;
+                fs::copy(entry.path(), crashed.join(entry.file_name()))?;
+            }
+            drop(pending_outputs);
+        }
+
+        // Each log is replayed in order and written out to a table.
+        let db = SaturnDB::open(&crashed, options)?;
+        assert_eq!(db.num_files_at_level(0), 4);
+        let logs = log_numbers(&crashed)?;
+        assert_eq!(logs.len(), 1);
+        assert_eq!(db.inner.versions.lock().unwrap().log_number(), logs[0]);
+        for i in 0..35u8 {
+            assert_eq!(db.get(&vec![i])?, Some(big_value(i)));
+        }
+        assert_eq!(db.snapshot().sequence(), 35);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_logs_outlive_unrecorded_flushes() -> io::Result<()> {
+        let path = temp_db("logs_outlive_unrecorded_flushes");
+        let options = Options {
+            write_buffer_size: TEN_ENTRIES,
+            ..Options::default()
+        };
+        let db = SaturnDB::open(&path, options)?;
+        let first_log = log_numbers(&path)?[0];
+        let pending_outputs = db.inner.pending_outputs.lock().unwrap();
+        for i in 0..11u8 {
+            db.put(vec![i], big_value(i))?;
+        }
+        // The flush has taken a number for its table and waits for
+        // `pending_outputs`. Once let go, it writes the table, then waits
+        // for the memtables lock to record the table in the manifest.
+        let table_number = first_log + 2;
+        while db.inner.versions.lock().unwrap().next_file_number() <= table_number {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let memtables = db.inner.memtables.lock().unwrap();
+        drop(pending_outputs);
+        while !filename::table_file_name(&path, table_number).exists() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        db.inner.delete_obsolete_files()?;
+        assert_eq!(log_numbers(&path)?, [first_log, first_log + 1]);
+        assert_eq!(db.inner.versions.lock().unwrap().log_number(), first_log);
+        assert_eq!(db.num_files_at_level(0), 0);
+
+        // Once the manifest has the table, the log goes.
+        drop(memtables);
+        db.wait_for_background_work()?;
+        assert_eq!(db.num_files_at_level(0), 1);
+        assert_eq!(log_numbers(&path)?, [first_log + 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_background_flush() -> io::Result<()> {
+        let path = temp_db("background_flush");
+        let options = Options {
+            write_buffer_size: TEN_ENTRIES,
+            max_immutable_memtables: 1,
+            ..Options::default()
+        };
+        let db = Arc::new(SaturnDB::open(&path, options)?);
+
+        // Keep the background thread from starting a table.
+        let pending_outputs = db.inner.pending_outputs.lock().unwrap();
+        for i in 0..11u8 {
+            db.put(vec![i], big_value(i))?;
+        }
+        // The full memtable is still readable while it waits.
+        assert_eq!(db.inner.memtables.lock().unwrap().immutable.len(), 1);
+        assert_eq!(db.num_files_at_level(0), 0);
+        for i in 0..11u8 {
+            assert_eq!(db.get(&vec![i])?, Some(big_value(i)));
+        }
+        assert_eq!(keys_forward(&mut db.iter()?).len(), 11);
+
+        // Filling the second memtable stalls the writer.
+        let writer = {
+            let db = db.clone();
+            thread::spawn(move || -> io::Result<()> {
+                for i in 11..21u8 {
+                    db.put(vec![i], big_value(i))?;
+                }
+                Ok(())
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!writer.is_finished());
+
+        drop(pending_outputs);
+        writer.join().unwrap()?;
+        db.wait_for_background_work()?;
+        assert!(db.num_files_at_level(0) >= 1);
+        for i in 0..21u8 {
+            assert_eq!(db.get(&vec![i])?, Some(big_value(i)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_background_flush_error() -> io::Result<()> {
+        let path = temp_db("background_flush_error");
+        let options = Options {
+            write_buffer_size: TEN_ENTRIES,
+            max_immutable_memtables: 1,
+            ..Options::default()
+        };
+        let table;
+        {
+            let db = Arc::new(SaturnDB::open(&path, options.clone())?);
+            // A directory where the first table goes makes its flush fail.
+            table = filename::table_file_name(&path, log_numbers(&path)?[0] + 2);
+            fs::create_dir(&table)?;
+
+            let pending_outputs = db.inner.pending_outputs.lock().unwrap();
+            for i in 0..11u8 {
+                db.put(vec![i], big_value(i))?;
+            }
+            // A writer stopped on the full memtables is woken by the error.
+            let writer = {
+                let db = db.clone();
+                thread::spawn(move || -> io::Result<()> {
+                    for i in 11..21u8 {
+                        db.put(vec![i], big_value(i))?;
+                    }
+                    Ok(())
+                })
+            };
+            thread::sleep(Duration::from_millis(50));
+            assert!(!writer.is_finished());
+            drop(pending_outputs);
+            let err = writer.join().unwrap().unwrap_err();
+            let background_err = db.wait_for_background_work().unwrap_err();
+            assert_eq!(err.kind(), background_err.kind());
+            assert_eq!(err.to_string(), background_err.to_string());
+
+            // Later writes fail the same way; reads go on.
+            let err = db.put(b"x".to_vec(), b"x".to_vec()).unwrap_err();
+            assert_eq!(err.kind(), background_err.kind());
+            for i in 0..11u8 {
+                assert_eq!(db.get(&vec![i])?, Some(big_value(i)));
+            }
+            assert_eq!(db.num_files_at_level(0), 0);
+        }
+
+        fs::remove_dir(&table)?;
+        let db = SaturnDB::open(&path, options)?;
+        for i in 0..11u8 {
+            assert_eq!(db.get(&vec![i])?, Some(big_value(i)));
+        }
+        db.put(b"x".to_vec(), b"x".to_vec())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_write_stalls() -> io::Result<()> {
+        use crate::write_stall::WriteStallCause;
+
+        let path = temp_db("write_stalls");
+        let options = Options {
+            write_buffer_size: TEN_ENTRIES,
+            level0_file_num_compaction_trigger: 100,
+            level0_slowdown_writes_trigger: 1,
+            ..Options::default()
+        };
+        let db = SaturnDB::open(&path, options)?;
+        for i in 0..11u8 {
+            db.put(vec![i], big_value(i))?;
+        }
+        db.wait_for_background_work()?;
+        let stats = db.write_stall_stats();
+        assert_eq!(
+            stats.condition,
+            WriteStallCondition::Delayed(WriteStallCause::Level0FileCount)
+        );
+        assert_eq!(stats.delayed_writes, 0);
+        db.put(b"x".to_vec(), b"x".to_vec())?;
+        let stats = db.write_stall_stats();
+        assert_eq!(stats.delayed_writes, 1);
+        assert!(stats.delay_time >= Duration::from_millis(1));
+        assert_eq!(stats.stopped_writes, 0);
+        drop(db);
+
+        let path = temp_db("write_stops");
+        let options = Options {
+            write_buffer_size: TEN_ENTRIES,
+            level0_file_num_compaction_trigger: 1,
+            level0_stop_writes_trigger: 1,
+            ..Options::default()
+        };
+        let db = Arc::new(SaturnDB::open(&path, options)?);
+        // Keep the background thread from compacting the flushed table.
+        let picker = db.inner.compaction_picker.lock().unwrap();
+        for i in 0..11u8 {
+            db.put(vec![i], big_value(i))?;
+        }
+        while db.num_files_at_level(0) == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(
+            db.write_stall_stats().condition,
+            WriteStallCondition::Stopped(WriteStallCause::Level0FileCount)
+        );
+        let writer = {
+            let db = db.clone();
+            thread::spawn(move || db.put(b"x".to_vec(), b"x".to_vec()))
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!writer.is_finished());
+
+        drop(picker);
+        writer.join().unwrap()?;
+        db.wait_for_background_work()?;
+        let stats = db.write_stall_stats();
+        assert_eq!(stats.condition, WriteStallCondition::Normal);
+        assert_eq!(stats.stopped_writes, 1);
+        assert!(stats.stop_time >= Duration::from_millis(50));
+        assert_eq!(db.get(&b"x".to_vec())?, Some(b"x".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_write_options() -> io::Result<()> {
+        let path = temp_db("write_options");
+        {
+            let db = SaturnDB::open(&path, Options::default())?;
+            let synced = WriteOptions {
+                sync: true,
+                ..WriteOptions::default()
+            };
+            db.put_opt(b"a".to_vec(), b"1".to_vec(), &synced)?;
+            assert!(!db.inner.wal.lock().unwrap().needs_sync());
+
+            db.put(b"b".to_vec(), b"2".to_vec())?;
+            assert!(db.inner.wal.lock().unwrap().needs_sync());
+            db.sync_wal()?;
+            assert!(!db.inner.wal.lock().unwrap().needs_sync());
+
+            let unlogged = WriteOptions {
+                disable_wal: true,
+                ..WriteOptions::default()
+            };
+            db.put_opt(b"c".to_vec(), b"3".to_vec(), &unlogged)?;
+            db.delete_opt(b"a".to_vec(), &unlogged)?;
+            assert!(!db.inner.wal.lock().unwrap().needs_sync());
+            assert_eq!(keys_forward(&mut db.iter()?), ["b", "c"]);
+        }
+
+        // Writes that skipped the log are lost without a flush.
+        let db = SaturnDB::open(&path, Options::default())?;
+        assert_eq!(keys_forward(&mut db.iter()?), ["a", "b"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sdb_wal_sync_policy() -> io::Result<()> {
+        let path = temp_db("wal_sync_policy");
+        let options = Options {
+            wal_sync_policy: WalSyncPolicy::EveryWrite,
+            wal_sync_method: SyncMethod::Fsync,
+            ..Options::default()
+        };
+        {
+            let db = SaturnDB::open(&path, options)?;
+            db.put(b"a".to_vec(), b"1".to_vec())?;
+            assert!(!db.inner.wal.lock().unwrap().needs_sync());
+        }
+
+        let options = Options {
+            wal_sync_policy: WalSyncPolicy::Interval(Duration::from_millis(10)),
+            ..Options::default()
+        };
+        let db = SaturnDB::open(&path, options)?;
+        db.put(b"b".to_vec(), b"2".to_vec())?;
+        let mut waited = Duration::ZERO;
+        while db.inner.wal.lock().unwrap().needs_sync() {
+            assert!(waited < Duration::from_secs(10), "log was never synced");
+            thread::sleep(Duration::from_millis(5));
+            waited += Duration::from_millis(5);
+        }
+        assert_eq!(keys_forward(&mut db.iter()?), ["a", "b"]);
+
+        // Closing the database stops the sync thread, without waiting out
+        // its interval.
+        let options = Options {
+            wal_sync_policy: WalSyncPolicy::Interval(Duration::from_secs(3600)),
+            ..Options::default()
+        };
+        drop(db);
+        let db = SaturnDB::open(&path, options)?;
+        let inner = Arc::downgrade(&db.inner);
+        let start = Instant::now();
+        drop(db);
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(inner.upgrade().is_none());
+        Ok(())
+    }
 }
@@ -1,9 +1,27 @@
-use crate::common::{Key, Value};
+// A skip list that readers can search while a writer inserts into it.
+//
+// Each node is a single arena allocation holding its links, key and value.
+// Nodes are never removed or freed before the list is dropped. A node is
+// linked in bottom level first, each link with a compare-and-swap that
+// publishes the node (release) to readers loading links (acquire). A reader
+// therefore sees a node either fully built or not at all, and a search that
+// misses a node at an upper level finds it on the levels below. Concurrent
+// inserts that lose a race for a link find their place again and retry.
+// Searches treat equal keys alike, so they stay correct even where threads
+// inserting equal keys at once linked them in different orders on
+// different levels.
+
+use crate::arena::Arena;
 use crate::comparator::Comparator;
+use crate::iterator::InternalIterator;
 use rand::Rng;
 use std::cmp::Ordering;
-use std::marker::PhantomData;
+use std::io;
+use std::mem;
+use std::ops::Deref;
 use std::ptr;
+use std::slice;
+use std::sync::atomic::{self, AtomicPtr, AtomicUsize};
 use std::sync::Arc;
 
 /// The maximum number of levels in the skip list.
@@ -16,147 +34,311 @@ const P: f64 = 0.25;
 
 type Link = *mut Node;
 
+/// The header of a node. It is followed in memory by `height` links, then
+/// the key and the value. Nodes are only handled through raw pointers, so
+/// reads past the header stay within the allocation.
+#[repr(C)]
 struct Node {
-    key: Key,
-    val: Value,
-    next: Vec<Link>,
+    height: usize,
+    key_len: usize,
+    value_len: usize,
 }
 
 impl Node {
-    pub fn new(key: Key, value: Value, level: usize) -> Self {
-        Node {
-            key: key,
-            val: value,
-            next: vec![ptr::null_mut(); level],
+    /// Allocates a node in `arena` with `height` null links.
+    fn new(arena: &Arena, key: &[u8], value: &[u8], height: usize) -> Link {
+        let links = height * mem::size_of::<AtomicPtr<Node>>();
+        let size = mem::size_of::<Node>() + links + key.len() + value.len();
+        let node = arena.allocate(size) as Link;
+        unsafe {
+            node.write(Node {
+                height,
+                key_len: key.len(),
+                value_len: value.len(),
+            });
+            for level in 0..height {
+                Self::link_ptr(node, level).write(AtomicPtr::new(ptr::null_mut()));
+            }
+            let data = Self::data_ptr(node);
+            ptr::copy_nonoverlapping(key.as_ptr(), data, key.len());
+            ptr::copy_nonoverlapping(value.as_ptr(), data.add(key.len()), value.len());
+        }
+        node
+    }
+
+    unsafe fn link_ptr(node: Link, level: usize) -> *mut AtomicPtr<Node> {
+        unsafe { (node.add(1) as *mut AtomicPtr<Node>).add(level) }
+    }
+
+    unsafe fn data_ptr(node: Link) -> *mut u8 {
+        unsafe { Self::link_ptr(node, (*node).height) as *mut u8 }
+    }
+
+    /// Returns the link of `node` at `level`. `node` must be a node of a
+    /// list outliving `'a`, at least `level + 1` high.
+    unsafe fn link<'a>(node: Link, level: usize) -> &'a AtomicPtr<Node> {
+        debug_assert!(level < unsafe { (*node).height });
+        unsafe { &*Self::link_ptr(node, level) }
+    }
+
+    unsafe fn next(node: Link, level: usize) -> Link {
+        unsafe { Self::link(node, level).load(atomic::Ordering::Acquire) }
+    }
+
+    unsafe fn key<'a>(node: Link) -> &'a [u8] {
+        unsafe { slice::from_raw_parts(Self::data_ptr(node), (*node).key_len) }
+    }
+
+    unsafe fn value<'a>(node: Link) -> &'a [u8] {
+        unsafe {
+            let data = Self::data_ptr(node).add((*node).key_len);
+            slice::from_raw_parts(data, (*node).value_len)
         }
     }
 }
 
 pub struct SkipList {
+    arena: Arena,
     head: Link,
-    level: usize,
+    // Height of the tallest node. Readers may see a stale height, which
+    // only makes them start their search lower.
+    level: AtomicUsize,
+    len: AtomicUsize,
     comparator: Arc<dyn Comparator>,
-    rng: rand::rngs::ThreadRng,
 }
 
+// SAFETY: the nodes live in the list's arena until the list is dropped.
+// Links are atomic, and nodes are immutable once published.
+unsafe impl Send for SkipList {}
+unsafe impl Sync for SkipList {}
+
 impl SkipList {
     pub fn new(comparator: Arc<dyn Comparator>) -> Self {
-        // The head node can now be a regular node with empty key/value pairs.
-        let head_node = Box::new(Node::new(Vec::new(), Vec::new(), MAX_LEVEL));
+        let arena = Arena::new();
+        // The head node is a regular node with an empty key and value.
+        let head = Node::new(&arena, &[], &[], MAX_LEVEL);
 
         SkipList {
-            head: Box::into_raw(head_node),
-            level: 1,
+            arena,
+            head,
+            level: AtomicUsize::new(1),
+            len: AtomicUsize::new(0),
             comparator,
-            rng: rand::rng(),
         }
     }
 
-    fn random_level(&mut self) -> usize {
+    fn random_level(&self) -> usize {
+        let mut rng = rand::rng();
         let mut level = 1;
-        while self.rng.random::<f64>() < P && level < MAX_LEVEL {
+        while rng.random::<f64>() < P && level < MAX_LEVEL {
             level += 1;
         }
         level
     }
 
-    /// Inserts a key-value pair into the skip list.
-    pub fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) {
-        let mut update = vec![self.head; MAX_LEVEL];
+    fn level(&self) -> usize {
+        self.level.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Inserts a copy of a key-value pair into the skip list. Safe to call
+    /// from several threads at once, and while other threads read the list.
+    /// An entry goes before any equal keys already in the list, so one
+    /// thread's duplicates come newest first; equal keys inserted by several
+    /// threads at once come in the order they were linked.
+    pub fn insert(&self, key: &[u8], value: &[u8]) {
+        let new_level = self.random_level();
+        self.level.fetch_max(new_level, atomic::Ordering::Relaxed);
+
+        // The nodes the new node goes between at each of its levels.
+        let mut prev = [self.head; MAX_LEVEL];
+        let mut next = [ptr::null_mut(); MAX_LEVEL];
         let mut current = self.head;
+        for i in (0..self.level()).rev() {
+            let (before, after) = self.find_splice(key, current, i);
+            current = before;
+            prev[i] = before;
+            next[i] = after;
+        }
 
-        unsafe {
-            for i in (0..self.level).rev() {
-                // Comparison is now done directly on `next_node.key`.
-                while let Some(next_node) = (&(*current)).next[i].as_ref() {
-                    // We compare the new key with the key in the next node.
-                    if self.comparator.compare(&next_node.key, &key) == Ordering::Less {
-                        current = next_node as *const _ as *mut _;
-                    } else {
-                        break;
+        let node = Node::new(&self.arena, key, value, new_level);
+        for i in 0..new_level {
+            loop {
+                unsafe { Node::link(node, i).store(next[i], atomic::Ordering::Relaxed) };
+                let link = unsafe { Node::link(prev[i], i) };
+                match link.compare_exchange(
+                    next[i],
+                    node,
+                    atomic::Ordering::Release,
+                    atomic::Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(_) => {
+                        // Another insert got in between; the new node goes
+                        // after it or before it, still after `prev[i]`.
+                        (prev[i], next[i]) = self.find_splice(key, prev[i], i);
                     }
                 }
-                update[i] = current;
             }
+        }
+        self.len.fetch_add(1, atomic::Ordering::Relaxed);
+    }
 
-            let new_level = self.random_level();
-            if new_level > self.level {
-                self.level = new_level;
-            }
-
-            let new_node = Box::into_raw(Box::new(Node::new(key, value, new_level)));
-
-            for i in 0..new_level {
-                (&mut (*new_node)).next[i] = (&(*update[i])).next[i];
-                (&mut (*update[i])).next[i] = new_node;
+    /// Returns the nodes at `level` between which `key` belongs, searching
+    /// forward from `start`, which must sort before `key`. The key goes
+    /// before any equal keys, so the newest of duplicates comes first.
+    fn find_splice(&self, key: &[u8], start: Link, level: usize) -> (Link, Link) {
+        let mut before = start;
+        loop {
+            let after = unsafe { Node::next(before, level) };
+            if !after.is_null()
+                && self.comparator.compare(unsafe { Node::key(after) }, key) == Ordering::Less
+            {
+                before = after;
+            } else {
+                return (before, after);
             }
         }
     }
 
     /// Searches for a key and returns a reference to its value if found.
-    pub fn get(&self, key: &[u8]) -> Option<&Vec<u8>> {
-        let mut current = self.head;
-        unsafe {
-            for i in (0..self.level).rev() {
-                while let Some(next_node) = (&(*current)).next[i].as_ref() {
-                    if self.comparator.compare(&next_node.key, key) == Ordering::Less {
-                        current = next_node as *const _ as *mut _;
-                    } else {
-                        break;
-                    }
-                }
-            }
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        let (_, node) = self.find_last_where(|node| self.compare(node, key) == Ordering::Less);
+        if !node.is_null() && self.compare(unsafe { Node::key(node) }, key) == Ordering::Equal {
+            Some(unsafe { Node::value(node) })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of entries in the list.
+    pub fn len(&self) -> usize {
+        self.len.load(atomic::Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the bytes of memory allocated for the entries of the list.
+    pub fn approximate_memory_usage(&self) -> usize {
+        self.arena.memory_usage()
+    }
 
-            if let Some(next_node) = (&(*current)).next[0].as_ref() {
-                if self.comparator.compare(&next_node.key, key) == Ordering::Equal {
-                    // Return a reference to the value.
-                    return Some(&next_node.val);
+    /// Returns an iterator over the key-value pairs in the skip list.
+    pub fn iter(&self) -> SkipListIterator<&SkipList> {
+        SkipListIterator::new(self)
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        self.comparator.compare(a, b)
+    }
+
+    /// Returns the last node whose key satisfies `before`, or the head if
+    /// there is none, along with the node that followed it. Keys satisfying
+    /// `before` must come first in the list. The link is not loaded again,
+    /// as a concurrent insert may have put a node in between since.
+    fn find_last_where(&self, before: impl Fn(&[u8]) -> bool) -> (Link, Link) {
+        let mut current = self.head;
+        let mut next = ptr::null_mut();
+        for i in (0..self.level()).rev() {
+            loop {
+                next = unsafe { Node::next(current, i) };
+                if next.is_null() || !before(unsafe { Node::key(next) }) {
+                    break;
                 }
+                current = next;
             }
         }
-        None
+        (current, next)
     }
 
-    /// Returns an iterator over the key-value pairs in the skip list.
-    pub fn iter<'a>(&'a self) -> SkipListIterator<'a> {
-        SkipListIterator {
-            current: unsafe { (&(*self.head)).next[0] },
-            _phantom: PhantomData,
+    /// Returns the node of the last entry, or null if the list is empty.
+    fn find_last(&self) -> Link {
+        let (last, _) = self.find_last_where(|_| true);
+        self.null_if_head(last)
+    }
+
+    fn null_if_head(&self, node: Link) -> Link {
+        if node == self.head {
+            ptr::null_mut()
+        } else {
+            node
         }
     }
 }
 
-impl Drop for SkipList {
-    fn drop(&mut self) {
-        unsafe {
-            let mut current = (&(*self.head)).next[0];
-            while !current.is_null() {
-                let node_to_free = Box::from_raw(current);
-                current = node_to_free.next[0];
-            }
-            let _ = Box::from_raw(self.head);
+/// A position in a skip list, which it reaches through `L`: a reference to
+/// the list, or an `Arc` that keeps it alive. Moving forward follows a
+/// link; moving backward searches from the head. Entries inserted while
+/// iterating may or may not be seen.
+pub struct SkipListIterator<L> {
+    list: L,
+    // Null when not positioned at an entry.
+    node: Link,
+}
+
+impl<L: Deref<Target = SkipList>> SkipListIterator<L> {
+    pub fn new(list: L) -> Self {
+        Self {
+            list,
+            node: ptr::null_mut(),
         }
     }
-}
 
-pub struct SkipListIterator<'a> {
-    current: Link,
-    _phantom: PhantomData<&'a Node>,
+    /// Positions at the last entry with a key at or before `target`.
+    pub fn seek_for_prev(&mut self, target: &[u8]) {
+        let list = &*self.list;
+        let (before, _) =
+            list.find_last_where(|node| list.compare(node, target) != Ordering::Greater);
+        self.node = list.null_if_head(before);
+    }
 }
 
-impl<'a> Iterator for SkipListIterator<'a> {
-    type Item = (&'a Key, &'a Value);
+impl<L: Deref<Target = SkipList>> InternalIterator for SkipListIterator<L> {
+    fn valid(&self) -> bool {
+        !self.node.is_null()
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.current.is_null() {
-            None
-        } else {
-            unsafe {
-                let node = &*self.current;
-                self.current = node.next[0];
-                Some((&node.key, &node.val))
-            }
-        }
+    fn key(&self) -> &[u8] {
+        assert!(self.valid());
+        unsafe { Node::key(self.node) }
+    }
+
+    fn value(&self) -> &[u8] {
+        assert!(self.valid());
+        unsafe { Node::value(self.node) }
+    }
+
+    fn seek_to_first(&mut self) {
+        self.node = unsafe { Node::next(self.list.head, 0) };
+    }
+
+    fn seek_to_last(&mut self) {
+        self.node = self.list.find_last();
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        let list = &*self.list;
+        let (_, after) = list.find_last_where(|node| list.compare(node, target) == Ordering::Less);
+        self.node = after;
+    }
+
+    fn next(&mut self) {
+        assert!(self.valid());
+        self.node = unsafe { Node::next(self.node, 0) };
+    }
+
+    /// Moves to the last entry with a key before the current one, skipping
+    /// any entries with an equal key.
+    fn prev(&mut self) {
+        let list = &*self.list;
+        let key = self.key();
+        let (before, _) = list.find_last_where(|node| list.compare(node, key) == Ordering::Less);
+        self.node = list.null_if_head(before);
+    }
+
+    fn status(&self) -> io::Result<()> {
+        Ok(())
     }
 }
 
@@ -164,22 +346,36 @@ impl<'a> Iterator for SkipListIterator<'a> {
 mod tests {
     use super::*;
     use crate::comparator::BytewiseComparator;
+    use std::collections::HashMap;
+    use std::thread;
+
+    /// Returns every entry, in order.
+    fn entries(list: &SkipList) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut iter = list.iter();
+        iter.seek_to_first();
+        let mut entries = Vec::new();
+        while iter.valid() {
+            entries.push((iter.key().to_vec(), iter.value().to_vec()));
+            iter.next();
+        }
+        entries
+    }
 
     #[test]
     fn test_insert_and_get() {
         let comparator = Arc::new(BytewiseComparator::new());
-        let mut list = SkipList::new(comparator);
+        let list = SkipList::new(comparator);
 
-        list.insert(b"apple".to_vec(), b"red".to_vec());
-        list.insert(b"banana".to_vec(), b"yellow".to_vec());
-        list.insert(b"cherry".to_vec(), b"dark red".to_vec());
+        list.insert(b"apple", b"red");
+        list.insert(b"banana", b"yellow");
+        list.insert(b"cherry", b"dark red");
 
         // Test successful gets
         let banana_val = list.get(b"banana").unwrap();
-        assert_eq!(*banana_val, b"yellow".to_vec());
+        assert_eq!(banana_val, b"yellow");
 
         let apple_val = list.get(b"apple").unwrap();
-        assert_eq!(*apple_val, b"red".to_vec());
+        assert_eq!(apple_val, b"red");
 
         // Test unsuccessful get
         assert!(list.get(b"grape").is_none());
@@ -188,14 +384,14 @@ mod tests {
     #[test]
     fn test_iterator_order() {
         let comparator = Arc::new(BytewiseComparator::new());
-        let mut list = SkipList::new(comparator);
+        let list = SkipList::new(comparator);
 
-        list.insert(b"zulu".to_vec(), b"4".to_vec());
-        list.insert(b"alpha".to_vec(), b"1".to_vec());
-        list.insert(b"bravo".to_vec(), b"2".to_vec());
-        list.insert(b"x-ray".to_vec(), b"3".to_vec());
+        list.insert(b"zulu", b"4");
+        list.insert(b"alpha", b"1");
+        list.insert(b"bravo", b"2");
+        list.insert(b"x-ray", b"3");
 
-        let results: Vec<_> = list.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        let results = entries(&list);
 
         assert_eq!(results.len(), 4);
         assert_eq!(results[0], (b"alpha".to_vec(), b"1".to_vec()));
@@ -207,13 +403,13 @@ mod tests {
     #[test]
     fn test_duplicates_are_inserted() {
         let comparator = Arc::new(BytewiseComparator::new());
-        let mut list = SkipList::new(comparator);
+        let list = SkipList::new(comparator);
 
         // Insert two duplicates: the later insert should come first among equals.
-        list.insert(b"key1".to_vec(), b"value2".to_vec());
-        list.insert(b"key1".to_vec(), b"value1".to_vec());
+        list.insert(b"key1", b"value2");
+        list.insert(b"key1", b"value1");
 
-        let collected: Vec<_> = list.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        let collected = entries(&list);
         assert_eq!(collected.len(), 2);
 
         // Among equal keys, iteration shows newest first (LIFO for duplicates).
@@ -222,7 +418,7 @@ mod tests {
 
         // get() returns the most recent value for the key (last-writer-wins).
         let val = list.get(b"key1").unwrap();
-        assert_eq!(*val, b"value1".to_vec());
+        assert_eq!(val, b"value1");
     }
 
     #[test]
@@ -231,7 +427,96 @@ mod tests {
         let list = SkipList::new(comparator);
 
         assert!(list.get(b"any_key").is_none());
-        assert_eq!(list.iter().count(), 0);
+        assert!(entries(&list).is_empty());
+        assert!(list.is_empty());
+        let mut iter = list.iter();
+        iter.seek_to_last();
+        assert!(!iter.valid());
+        iter.seek(b"any_key");
+        assert!(!iter.valid());
+        iter.seek_for_prev(b"any_key");
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn test_neighbour_lookups() {
+        let comparator = Arc::new(BytewiseComparator::new());
+        let list = SkipList::new(comparator);
+        for key in [b"b", b"d", b"f"] {
+            list.insert(key, &[]);
+        }
+        assert_eq!(list.len(), 3);
+
+        let mut iter = list.iter();
+        let mut key = |position: &dyn Fn(&mut SkipListIterator<&SkipList>)| {
+            position(&mut iter);
+            iter.valid().then(|| iter.key().to_vec())
+        };
+        assert_eq!(key(&|iter| iter.seek_to_first()), Some(b"b".to_vec()));
+        assert_eq!(key(&|iter| iter.seek_to_last()), Some(b"f".to_vec()));
+        assert_eq!(key(&|iter| iter.seek(b"a")), Some(b"b".to_vec()));
+        assert_eq!(key(&|iter| iter.seek(b"d")), Some(b"d".to_vec()));
+        assert_eq!(key(&|iter| iter.seek(b"e")), Some(b"f".to_vec()));
+        assert_eq!(key(&|iter| iter.seek(b"g")), None);
+        assert_eq!(key(&|iter| iter.seek_for_prev(b"a")), None);
+        assert_eq!(key(&|iter| iter.seek_for_prev(b"d")), Some(b"d".to_vec()));
+        assert_eq!(key(&|iter| iter.seek_for_prev(b"e")), Some(b"d".to_vec()));
+        assert_eq!(key(&|iter| iter.seek_for_prev(b"g")), Some(b"f".to_vec()));
+
+        // Step both ways from the middle.
+        assert_eq!(key(&|iter| iter.seek(b"c")), Some(b"d".to_vec()));
+        assert_eq!(key(&|iter| iter.next()), Some(b"f".to_vec()));
+        assert_eq!(key(&|iter| iter.next()), None);
+        assert_eq!(key(&|iter| iter.seek(b"e")), Some(b"f".to_vec()));
+        assert_eq!(key(&|iter| iter.prev()), Some(b"d".to_vec()));
+        assert_eq!(key(&|iter| iter.prev()), Some(b"b".to_vec()));
+        assert_eq!(key(&|iter| iter.prev()), None);
+    }
+
+    #[test]
+    fn test_iterator_steps_across_duplicates() {
+        let comparator = Arc::new(BytewiseComparator::new());
+        let list = SkipList::new(comparator);
+        list.insert(b"b", b"0");
+        for value in [b"1", b"2", b"3"] {
+            list.insert(b"d", value);
+        }
+        list.insert(b"f", b"4");
+
+        // Duplicates come newest first: b0 d3 d2 d1 f4.
+        let mut iter = list.iter();
+        let mut value = |position: &dyn Fn(&mut SkipListIterator<&SkipList>)| {
+            position(&mut iter);
+            iter.valid().then(|| iter.value().to_vec())
+        };
+        assert_eq!(value(&|iter| iter.seek(b"d")), Some(b"3".to_vec()));
+        assert_eq!(value(&|iter| iter.next()), Some(b"2".to_vec()));
+        // Moving back skips the rest of the equal keys.
+        assert_eq!(value(&|iter| iter.prev()), Some(b"0".to_vec()));
+        assert_eq!(value(&|iter| iter.seek_to_last()), Some(b"4".to_vec()));
+        assert_eq!(value(&|iter| iter.prev()), Some(b"1".to_vec()));
+        assert_eq!(value(&|iter| iter.prev()), Some(b"0".to_vec()));
+
+        // Seeking for a previous key lands on the oldest of the duplicates.
+        assert_eq!(value(&|iter| iter.seek_for_prev(b"e")), Some(b"1".to_vec()));
+        assert_eq!(value(&|iter| iter.seek_for_prev(b"c")), Some(b"0".to_vec()));
+        assert_eq!(value(&|iter| iter.seek_for_prev(b"d")), Some(b"1".to_vec()));
+        assert_eq!(value(&|iter| iter.next()), Some(b"4".to_vec()));
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let comparator = Arc::new(BytewiseComparator::new());
+        let list = SkipList::new(comparator);
+        let empty = list.approximate_memory_usage();
+        assert!(empty > 0);
+        for i in 0..100u8 {
+            list.insert(&[i], &[0; 100]);
+        }
+        // Every entry takes at least its key, value and one link.
+        let used = list.approximate_memory_usage() - empty;
+        assert!(used >= 100 * (101 + 8));
+        assert!(used < 100 * (101 + 8 * MAX_LEVEL + 64) + 4096);
     }
 
     #[test]
@@ -239,12 +524,212 @@ mod tests {
         // This test simply creates and drops a list with many items.
         // Running this with a tool like `valgrind` should confirm no memory is leaked.
         let comparator = Arc::new(BytewiseComparator::new());
-        let mut list = SkipList::new(comparator);
+        let list = SkipList::new(comparator);
         for i in 0..1000 {
             let key = format!("key{}", i);
             let val = format!("val{}", i);
-            list.insert(key.into_bytes(), val.into_bytes());
+            list.insert(key.as_bytes(), val.as_bytes());
         }
         // Drop
     }
+
+    #[test]
+    fn test_concurrent_inserts() {
+        let list = Arc::new(SkipList::new(Arc::new(BytewiseComparator::new())));
+        let writers: Vec<_> = (0..4)
+            .map(|t| {
+                let list = list.clone();
+                thread::spawn(move || {
+                    for i in 0..2000 {
+                        list.insert(format!("{:05}", i * 4 + t).as_bytes(), &[t as u8]);
+                    }
+                })
+            })
+            .collect();
+        // Readers always see the list in order, never shrinking.
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let list = list.clone();
+                thread::spawn(move || {
+                    let mut seen = 0;
+                    while seen < 8000 {
+                        let keys: Vec<_> = entries(&list).into_iter().map(|(key, _)| key).collect();
+                        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+                        assert!(keys.len() >= seen);
+                        seen = keys.len();
+                    }
+                })
+            })
+            .collect();
+        for thread in writers.into_iter().chain(readers) {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(list.len(), 8000);
+        for (i, (key, value)) in entries(&list).into_iter().enumerate() {
+            assert_eq!(key, format!("{i:05}").as_bytes());
+            assert_eq!(value, [(i % 4) as u8]);
+        }
+    }
+
+    #[test]
+    fn test_concurrent_inserts_of_equal_keys() {
+        const WRITERS: u8 = 4;
+        const ROUNDS: u8 = 50;
+        const KEYS: usize = 10;
+        const TOTAL: usize = WRITERS as usize * ROUNDS as usize * KEYS;
+        // Each value is its writer and round. Keys never go backwards, and
+        // each writer's entries for a key come newest first.
+        fn check(entries: &[(Vec<u8>, Vec<u8>)]) {
+            assert!(entries.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+            let mut last_round = HashMap::new();
+            for (key, value) in entries {
+                if let Some(round) = last_round.insert((key, value[0]), value[1]) {
+                    assert!(round > value[1]);
+                }
+            }
+        }
+
+        let list = Arc::new(SkipList::new(Arc::new(BytewiseComparator::new())));
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|t| {
+                let list = list.clone();
+                thread::spawn(move || {
+                    for round in 0..ROUNDS {
+                        for k in 0..KEYS {
+                            list.insert(format!("{k:02}").as_bytes(), &[t, round]);
+                        }
+                    }
+                })
+            })
+            .collect();
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let list = list.clone();
+                thread::spawn(move || {
+                    let mut seen = 0;
+                    while seen < TOTAL {
+                        let entries = entries(&list);
+                        check(&entries);
+                        assert!(entries.len() >= seen);
+                        seen = entries.len();
+                    }
+                })
+            })
+            .collect();
+        for thread in writers.into_iter().chain(readers) {
+            thread.join().unwrap();
+        }
+
+        let entries = entries(&list);
+        assert_eq!(entries.len(), TOTAL);
+        check(&entries);
+        for k in 0..KEYS {
+            let key = format!("{k:02}").into_bytes();
+            let count = entries.iter().filter(|(other, _)| *other == key).count();
+            assert_eq!(count, TOTAL / KEYS);
+            // The newest entry is the last round of some writer.
+            assert_eq!(list.get(&key).unwrap()[1], ROUNDS - 1);
+        }
+    }
+
+    #[test]
+    fn test_iterators_during_concurrent_inserts() {
+        const COUNT: usize = 4000;
+        let key = |i: usize| format!("{:05}", i * 7919 % COUNT).into_bytes();
+        let list = Arc::new(SkipList::new(Arc::new(BytewiseComparator::new())));
+        let writers: Vec<_> = (0..2)
+            .map(|t| {
+                let list = list.clone();
+                thread::spawn(move || {
+                    for i in (t..COUNT).step_by(2) {
+                        list.insert(&key(i), &[]);
+                    }
+                })
+            })
+            .collect();
+        // Every step lands on a key in the right direction, and stepping
+        // back after stepping forward never goes past the starting key.
+        let readers: Vec<_> = (0..2)
+            .map(|r| {
+                let list = list.clone();
+                thread::spawn(move || {
+                    let mut i = r;
+                    while list.len() < COUNT {
+                        let target = key(i % COUNT);
+                        i += 31;
+                        let mut iter = list.iter();
+                        iter.seek_for_prev(&target);
+                        if iter.valid() {
+                            assert!(iter.key() <= &target[..]);
+                        }
+                        iter.seek(&target);
+                        if !iter.valid() {
+                            continue;
+                        }
+                        let start = iter.key().to_vec();
+                        assert!(start >= target);
+                        iter.next();
+                        if !iter.valid() {
+                            continue;
+                        }
+                        let after = iter.key().to_vec();
+                        assert!(after > start);
+                        iter.prev();
+                        let back = iter.key().to_vec();
+                        assert!(back >= start && back < after);
+                        iter.prev();
+                        if iter.valid() {
+                            assert!(iter.key() < &back[..]);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in writers.into_iter().chain(readers) {
+            thread.join().unwrap();
+        }
+        let keys: Vec<_> = entries(&list).into_iter().map(|(key, _)| key).collect();
+        assert_eq!(
+            keys,
+            (0..COUNT)
+                .map(|i| format!("{i:05}").into_bytes())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_readers_see_completed_inserts() {
+        const COUNT: usize = 5000;
+        // Keys are inserted out of order.
+        let key = |i: usize| format!("{:05}", i * 7919 % COUNT).into_bytes();
+        let list = Arc::new(SkipList::new(Arc::new(BytewiseComparator::new())));
+        let inserted = Arc::new(AtomicUsize::new(0));
+        let readers: Vec<_> = (0..3)
+            .map(|r| {
+                let list = list.clone();
+                let inserted = inserted.clone();
+                thread::spawn(move || loop {
+                    let done = inserted.load(atomic::Ordering::Acquire);
+                    for i in (r..done).step_by(97) {
+                        assert_eq!(list.get(&key(i)), Some(&key(i)[..]));
+                        let mut iter = list.iter();
+                        iter.seek_for_prev(&key(i));
+                        assert_eq!(iter.key(), key(i));
+                    }
+                    if done == COUNT {
+                        break;
+                    }
+                })
+            })
+            .collect();
+        for i in 0..COUNT {
+            list.insert(&key(i), &key(i));
+            inserted.store(i + 1, atomic::Ordering::Release);
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(entries(&list).len(), COUNT);
+    }
 }
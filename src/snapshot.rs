@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::common::SequenceNumber;
+
+/// A consistent, read-only view of a database at the moment it was taken.
+/// Reads through a snapshot ignore every write made after it. The versions
+/// a snapshot needs are kept by compaction until the snapshot is dropped.
+pub struct Snapshot {
+    sequence: SequenceNumber,
+    list: Arc<SnapshotList>,
+}
+
+impl Snapshot {
+    /// Returns the sequence number of the last write the snapshot sees.
+    pub fn sequence(&self) -> SequenceNumber {
+        self.sequence
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.list.release(self.sequence);
+    }
+}
+
+/// Tracks the sequence numbers pinned by live snapshots.
+#[derive(Default)]
+pub struct SnapshotList {
+    // Number of live snapshots at each sequence number.
+    sequences: Mutex<BTreeMap<SequenceNumber, usize>>,
+}
+
+impl SnapshotList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pins `sequence` until the returned snapshot is dropped.
+    pub fn acquire(self: &Arc<Self>, sequence: SequenceNumber) -> Snapshot {
+        *self.sequences.lock().unwrap().entry(sequence).or_insert(0) += 1;
+        Snapshot {
+            sequence,
+            list: self.clone(),
+        }
+    }
+
+    /// Returns the sequence number of the oldest live snapshot.
+    pub fn oldest(&self) -> Option<SequenceNumber> {
+        self.sequences.lock().unwrap().keys().next().copied()
+    }
+
+    fn release(&self, sequence: SequenceNumber) {
+        let mut sequences = self.sequences.lock().unwrap();
+        let count = sequences.get_mut(&sequence).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            sequences.remove(&sequence);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oldest_live_snapshot() {
+        let list = Arc::new(SnapshotList::new());
+        assert_eq!(list.oldest(), None);
+
+        let first = list.acquire(5);
+        let second = list.acquire(5);
+        let third = list.acquire(9);
+        assert_eq!(list.oldest(), Some(5));
+        assert_eq!(third.sequence(), 9);
+
+        drop(first);
+        assert_eq!(list.oldest(), Some(5));
+        drop(second);
+        assert_eq!(list.oldest(), Some(9));
+        drop(third);
+        assert_eq!(list.oldest(), None);
+    }
+}
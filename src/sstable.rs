@@ -24,157 +24,181 @@
 // | "name" -> "Alice"|         | "age" -> Tombstone|
 // +------------------+         +------------------+
 
-use std::collections::BTreeMap;
+use std::cmp::Ordering;
 use std::fs::File;
-use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::io::{self, BufWriter};
 use std::path::{Path, PathBuf};
-
-use crate::bloom_filter::BloomFilter;
-use crate::common::{Entry, Key, SequenceNumber, Value};
-use crate::wal::read_bytes;
-
+use std::sync::Arc;
+
+use crate::common::SequenceNumber;
+use crate::comparator::Comparator;
+use crate::dbformat::{
+    extract_user_key, parse_internal_key, InternalKey, InternalKeyComparator, LookupResult,
+    VALUE_TYPE_FOR_SEEK,
+};
+use crate::iterator::InternalIterator;
+use crate::options::Options;
+use crate::table_reader::{TableIter, TableReader};
+use crate::table_writer::TableBuilder;
+
+/// A table of the database. Keys are internal keys, so every version of a
+/// user key and every tombstone is kept; the bloom filter holds user keys.
 pub struct SSTable {
     pub file_path: PathBuf,
-    pub index: BTreeMap<Key, u64>, // Key to file offset
-    bloom_filter: BloomFilter,
+    reader: Arc<TableReader>,
+    // Orders the user keys within the internal keys.
+    comparator: Arc<dyn Comparator>,
 }
 
 impl SSTable {
     pub fn new<P: AsRef<Path>>(
         file_path: P,
-        index: BTreeMap<Key, u64>,
-        bloom_filter: BloomFilter,
+        reader: TableReader,
+        comparator: Arc<dyn Comparator>,
     ) -> Self {
         Self {
             file_path: file_path.as_ref().to_path_buf(),
-            index,
-            bloom_filter,
+            reader: Arc::new(reader),
+            comparator,
         }
     }
 
+    /// Writes every entry of `iter`, such as a flushed memtable, to a new
+    /// table.
     pub fn write<P>(
-        data: BTreeMap<Key, (Value, SequenceNumber)>,
-        tombstones: BTreeMap<Key, SequenceNumber>,
+        iter: &mut dyn InternalIterator,
         file_path: &P,
-    ) -> std::io::Result<SSTable>
+        options: &Options,
+    ) -> io::Result<SSTable>
     where
         P: AsRef<Path> + ?Sized,
     {
-        let mut file = BufWriter::new(File::create(file_path)?);
-        let mut index = BTreeMap::new();
-        let mut bloom_filter = BloomFilter::default();
-
-        for (key, (value, sequence_number)) in data {
-            let offset = file.seek(SeekFrom::Current(0))?;
-            // Serialize entry
-            write_entry(&mut file, 0, &key, Some(&value), sequence_number)?;
-            index.insert(key.clone(), offset);
-            bloom_filter.add(&key);
+        let mut builder = SSTable::builder(file_path, options)?;
+        iter.seek_to_first();
+        while iter.valid() {
+            builder.add(iter.key(), iter.value())?;
+            iter.next();
         }
+        iter.status()?;
+        builder.finish()?;
 
-        for (key, sequence_number) in tombstones {
-            let offset = file.seek(SeekFrom::Current(0))?;
-            write_entry(&mut file, 1, &key, None, sequence_number)?;
-            index.insert(key.clone(), offset);
-            bloom_filter.add(&key);
-        }
+        SSTable::open(file_path, options)
+    }
 
-        Ok(SSTable::new(file_path, index, bloom_filter))
+    /// Creates a table file and returns a builder that takes internal keys.
+    pub fn builder<P>(file_path: &P, options: &Options) -> io::Result<TableBuilder<BufWriter<File>>>
+    where
+        P: AsRef<Path> + ?Sized,
+    {
+        let file = BufWriter::new(File::create(file_path)?);
+        Ok(TableBuilder::new(file, &table_options(options)).with_filter_key(extract_user_key))
     }
 
-    pub fn get(&self, key: &Key) -> std::io::Result<Option<(Value, SequenceNumber)>> {
-        if !self.bloom_filter.contains(key) {
-            return Ok(None);
-        }
+    /// Opens a table file, reading its index and bloom filter back from disk.
+    pub fn open<P: AsRef<Path>>(file_path: P, options: &Options) -> io::Result<SSTable> {
+        let reader = TableReader::open(&file_path, &table_options(options))?;
+        Ok(SSTable::new(file_path, reader, options.comparator.clone()))
+    }
 
-        if let Some(&offset) = self.index.get(key) {
-            let mut file = File::open(&self.file_path)?;
-            file.seek(SeekFrom::Start(offset))?;
-            read_entry(&mut file).map(|entry| match entry {
-                Entry::Put { key: _, value } => Some((value, 0)), // Sequence number can be stored if needed
-                Entry::Delete { .. } => None,
-            })
-        } else {
-            Ok(None)
-        }
+    /// Returns an iterator over every entry of the table, keyed by internal
+    /// key.
+    pub fn iter(&self) -> TableIter {
+        self.reader.iter()
     }
-}
 
-pub fn write_entry<W: Write>(
-    writer: &mut W,
-    entry_type: u8,
-    key: &Key,
-    value: Option<&Value>,
-    sequence_number: SequenceNumber,
-) -> std::io::Result<()> {
-    writer.write_all(&[entry_type])?;
-    writer.write_all(&(sequence_number.to_be_bytes()))?;
-    writer.write_all(&(key.len() as u32).to_be_bytes())?;
-    writer.write_all(key)?;
-    if let Some(value) = value {
-        writer.write_all(&(value.len() as u32).to_be_bytes())?;
-        writer.write_all(value)?;
+    /// Looks up the newest write to `key` with a sequence number at or below
+    /// `sequence`.
+    pub fn get(&self, key: &[u8], sequence: SequenceNumber) -> io::Result<LookupResult> {
+        if !self.reader.may_contain(key) {
+            return Ok(LookupResult::NotFound);
+        }
+        let lookup = InternalKey::new(key, sequence, VALUE_TYPE_FOR_SEEK);
+        let (found, value) = match self.reader.seek(lookup.encode())? {
+            Some(entry) => entry,
+            None => return Ok(LookupResult::NotFound),
+        };
+        let parsed = parse_internal_key(&found)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad internal key"))?;
+        if self.comparator.compare(parsed.user_key, key) != Ordering::Equal {
+            return Ok(LookupResult::NotFound);
+        }
+        Ok(LookupResult::from_entry(parsed.value_type, value))
     }
-    Ok(())
 }
 
-pub fn read_entry<R: Read>(reader: &mut R) -> std::io::Result<Entry> {
-    let mut type_byte = [0u8; 1];
-    reader.read_exact(&mut type_byte)?;
-    let mut seq_bytes = [0u8; 8];
-    reader.read_exact(&mut seq_bytes)?;
-    //let sequence_number = u64::from_be_bytes(seq_bytes);
-    let key = read_bytes(reader).ok_or(std::io::Error::new(
-        std::io::ErrorKind::InvalidData,
-        "Invalid key",
-    ))?;
-    match type_byte[0] {
-        0 => {
-            let value = read_bytes(reader).ok_or(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Invalid value",
-            ))?;
-            Ok(Entry::Put { key, value })
-        }
-        1 => Ok(Entry::Delete { key }),
-        _ => Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "Invalid entry type",
-        )),
+/// Returns `options` with the comparator replaced by one for internal keys.
+fn table_options(options: &Options) -> Options {
+    Options {
+        comparator: Arc::new(InternalKeyComparator::new(options.comparator.clone())),
+        ..options.clone()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::dbformat::{ValueType, MAX_SEQUENCE_NUMBER};
+    use crate::memtable::{MemTable, MemTableIterator};
     use std::path::Path;
 
+    fn entries() -> MemTableIterator {
+        let memtable = Arc::new(MemTable::new(100));
+        for (key, sequence, value_type, value) in [
+            (&b"key1"[..], 1, ValueType::Value, &b"value1"[..]),
+            (b"key2", 2, ValueType::Value, b"value2"),
+            (b"key3", 3, ValueType::Deletion, b""),
+            (b"key2", 4, ValueType::Value, b"value2b"),
+        ] {
+            memtable.add(sequence, value_type, key, value);
+        }
+        memtable.iter()
+    }
+
     #[test]
     fn test_sstable_write_and_get() -> std::io::Result<()> {
         let file_path = Path::new("/tmp/sstable.db");
+        let sstable = SSTable::write(&mut entries(), file_path, &Options::default())?;
 
-        let mut data = BTreeMap::new();
-        data.insert(b"key1".to_vec(), (b"value1".to_vec(), 1));
-        data.insert(b"key2".to_vec(), (b"value2".to_vec(), 2));
-
-        let mut tombstones = BTreeMap::new();
-        tombstones.insert(b"key3".to_vec(), 3);
-
-        let sstable = SSTable::write(data.clone(), tombstones.clone(), file_path)?;
         // Test existing keys
-        let retrieved = sstable.get(&b"key1".to_vec())?;
-        assert_eq!(retrieved, Some((b"value1".to_vec(), 0)));
+        let retrieved = sstable.get(b"key1", MAX_SEQUENCE_NUMBER)?;
+        assert_eq!(retrieved, LookupResult::Found(b"value1".to_vec()));
+
+        let retrieved = sstable.get(b"key2", MAX_SEQUENCE_NUMBER)?;
+        assert_eq!(retrieved, LookupResult::Found(b"value2b".to_vec()));
 
-        let retrieved = sstable.get(&b"key2".to_vec())?;
-        assert_eq!(retrieved, Some((b"value2".to_vec(), 0)));
+        // Older versions stay readable at older sequence numbers
+        let retrieved = sstable.get(b"key2", 3)?;
+        assert_eq!(retrieved, LookupResult::Found(b"value2".to_vec()));
+        assert_eq!(sstable.get(b"key2", 1)?, LookupResult::NotFound);
 
         // Test tombstone key
-        let retrieved = sstable.get(&b"key3".to_vec())?;
-        assert_eq!(retrieved, None);
+        let retrieved = sstable.get(b"key3", MAX_SEQUENCE_NUMBER)?;
+        assert_eq!(retrieved, LookupResult::Deleted);
 
         // Test non-existing key
-        let retrieved = sstable.get(&b"key4".to_vec())?;
-        assert_eq!(retrieved, None);
+        let retrieved = sstable.get(b"key4", MAX_SEQUENCE_NUMBER)?;
+        assert_eq!(retrieved, LookupResult::NotFound);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sstable_open() -> std::io::Result<()> {
+        let file_path = Path::new("/tmp/sstable_load.db");
+        SSTable::write(&mut entries(), file_path, &Options::default())?;
+
+        // A copied table carries its own index and filter.
+        let copy_path = Path::new("/tmp/sstable_load_copy.db");
+        std::fs::copy(file_path, copy_path)?;
+        let loaded = SSTable::open(copy_path, &Options::default())?;
+        assert_eq!(loaded.file_path, copy_path);
+        assert_eq!(
+            loaded.get(b"key1", MAX_SEQUENCE_NUMBER)?,
+            LookupResult::Found(b"value1".to_vec())
+        );
+        assert_eq!(
+            loaded.get(b"key3", MAX_SEQUENCE_NUMBER)?,
+            LookupResult::Deleted
+        );
         Ok(())
     }
 }
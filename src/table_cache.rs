@@ -0,0 +1,155 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::filename;
+use crate::options::Options;
+use crate::sstable::SSTable;
+
+/// Keeps the tables of a database open, keyed by file number. At most
+/// `Options::max_open_files` stay open; the least recently used table is
+/// closed to make room, once no iterator still holds it.
+pub struct TableCache {
+    dbname: PathBuf,
+    options: Options,
+    tables: Mutex<OpenTables>,
+}
+
+/// The open tables, with the order they were last used in.
+#[derive(Default)]
+struct OpenTables {
+    // Each table with the tick of its last use.
+    tables: HashMap<u64, (Arc<SSTable>, u64)>,
+    // File numbers by the tick of their last use, least recent first.
+    by_last_use: BTreeMap<u64, u64>,
+    clock: u64,
+}
+
+impl OpenTables {
+    fn get(&mut self, number: u64) -> Option<Arc<SSTable>> {
+        self.clock += 1;
+        let (table, last_use) = self.tables.get_mut(&number)?;
+        self.by_last_use.remove(last_use);
+        *last_use = self.clock;
+        self.by_last_use.insert(self.clock, number);
+        Some(table.clone())
+    }
+
+    /// Adds `table`, first closing the least recently used tables so no
+    /// more than `capacity` are open.
+    fn insert(&mut self, number: u64, table: Arc<SSTable>, capacity: usize) {
+        self.remove(number);
+        while self.tables.len() >= capacity {
+            let Some((_, oldest)) = self.by_last_use.pop_first() else {
+                break;
+            };
+            self.tables.remove(&oldest);
+        }
+        self.clock += 1;
+        self.tables.insert(number, (table, self.clock));
+        self.by_last_use.insert(self.clock, number);
+    }
+
+    fn remove(&mut self, number: u64) {
+        if let Some((_, last_use)) = self.tables.remove(&number) {
+            self.by_last_use.remove(&last_use);
+        }
+    }
+}
+
+impl TableCache {
+    pub fn new(dbname: &Path, options: Options) -> Self {
+        Self {
+            dbname: dbname.to_path_buf(),
+            options,
+            tables: Mutex::new(OpenTables::default()),
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.options.max_open_files.max(1)
+    }
+
+    /// Returns table `number`, loading it from disk if it is not open.
+    pub fn find_table(&self, number: u64) -> io::Result<Arc<SSTable>> {
+        if let Some(table) = self.tables.lock().unwrap().get(number) {
+            return Ok(table);
+        }
+        let path = filename::table_file_name(&self.dbname, number);
+        let table = Arc::new(SSTable::open(path, &self.options)?);
+        let mut tables = self.tables.lock().unwrap();
+        // Another thread may have loaded it in the meantime.
+        if let Some(table) = tables.get(number) {
+            return Ok(table);
+        }
+        tables.insert(number, table.clone(), self.capacity());
+        Ok(table)
+    }
+
+    /// Registers a table that was just written, so it is not loaded again.
+    pub fn insert(&self, number: u64, table: SSTable) {
+        self.tables
+            .lock()
+            .unwrap()
+            .insert(number, Arc::new(table), self.capacity());
+    }
+
+    /// Drops table `number`, which is no longer part of the database.
+    pub fn evict(&self, number: u64) {
+        self.tables.lock().unwrap().remove(number);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbformat::{InternalKey, ValueType};
+    use std::fs;
+
+    fn open_numbers(cache: &TableCache) -> Vec<u64> {
+        let tables = cache.tables.lock().unwrap();
+        tables.by_last_use.values().copied().collect()
+    }
+
+    #[test]
+    fn closes_the_least_recently_used_table() -> io::Result<()> {
+        let dbname = std::env::temp_dir().join("saturn_table_cache_lru");
+        let _ = fs::remove_dir_all(&dbname);
+        fs::create_dir_all(&dbname)?;
+        let options = Options {
+            max_open_files: 2,
+            ..Options::default()
+        };
+        for number in 1..=3 {
+            let path = filename::table_file_name(&dbname, number);
+            let mut builder = SSTable::builder(&path, &options)?;
+            let key = InternalKey::new(format!("k{number}").as_bytes(), 1, ValueType::Value);
+            builder.add(key.encode(), b"v")?;
+            builder.finish()?;
+        }
+
+        let cache = TableCache::new(&dbname, options);
+        cache.find_table(1)?;
+        cache.find_table(2)?;
+        cache.find_table(1)?;
+        assert_eq!(open_numbers(&cache), [2, 1]);
+        cache.find_table(3)?;
+        assert_eq!(open_numbers(&cache), [1, 3]);
+
+        // An open table is read without its file; a closed one is opened
+        // again from it.
+        fs::remove_file(filename::table_file_name(&dbname, 1))?;
+        cache.find_table(1)?;
+        cache.find_table(2)?;
+        assert_eq!(open_numbers(&cache), [1, 2]);
+        cache.find_table(3)?;
+        let err = cache.find_table(1).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(open_numbers(&cache), [2, 3]);
+
+        cache.evict(2);
+        assert_eq!(open_numbers(&cache), [3]);
+        Ok(())
+    }
+}
@@ -0,0 +1,515 @@
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::block::{Block, BlockIter};
+use crate::bloom_filter::BloomFilter;
+use crate::common::SegmentHandle;
+use crate::comparator::{BytewiseComparator, Comparator};
+use crate::crc::crc32c;
+use crate::iterator::InternalIterator;
+use crate::options::Options;
+use crate::table_writer::{
+    TableFooter, BLOCK_TRAILER_SIZE, COMPARATOR_PROPERTY, FILTER_BLOCK_NAME, FOOTER_SIZE,
+    PROPERTIES_BLOCK_NAME,
+};
+
+/// Reads a table written by `TableBuilder`. Only the index block and the
+/// bloom filter are kept in memory; data blocks are read from the file as
+/// needed, with positional reads so concurrent lookups do not wait on each
+/// other.
+pub struct TableReader {
+    file: File,
+    comparator: Arc<dyn Comparator>,
+    index_block: Block,
+    filter: Option<BloomFilter>,
+}
+
+impl TableReader {
+    /// Opens the table at `path`. Fails if it was written with a comparator
+    /// other than the one in `options`.
+    pub fn open<P: AsRef<Path>>(path: P, options: &Options) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        if size < FOOTER_SIZE as u64 {
+            return Err(corruption("file is too short to be a table"));
+        }
+        let mut footer = [0u8; FOOTER_SIZE];
+        read_exact_at(&file, &mut footer, size - FOOTER_SIZE as u64)?;
+        let footer = TableFooter::decode(&footer)?;
+        let index_block = read_block(&file, &footer.index)?;
+        let metaindex = read_block(&file, &footer.metaindex)?;
+        check_comparator(&file, &metaindex, options.comparator.as_ref())?;
+        let filter = read_filter(&file, &metaindex)?;
+        Ok(Self {
+            file,
+            comparator: options.comparator.clone(),
+            index_block,
+            filter,
+        })
+    }
+
+    /// Returns the value stored under exactly `key`, if any.
+    pub fn get(&self, key: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        if !self.may_contain(key) {
+            return Ok(None);
+        }
+        match self.seek(key)? {
+            Some((found, value)) if self.comparator.compare(&found, key) == Ordering::Equal => {
+                Ok(Some(value))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the first entry with a key at or after `target`, if any. The
+    /// bloom filter is not consulted.
+    pub fn seek(&self, target: &[u8]) -> io::Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let mut index_iter = self.index_block.iter(self.comparator.clone());
+        index_iter.seek(target);
+        while index_iter.valid() {
+            let mut block_iter = self.block_iter(index_iter.value())?;
+            block_iter.seek(target);
+            if block_iter.valid() {
+                return Ok(Some((
+                    block_iter.key().to_vec(),
+                    block_iter.value().to_vec(),
+                )));
+            }
+            check(&block_iter)?;
+            index_iter.next();
+        }
+        check(&index_iter).map(|_| None)
+    }
+
+    /// Returns false if the table's bloom filter rules out `key`.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.filter
+            .as_ref()
+            .is_none_or(|filter| filter.contains(key))
+    }
+
+    pub fn iter(self: &Arc<Self>) -> TableIter {
+        TableIter {
+            table: self.clone(),
+            index_iter: self.index_block.iter(self.comparator.clone()),
+            data_iter: None,
+            error: None,
+        }
+    }
+
+    fn block_iter(&self, encoded_handle: &[u8]) -> io::Result<BlockIter> {
+        let (handle, _) = SegmentHandle::decode(encoded_handle)
+            .ok_or_else(|| corruption("bad block handle in index"))?;
+        let block = read_block(&self.file, &handle)?;
+        Ok(block.iter(self.comparator.clone()))
+    }
+}
+
+fn read_block(file: &File, handle: &SegmentHandle) -> io::Result<Block> {
+    Block::new(read_raw_block(file, handle)?)
+}
+
+/// Reads the contents of a block and verifies its checksum.
+fn read_raw_block(file: &File, handle: &SegmentHandle) -> io::Result<Vec<u8>> {
+    let mut data = vec![0u8; handle.length() + BLOCK_TRAILER_SIZE];
+    read_exact_at(file, &mut data, handle.offset() as u64)?;
+    let trailer = data.split_off(handle.length());
+    let expected = crc32c::unmask(crc32c::get_fixed32_le(&trailer));
+    if crc32c::value(&data) != expected {
+        return Err(corruption("block checksum mismatch"));
+    }
+    Ok(data)
+}
+
+/// Fills `buf` from `offset` in `file` without going through the file's
+/// cursor, so reads from several threads need no lock.
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+/// Fills `buf` from `offset` in `file`. `seek_read` moves the cursor, but
+/// every read gives its own offset, so reads from several threads need no
+/// lock.
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "failed to fill whole buffer",
+                ))
+            }
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Returns the handle of the meta block named `name` in the metaindex
+/// block, if the table has one.
+fn find_meta_block(metaindex: &Block, name: &[u8]) -> io::Result<Option<SegmentHandle>> {
+    let mut iter = metaindex.iter(Arc::new(BytewiseComparator::new()));
+    iter.seek(name);
+    if !iter.valid() || iter.key() != name {
+        return check(&iter).map(|_| None);
+    }
+    let (handle, _) =
+        SegmentHandle::decode(iter.value()).ok_or_else(|| corruption("bad meta block handle"))?;
+    Ok(Some(handle))
+}
+
+/// Reads the bloom filter named in the metaindex block, if the table has one.
+fn read_filter(file: &File, metaindex: &Block) -> io::Result<Option<BloomFilter>> {
+    let Some(handle) = find_meta_block(metaindex, FILTER_BLOCK_NAME)? else {
+        return Ok(None);
+    };
+    let block = read_raw_block(file, &handle)?;
+    BloomFilter::decode(&block)
+        .map(Some)
+        .ok_or_else(|| corruption("bad filter block"))
+}
+
+/// Fails if the table records a comparator other than `comparator`. Tables
+/// without properties predate them and are taken to match.
+fn check_comparator(file: &File, metaindex: &Block, comparator: &dyn Comparator) -> io::Result<()> {
+    let Some(handle) = find_meta_block(metaindex, PROPERTIES_BLOCK_NAME)? else {
+        return Ok(());
+    };
+    let properties = read_block(file, &handle)?;
+    let mut iter = properties.iter(Arc::new(BytewiseComparator::new()));
+    iter.seek(COMPARATOR_PROPERTY);
+    if !iter.valid() || iter.key() != COMPARATOR_PROPERTY {
+        return check(&iter);
+    }
+    if iter.value() != comparator.name().as_bytes() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "table was written with comparator {}, not {}",
+                String::from_utf8_lossy(iter.value()),
+                comparator.name()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn check(iter: &BlockIter) -> io::Result<()> {
+    if iter.corrupted() {
+        return Err(corruption("bad entry in block"));
+    }
+    Ok(())
+}
+
+fn corruption(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+/// Iterates over every entry of a table, reading one data block at a time.
+pub struct TableIter {
+    table: Arc<TableReader>,
+    index_iter: BlockIter,
+    data_iter: Option<BlockIter>,
+    error: Option<io::Error>,
+}
+
+impl TableIter {
+    fn init_data_block(&mut self) {
+        self.data_iter = None;
+        if !self.index_iter.valid() {
+            if self.index_iter.corrupted() {
+                self.record_error(corruption("bad entry in index block"));
+            }
+            return;
+        }
+        match self.table.block_iter(self.index_iter.value()) {
+            Ok(iter) => self.data_iter = Some(iter),
+            Err(err) => self.record_error(err),
+        }
+    }
+
+    fn skip_empty_data_blocks(&mut self) {
+        loop {
+            match &self.data_iter {
+                Some(iter) if iter.valid() => return,
+                Some(iter) if iter.corrupted() => {
+                    self.record_error(corruption("bad entry in block"));
+                    self.data_iter = None;
+                    return;
+                }
+                Some(_) => {}
+                None => return,
+            }
+            self.index_iter.next();
+            self.init_data_block();
+            if let Some(iter) = self.data_iter.as_mut() {
+                iter.seek_to_first();
+            }
+        }
+    }
+
+    fn skip_empty_data_blocks_backward(&mut self) {
+        loop {
+            match &self.data_iter {
+                Some(iter) if iter.valid() => return,
+                Some(iter) if iter.corrupted() => {
+                    self.record_error(corruption("bad entry in block"));
+                    self.data_iter = None;
+                    return;
+                }
+                Some(_) => {}
+                None => return,
+            }
+            self.index_iter.prev();
+            self.init_data_block();
+            if let Some(iter) = self.data_iter.as_mut() {
+                iter.seek_to_last();
+            }
+        }
+    }
+
+    fn record_error(&mut self, err: io::Error) {
+        if self.error.is_none() {
+            self.error = Some(err);
+        }
+    }
+}
+
+impl InternalIterator for TableIter {
+    fn valid(&self) -> bool {
+        self.data_iter.as_ref().is_some_and(|iter| iter.valid())
+    }
+
+    fn key(&self) -> &[u8] {
+        self.data_iter.as_ref().unwrap().key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.data_iter.as_ref().unwrap().value()
+    }
+
+    /// Returns the first error hit while iterating.
+    fn status(&self) -> io::Result<()> {
+        match &self.error {
+            Some(err) => Err(io::Error::new(err.kind(), err.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    fn seek_to_first(&mut self) {
+        self.index_iter.seek_to_first();
+        self.init_data_block();
+        if let Some(iter) = self.data_iter.as_mut() {
+            iter.seek_to_first();
+        }
+        self.skip_empty_data_blocks();
+    }
+
+    fn seek_to_last(&mut self) {
+        self.index_iter.seek_to_last();
+        self.init_data_block();
+        if let Some(iter) = self.data_iter.as_mut() {
+            iter.seek_to_last();
+        }
+        self.skip_empty_data_blocks_backward();
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        self.index_iter.seek(target);
+        self.init_data_block();
+        if let Some(iter) = self.data_iter.as_mut() {
+            iter.seek(target);
+        }
+        self.skip_empty_data_blocks();
+    }
+
+    fn next(&mut self) {
+        self.data_iter.as_mut().unwrap().next();
+        self.skip_empty_data_blocks();
+    }
+
+    fn prev(&mut self) {
+        self.data_iter.as_mut().unwrap().prev();
+        self.skip_empty_data_blocks_backward();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::RenamedComparator;
+    use crate::table_writer::TableBuilder;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!("saturn_table_{name}.sst"));
+        path
+    }
+
+    fn build(path: &Path, options: &Options, count: usize) -> io::Result<u64> {
+        let mut builder = TableBuilder::new(File::create(path)?, options);
+        for i in 0..count {
+            builder.add(
+                format!("key{:05}", i).as_bytes(),
+                format!("value{}", i).as_bytes(),
+            )?;
+        }
+        builder.finish()
+    }
+
+    #[test]
+    fn build_and_read_many_blocks() -> io::Result<()> {
+        let path = temp_path("many_blocks");
+        let options = Options {
+            block_size: 256,
+            ..Options::default()
+        };
+        let size = build(&path, &options, 1000)?;
+        assert_eq!(fs::metadata(&path)?.len(), size);
+
+        let table = Arc::new(TableReader::open(&path, &options)?);
+        assert_eq!(table.get(b"key00000")?, Some(b"value0".to_vec()));
+        assert_eq!(table.get(b"key00777")?, Some(b"value777".to_vec()));
+        assert_eq!(table.get(b"key00999")?, Some(b"value999".to_vec()));
+        assert_eq!(table.get(b"key0077")?, None);
+        assert_eq!(table.get(b"key01000")?, None);
+        assert_eq!(table.get(b"a")?, None);
+
+        let mut iter = table.iter();
+        iter.seek_to_first();
+        let mut count = 0;
+        while iter.valid() {
+            assert_eq!(iter.key(), format!("key{:05}", count).as_bytes());
+            count += 1;
+            iter.next();
+        }
+        assert_eq!(count, 1000);
+        iter.status()?;
+
+        iter.seek_to_last();
+        while iter.valid() {
+            count -= 1;
+            assert_eq!(iter.key(), format!("key{:05}", count).as_bytes());
+            iter.prev();
+        }
+        assert_eq!(count, 0);
+        iter.status()?;
+
+        iter.seek(b"key00500x");
+        assert_eq!(iter.key(), b"key00501");
+        iter.prev();
+        iter.prev();
+        assert_eq!(iter.key(), b"key00499");
+        iter.seek(b"zzz");
+        assert!(!iter.valid());
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_reads() -> io::Result<()> {
+        let path = temp_path("concurrent_reads");
+        let options = Options {
+            block_size: 256,
+            ..Options::default()
+        };
+        build(&path, &options, 1000)?;
+
+        let table = Arc::new(TableReader::open(&path, &options)?);
+        let readers: Vec<_> = (0..4)
+            .map(|t| {
+                let table = table.clone();
+                std::thread::spawn(move || {
+                    for i in (t..1000).step_by(4) {
+                        let key = format!("key{:05}", i);
+                        let value = table.get(key.as_bytes()).unwrap();
+                        assert_eq!(value, Some(format!("value{}", i).into_bytes()));
+                    }
+                })
+            })
+            .collect();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn filter_is_read_back() -> io::Result<()> {
+        let path = temp_path("filter");
+        let options = Options::default();
+        build(&path, &options, 100)?;
+
+        let table = TableReader::open(&path, &options)?;
+        assert!(table.filter.is_some());
+        for i in 0..100 {
+            assert!(table.may_contain(format!("key{:05}", i).as_bytes()));
+        }
+        let false_positives = (100..1100)
+            .filter(|i| table.may_contain(format!("key{:05}", i).as_bytes()))
+            .count();
+        assert!(false_positives < 50);
+
+        let options = Options {
+            bloom_bits_per_key: 0,
+            ..Options::default()
+        };
+        build(&path, &options, 100)?;
+        let table = TableReader::open(&path, &options)?;
+        assert!(table.filter.is_none());
+        assert_eq!(table.get(b"key00042")?, Some(b"value42".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn detects_corruption() -> io::Result<()> {
+        let path = temp_path("corrupt");
+        let options = Options::default();
+        build(&path, &options, 10)?;
+
+        let mut data = fs::read(&path)?;
+        data[3] ^= 0xff;
+        fs::write(&path, &data)?;
+        let table = TableReader::open(&path, &options)?;
+        let err = table.get(b"key00001").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let len = data.len();
+        data[len - 1] ^= 0xff;
+        fs::write(&path, &data)?;
+        assert!(TableReader::open(&path, &options).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_another_comparator() -> io::Result<()> {
+        let path = temp_path("comparator");
+        let options = Options::default();
+        build(&path, &options, 10)?;
+
+        let renamed = Options {
+            comparator: Arc::new(RenamedComparator),
+            ..Options::default()
+        };
+        let err = TableReader::open(&path, &renamed).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("BytewiseComparator"));
+
+        build(&path, &renamed, 10)?;
+        let table = TableReader::open(&path, &renamed)?;
+        assert_eq!(table.get(b"key00003")?, Some(b"value3".to_vec()));
+        Ok(())
+    }
+}
@@ -1,6 +1,206 @@
+// A table file is a sequence of blocks followed by a fixed-size footer:
+//
+// +------------------+
+// |  data block 1    |
+// |  ...             |
+// |  data block N    |
+// |  filter block    |  bloom filter over every key in the table
+// |  properties      |  property name -> value, such as the comparator
+// |  metaindex block |  meta block name -> handle
+// |  index block     |  one entry per data block: separator key -> handle
+// |  footer          |  metaindex and index handles, padding, magic number
+// +------------------+
+//
+// Every block is followed by a 4-byte trailer holding the masked CRC32C of
+// its contents.
+
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use crate::block::BlockBuilder;
+use crate::bloom_filter::BloomFilter;
 use crate::common::SegmentHandle;
+use crate::comparator::Comparator;
+use crate::crc::crc32c;
+use crate::options::Options;
+
+pub const TABLE_MAGIC_NUMBER: u64 = 0x5341_5455_524e_5442; // "SATURNTB"
+pub const BLOCK_TRAILER_SIZE: usize = 4;
+pub const FOOTER_SIZE: usize = 2 * SegmentHandle::MAX_ENCODED_LENGTH + 8;
+/// Metaindex key of the bloom filter block.
+pub const FILTER_BLOCK_NAME: &[u8] = b"filter.bloom";
+/// Metaindex key of the properties block.
+pub const PROPERTIES_BLOCK_NAME: &[u8] = b"properties";
+/// Property holding the name of the comparator that ordered the keys.
+pub const COMPARATOR_PROPERTY: &[u8] = b"saturn.comparator";
 
-#[derive (Debug, Clone)]
+#[derive(Debug, Clone)]
 pub struct TableFooter {
-    pub index: SegmentHandle
+    pub metaindex: SegmentHandle,
+    pub index: SegmentHandle,
+}
+
+impl TableFooter {
+    pub fn encode(&self) -> [u8; FOOTER_SIZE] {
+        let mut dest = [0u8; FOOTER_SIZE];
+        let len = self.metaindex.encode(&mut dest);
+        self.index.encode(&mut dest[len..]);
+        dest[FOOTER_SIZE - 8..].copy_from_slice(&TABLE_MAGIC_NUMBER.to_le_bytes());
+        dest
+    }
+
+    pub fn decode(src: &[u8]) -> io::Result<Self> {
+        if src.len() != FOOTER_SIZE || src[FOOTER_SIZE - 8..] != TABLE_MAGIC_NUMBER.to_le_bytes() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a table file (bad magic number)",
+            ));
+        }
+        let bad_handle = || io::Error::new(io::ErrorKind::InvalidData, "bad handle in footer");
+        let (metaindex, len) = SegmentHandle::decode(src).ok_or_else(bad_handle)?;
+        let (index, _) = SegmentHandle::decode(&src[len..]).ok_or_else(bad_handle)?;
+        Ok(TableFooter { metaindex, index })
+    }
+}
+
+/// Writes a table from entries added in increasing key order.
+pub struct TableBuilder<W: Write> {
+    dest: W,
+    comparator: Arc<dyn Comparator>,
+    block_size: usize,
+    bloom_bits_per_key: usize,
+    offset: usize,
+    data_block: BlockBuilder,
+    index_block: BlockBuilder,
+    last_key: Vec<u8>,
+    num_entries: usize,
+    filter_keys: Vec<Vec<u8>>,
+    filter_key: fn(&[u8]) -> &[u8],
+    // The index entry for a finished data block is only added once the next
+    // key is seen, so a short separator between the two blocks can be used.
+    pending_handle: Option<SegmentHandle>,
+}
+
+impl<W: Write> TableBuilder<W> {
+    pub fn new(dest: W, options: &Options) -> Self {
+        Self {
+            dest,
+            comparator: options.comparator.clone(),
+            block_size: options.block_size,
+            bloom_bits_per_key: options.bloom_bits_per_key,
+            offset: 0,
+            data_block: BlockBuilder::new(options.block_restart_interval),
+            index_block: BlockBuilder::new(1),
+            last_key: Vec::new(),
+            num_entries: 0,
+            filter_keys: Vec::new(),
+            filter_key: |key| key,
+            pending_handle: None,
+        }
+    }
+
+    /// Adds `filter_key(key)` to the bloom filter instead of the whole key,
+    /// so lookups can be filtered on part of a key.
+    pub fn with_filter_key(mut self, filter_key: fn(&[u8]) -> &[u8]) -> Self {
+        self.filter_key = filter_key;
+        self
+    }
+
+    pub fn add(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        debug_assert!(
+            self.num_entries == 0
+                || self.comparator.compare(&self.last_key, key) == std::cmp::Ordering::Less
+        );
+        if let Some(handle) = self.pending_handle.take() {
+            let separator = self.comparator.find_shortest_separator(&self.last_key, key);
+            self.add_index_entry(&separator, &handle);
+        }
+
+        if self.bloom_bits_per_key > 0 {
+            let filter_key = (self.filter_key)(key);
+            if self.filter_keys.last().map(|last| last.as_slice()) != Some(filter_key) {
+                self.filter_keys.push(filter_key.to_vec());
+            }
+        }
+        self.data_block.add(key, value);
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
+        self.num_entries += 1;
+
+        if self.data_block.current_size_estimate() >= self.block_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes out the data block being built, if any.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.data_block.is_empty() {
+            return Ok(());
+        }
+        let contents = self.data_block.finish();
+        self.pending_handle = Some(self.write_block(&contents)?);
+        Ok(())
+    }
+
+    /// Writes the filter, properties, metaindex and index blocks and the
+    /// footer. Returns the size of the table.
+    pub fn finish(mut self) -> io::Result<u64> {
+        self.flush()?;
+
+        let mut metaindex_block = BlockBuilder::new(1);
+        if self.bloom_bits_per_key > 0 {
+            let mut filter = BloomFilter::new(self.filter_keys.len(), self.bloom_bits_per_key);
+            for key in &self.filter_keys {
+                filter.add(key);
+            }
+            let handle = self.write_block(&filter.encode())?;
+            metaindex_block.add(FILTER_BLOCK_NAME, &encode_handle(&handle));
+        }
+        let mut properties_block = BlockBuilder::new(1);
+        properties_block.add(COMPARATOR_PROPERTY, self.comparator.name().as_bytes());
+        let handle = self.write_block(&properties_block.finish())?;
+        metaindex_block.add(PROPERTIES_BLOCK_NAME, &encode_handle(&handle));
+        let metaindex = self.write_block(&metaindex_block.finish())?;
+
+        if let Some(handle) = self.pending_handle.take() {
+            let successor = self.comparator.find_short_successor(&self.last_key);
+            self.add_index_entry(&successor, &handle);
+        }
+        let contents = self.index_block.finish();
+        let index = self.write_block(&contents)?;
+        self.dest
+            .write_all(&TableFooter { metaindex, index }.encode())?;
+        self.dest.flush()?;
+        Ok((self.offset + FOOTER_SIZE) as u64)
+    }
+
+    pub fn num_entries(&self) -> usize {
+        self.num_entries
+    }
+
+    /// Returns the number of bytes written so far.
+    pub fn file_size(&self) -> u64 {
+        self.offset as u64
+    }
+
+    fn add_index_entry(&mut self, key: &[u8], handle: &SegmentHandle) {
+        self.index_block.add(key, &encode_handle(handle));
+    }
+
+    fn write_block(&mut self, contents: &[u8]) -> io::Result<SegmentHandle> {
+        let handle = SegmentHandle::new(self.offset, contents.len());
+        let mut trailer = [0u8; BLOCK_TRAILER_SIZE];
+        crc32c::put_fixed32_le(&mut trailer, crc32c::mask(crc32c::value(contents)));
+        self.dest.write_all(contents)?;
+        self.dest.write_all(&trailer)?;
+        self.offset += contents.len() + BLOCK_TRAILER_SIZE;
+        Ok(handle)
+    }
+}
+
+fn encode_handle(handle: &SegmentHandle) -> Vec<u8> {
+    let mut encoded = [0u8; SegmentHandle::MAX_ENCODED_LENGTH];
+    let len = handle.encode(&mut encoded);
+    encoded[..len].to_vec()
 }
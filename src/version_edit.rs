@@ -0,0 +1,212 @@
+use std::collections::BTreeSet;
+use std::io;
+
+use crate::common::{
+    decode_var, get_length_prefixed, put_length_prefixed, put_var, SequenceNumber,
+};
+use crate::dbformat::InternalKey;
+
+// Tag numbers for the fields of a serialized `VersionEdit`.
+const COMPARATOR: usize = 1;
+const LOG_NUMBER: usize = 2;
+const NEXT_FILE_NUMBER: usize = 3;
+const LAST_SEQUENCE: usize = 4;
+const DELETED_FILE: usize = 6;
+const NEW_FILE: usize = 7;
+
+/// Metadata describing one live table file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetaData {
+    pub number: u64,
+    pub file_size: u64,
+    pub smallest: InternalKey,
+    pub largest: InternalKey,
+    /// Range of the sequence numbers of the entries in the file.
+    pub smallest_seqno: SequenceNumber,
+    pub largest_seqno: SequenceNumber,
+    /// When the oldest data in the file was written, in seconds since the
+    /// Unix epoch.
+    pub creation_time: u64,
+}
+
+/// A change to the set of live table files, persisted as one manifest record.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VersionEdit {
+    /// Name of the comparator the database's keys are ordered by.
+    pub comparator: Option<String>,
+    /// Write-ahead logs numbered below this hold no data that is not in a
+    /// table.
+    pub log_number: Option<u64>,
+    pub next_file_number: Option<u64>,
+    pub last_sequence: Option<SequenceNumber>,
+    pub deleted_files: BTreeSet<(usize, u64)>,
+    pub new_files: Vec<(usize, FileMetaData)>,
+}
+
+impl VersionEdit {
+    pub fn set_comparator_name(&mut self, name: &str) {
+        self.comparator = Some(name.to_string());
+    }
+
+    pub fn set_log_number(&mut self, number: u64) {
+        self.log_number = Some(number);
+    }
+
+    pub fn set_next_file_number(&mut self, number: u64) {
+        self.next_file_number = Some(number);
+    }
+
+    pub fn set_last_sequence(&mut self, sequence: SequenceNumber) {
+        self.last_sequence = Some(sequence);
+    }
+
+    pub fn add_file(&mut self, level: usize, file: FileMetaData) {
+        self.new_files.push((level, file));
+    }
+
+    pub fn delete_file(&mut self, level: usize, number: u64) {
+        self.deleted_files.insert((level, number));
+    }
+
+    pub fn encode(&self, dst: &mut Vec<u8>) {
+        if let Some(name) = &self.comparator {
+            put_var(dst, COMPARATOR);
+            put_length_prefixed(dst, name.as_bytes());
+        }
+        if let Some(number) = self.log_number {
+            put_var(dst, LOG_NUMBER);
+            put_var(dst, number as usize);
+        }
+        if let Some(number) = self.next_file_number {
+            put_var(dst, NEXT_FILE_NUMBER);
+            put_var(dst, number as usize);
+        }
+        if let Some(sequence) = self.last_sequence {
+            put_var(dst, LAST_SEQUENCE);
+            put_var(dst, sequence as usize);
+        }
+        for &(level, number) in &self.deleted_files {
+            put_var(dst, DELETED_FILE);
+            put_var(dst, level);
+            put_var(dst, number as usize);
+        }
+        for (level, file) in &self.new_files {
+            put_var(dst, NEW_FILE);
+            put_var(dst, *level);
+            put_var(dst, file.number as usize);
+            put_var(dst, file.file_size as usize);
+            put_length_prefixed(dst, file.smallest.encode());
+            put_length_prefixed(dst, file.largest.encode());
+            put_var(dst, file.smallest_seqno as usize);
+            put_var(dst, file.largest_seqno as usize);
+            put_var(dst, file.creation_time as usize);
+        }
+    }
+
+    pub fn decode(mut src: &[u8]) -> io::Result<Self> {
+        let mut edit = VersionEdit::default();
+        while !src.is_empty() {
+            let tag = get_var(&mut src)?;
+            match tag {
+                COMPARATOR => edit.comparator = Some(get_string(&mut src)?),
+                LOG_NUMBER => edit.log_number = Some(get_var(&mut src)? as u64),
+                NEXT_FILE_NUMBER => edit.next_file_number = Some(get_var(&mut src)? as u64),
+                LAST_SEQUENCE => edit.last_sequence = Some(get_var(&mut src)? as u64),
+                DELETED_FILE => {
+                    let level = get_var(&mut src)?;
+                    let number = get_var(&mut src)? as u64;
+                    edit.deleted_files.insert((level, number));
+                }
+                NEW_FILE => {
+                    let level = get_var(&mut src)?;
+                    let file = FileMetaData {
+                        number: get_var(&mut src)? as u64,
+                        file_size: get_var(&mut src)? as u64,
+                        smallest: get_internal_key(&mut src)?,
+                        largest: get_internal_key(&mut src)?,
+                        smallest_seqno: get_var(&mut src)? as u64,
+                        largest_seqno: get_var(&mut src)? as u64,
+                        creation_time: get_var(&mut src)? as u64,
+                    };
+                    edit.new_files.push((level, file));
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unknown version edit tag {}", tag),
+                    ))
+                }
+            }
+        }
+        Ok(edit)
+    }
+}
+
+fn get_var(src: &mut &[u8]) -> io::Result<usize> {
+    let (value, len) = decode_var(src).ok_or_else(truncated)?;
+    *src = &src[len..];
+    Ok(value)
+}
+
+fn get_string(src: &mut &[u8]) -> io::Result<String> {
+    let (bytes, len) = get_length_prefixed(src).ok_or_else(truncated)?;
+    let string = String::from_utf8(bytes.to_vec()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad comparator name in version edit",
+        )
+    })?;
+    *src = &src[len..];
+    Ok(string)
+}
+
+fn get_internal_key(src: &mut &[u8]) -> io::Result<InternalKey> {
+    let (bytes, len) = get_length_prefixed(src).ok_or_else(truncated)?;
+    let key = InternalKey::decode_from(bytes).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad internal key in version edit",
+        )
+    })?;
+    *src = &src[len..];
+    Ok(key)
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "truncated version edit")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbformat::ValueType;
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let mut edit = VersionEdit::default();
+        edit.set_comparator_name("BytewiseComparator");
+        edit.set_log_number(15);
+        edit.set_next_file_number(17);
+        edit.set_last_sequence(1 << 40);
+        edit.delete_file(0, 3);
+        edit.delete_file(2, 5);
+        edit.add_file(
+            1,
+            FileMetaData {
+                number: 16,
+                file_size: 4096,
+                smallest: InternalKey::new(b"apple", 3, ValueType::Value),
+                largest: InternalKey::new(b"pear", 9, ValueType::Deletion),
+                smallest_seqno: 3,
+                largest_seqno: 12,
+                creation_time: 1_700_000_000,
+            },
+        );
+
+        let mut encoded = Vec::new();
+        edit.encode(&mut encoded);
+        assert_eq!(VersionEdit::decode(&encoded).unwrap(), edit);
+
+        assert!(VersionEdit::decode(&encoded[..encoded.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,550 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+
+use crate::common::SequenceNumber;
+use crate::comparator::{BytewiseComparator, Comparator};
+use crate::dbformat::InternalKeyComparator;
+use crate::filename::{self, FileType};
+use crate::version_edit::{FileMetaData, VersionEdit};
+use crate::wal_reader::{Reader, Reporter};
+use crate::wal_writer::Writer;
+
+pub const NUM_LEVELS: usize = 7;
+
+/// An immutable snapshot of the live table files, grouped by level.
+///
+/// Files in level 0 may overlap and are kept from oldest to newest data.
+/// Files in every other level are disjoint and sorted by key.
+pub struct Version {
+    pub files: Vec<Vec<Arc<FileMetaData>>>,
+    comparator: Arc<InternalKeyComparator>,
+}
+
+/// An empty version of a database ordered bytewise.
+impl Default for Version {
+    fn default() -> Self {
+        Self::new(Arc::new(InternalKeyComparator::new(Arc::new(
+            BytewiseComparator::new(),
+        ))))
+    }
+}
+
+impl Version {
+    /// Returns an empty version whose keys are ordered by `comparator`.
+    pub fn new(comparator: Arc<InternalKeyComparator>) -> Self {
+        Self {
+            files: vec![Vec::new(); NUM_LEVELS],
+            comparator,
+        }
+    }
+
+    pub fn comparator(&self) -> &Arc<InternalKeyComparator> {
+        &self.comparator
+    }
+
+    /// Compares user keys.
+    pub fn compare_user_keys(&self, a: &[u8], b: &[u8]) -> Ordering {
+        self.comparator.user_comparator().compare(a, b)
+    }
+
+    /// Returns true if `user_key` is within the key range of `file`.
+    pub fn file_contains(&self, file: &FileMetaData, user_key: &[u8]) -> bool {
+        self.compare_user_keys(user_key, file.smallest.user_key()) != Ordering::Less
+            && self.compare_user_keys(user_key, file.largest.user_key()) != Ordering::Greater
+    }
+
+    /// Returns the files whose key range contains `key`, newest first.
+    pub fn files_for_key(&self, key: &[u8]) -> Vec<Arc<FileMetaData>> {
+        let contains = |f: &FileMetaData| self.file_contains(f, key);
+
+        let mut result: Vec<_> = self.files[0]
+            .iter()
+            .rev()
+            .filter(|f| contains(f))
+            .cloned()
+            .collect();
+        for files in &self.files[1..] {
+            let index = files.partition_point(|f| {
+                self.compare_user_keys(f.largest.user_key(), key) == Ordering::Less
+            });
+            if index < files.len() && contains(&files[index]) {
+                result.push(files[index].clone());
+            }
+        }
+        result
+    }
+
+    /// Returns the total size of the files in `level`.
+    pub fn level_bytes(&self, level: usize) -> u64 {
+        self.files[level].iter().map(|f| f.file_size).sum()
+    }
+
+    /// Returns the files in `level` that overlap the user key range
+    /// [`begin`, `end`].
+    ///
+    /// Since level-0 files may overlap each other, the range is widened to
+    /// cover every level-0 file it touches, so a key's versions are never
+    /// split between the files returned and the files left behind.
+    pub fn overlapping_inputs(
+        &self,
+        level: usize,
+        begin: &[u8],
+        end: &[u8],
+    ) -> Vec<Arc<FileMetaData>> {
+        let mut begin = begin.to_vec();
+        let mut end = end.to_vec();
+        'restart: loop {
+            let mut inputs = Vec::new();
+            for file in &self.files[level] {
+                let (smallest, largest) = (file.smallest.user_key(), file.largest.user_key());
+                if self.compare_user_keys(largest, &begin) == Ordering::Less
+                    || self.compare_user_keys(smallest, &end) == Ordering::Greater
+                {
+                    continue;
+                }
+                if level == 0 {
+                    let extends_begin = self.compare_user_keys(smallest, &begin) == Ordering::Less;
+                    let extends_end = self.compare_user_keys(largest, &end) == Ordering::Greater;
+                    if extends_begin {
+                        begin = smallest.to_vec();
+                    }
+                    if extends_end {
+                        end = largest.to_vec();
+                    }
+                    if extends_begin || extends_end {
+                        continue 'restart;
+                    }
+                }
+                inputs.push(file.clone());
+            }
+            return inputs;
+        }
+    }
+
+    fn apply(&self, edit: &VersionEdit) -> io::Result<Version> {
+        let mut files = self.files.clone();
+        for &(level, number) in &edit.deleted_files {
+            check_level(level)?;
+            files[level].retain(|f| f.number != number);
+        }
+        for (level, file) in &edit.new_files {
+            check_level(*level)?;
+            files[*level].push(Arc::new(file.clone()));
+        }
+        files[0].sort_by_key(|f| (f.largest_seqno, f.number));
+        for level_files in files.iter_mut().skip(1) {
+            level_files.sort_by(|a, b| {
+                self.comparator
+                    .compare(a.smallest.encode(), b.smallest.encode())
+            });
+        }
+        Ok(Version {
+            files,
+            comparator: self.comparator.clone(),
+        })
+    }
+}
+
+fn check_level(level: usize) -> io::Result<()> {
+    if level >= NUM_LEVELS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("level {} out of range", level),
+        ));
+    }
+    Ok(())
+}
+
+/// Owns the current `Version` and the manifest, a log of `VersionEdit`s
+/// from which the table set is rebuilt on open.
+pub struct VersionSet {
+    dbname: PathBuf,
+    // Orders the user keys. Its name is recorded in the manifest.
+    comparator: Arc<dyn Comparator>,
+    current: Arc<Version>,
+    // Write-ahead logs numbered below this are no longer needed.
+    log_number: u64,
+    next_file_number: u64,
+    last_sequence: SequenceNumber,
+    manifest_file_number: u64,
+    manifest: Option<Writer<File>>,
+    // Versions replaced by a later edit that may still be in use by readers.
+    old_versions: Vec<Weak<Version>>,
+}
+
+impl VersionSet {
+    pub fn new(dbname: &Path, comparator: Arc<dyn Comparator>) -> Self {
+        Self {
+            dbname: dbname.to_path_buf(),
+            current: Arc::new(Version::new(Arc::new(InternalKeyComparator::new(
+                comparator.clone(),
+            )))),
+            comparator,
+            log_number: 0,
+            next_file_number: 1,
+            last_sequence: 0,
+            manifest_file_number: 0,
+            manifest: None,
+            old_versions: Vec::new(),
+        }
+    }
+
+    pub fn current(&self) -> Arc<Version> {
+        self.current.clone()
+    }
+
+    pub fn new_file_number(&mut self) -> u64 {
+        let number = self.next_file_number;
+        self.next_file_number += 1;
+        number
+    }
+
+    /// Returns the number `new_file_number` hands out next.
+    pub fn next_file_number(&self) -> u64 {
+        self.next_file_number
+    }
+
+    /// Makes sure `number`, found on disk, is not handed out again.
+    pub fn mark_file_number_used(&mut self, number: u64) {
+        self.next_file_number = self.next_file_number.max(number + 1);
+    }
+
+    /// Returns the number of the oldest write-ahead log that may hold data
+    /// missing from the tables.
+    pub fn log_number(&self) -> u64 {
+        self.log_number
+    }
+
+    pub fn last_sequence(&self) -> SequenceNumber {
+        self.last_sequence
+    }
+
+    /// Records that every sequence number up to `sequence` has been used.
+    pub fn set_last_sequence(&mut self, sequence: SequenceNumber) {
+        debug_assert!(sequence >= self.last_sequence);
+        self.last_sequence = sequence;
+    }
+
+    /// Replays the manifest named by CURRENT. Fails if the database was
+    /// created with another comparator.
+    pub fn recover(&mut self) -> io::Result<()> {
+        let current = fs::read_to_string(filename::current_file_name(&self.dbname))?;
+        let name = match current.strip_suffix('\n') {
+            Some(name) if !name.is_empty() => name,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "CURRENT file does not end with newline",
+                ))
+            }
+        };
+        let manifest_file_number = match filename::parse_file_name(name) {
+            Some((number, FileType::Manifest)) => number,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("CURRENT names unexpected file {}", name),
+                ))
+            }
+        };
+        let file = File::open(self.dbname.join(name))?;
+        let mut reader = Reader::new(
+            BufReader::new(file),
+            Some(ManifestReporter::default()),
+            true,
+            0,
+        );
+
+        let mut version = Version::new(self.current.comparator.clone());
+        let mut log_number = None;
+        let mut next_file_number = None;
+        let mut last_sequence = None;
+        let mut record = Vec::new();
+        while reader.read_record(&mut record)? {
+            let edit = VersionEdit::decode(&record)?;
+            if let Some(name) = &edit.comparator {
+                if name != self.comparator.name() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "database was created with comparator {}, not {}",
+                            name,
+                            self.comparator.name()
+                        ),
+                    ));
+                }
+            }
+            version = version.apply(&edit)?;
+            log_number = edit.log_number.or(log_number);
+            next_file_number = edit.next_file_number.or(next_file_number);
+            last_sequence = edit.last_sequence.or(last_sequence);
+        }
+        if let (
+            _,
+            Some(ManifestReporter {
+                error: Some(reason),
+            }),
+        ) = reader.into_inner()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("corrupted manifest {}: {}", name, reason),
+            ));
+        }
+
+        self.next_file_number = next_file_number.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no next file number entry in manifest",
+            )
+        })?;
+        self.log_number = log_number.unwrap_or(0);
+        self.last_sequence = last_sequence.unwrap_or(0);
+        self.manifest_file_number = manifest_file_number;
+        self.current = Arc::new(version);
+        Ok(())
+    }
+
+    /// Applies `edit` to the current version and records it in the manifest.
+    ///
+    /// The first call after open starts a fresh manifest holding a snapshot
+    /// of the current state and points CURRENT at it.
+    pub fn log_and_apply(&mut self, edit: &mut VersionEdit) -> io::Result<()> {
+        let version = self.current.apply(edit)?;
+
+        let mut new_manifest = None;
+        if self.manifest.is_none() {
+            let number = self.new_file_number();
+            let path = filename::manifest_file_name(&self.dbname, number);
+            let mut writer = Writer::new(File::create(&path)?);
+            let mut record = Vec::new();
+            self.snapshot().encode(&mut record);
+            if let Err(err) = writer.add_record(&record) {
+                let _ = fs::remove_file(&path);
+                return Err(err);
+            }
+            self.manifest = Some(writer);
+            new_manifest = Some((number, path));
+        }
+
+        match edit.log_number {
+            Some(number) => debug_assert!(number >= self.log_number),
+            None => edit.set_log_number(self.log_number),
+        }
+        edit.set_next_file_number(self.next_file_number);
+        if edit.last_sequence.is_none() {
+            edit.set_last_sequence(self.last_sequence);
+        }
+        let mut record = Vec::new();
+        edit.encode(&mut record);
+        let writer = self.manifest.as_mut().unwrap();
+        let mut result = writer.add_record(&record);
+        if result.is_ok() {
+            result = writer.get_ref().sync_all();
+        }
+        if let Some((number, path)) = &new_manifest {
+            if result.is_ok() {
+                result = filename::set_current_file(&self.dbname, *number);
+            }
+            if result.is_err() {
+                self.manifest = None;
+                let _ = fs::remove_file(path);
+            }
+        }
+        result?;
+
+        if let Some((number, _)) = new_manifest {
+            let old = self.manifest_file_number;
+            self.manifest_file_number = number;
+            if old != 0 {
+                let _ = fs::remove_file(filename::manifest_file_name(&self.dbname, old));
+            }
+        }
+        self.log_number = edit.log_number.unwrap();
+        self.last_sequence = edit.last_sequence.unwrap();
+        let old = std::mem::replace(&mut self.current, Arc::new(version));
+        self.old_versions.push(Arc::downgrade(&old));
+        Ok(())
+    }
+
+    /// Returns the numbers of the table files referenced by the current
+    /// version or by an older version that is still in use.
+    pub fn live_files(&mut self) -> HashSet<u64> {
+        self.old_versions
+            .retain(|version| version.strong_count() > 0);
+        let mut live = HashSet::new();
+        let versions = self.old_versions.iter().filter_map(Weak::upgrade);
+        for version in versions.chain([self.current.clone()]) {
+            for files in &version.files {
+                live.extend(files.iter().map(|f| f.number));
+            }
+        }
+        live
+    }
+
+    /// Returns an edit that recreates the current state from scratch.
+    fn snapshot(&self) -> VersionEdit {
+        let mut edit = VersionEdit::default();
+        edit.set_comparator_name(self.comparator.name());
+        edit.set_log_number(self.log_number);
+        edit.set_next_file_number(self.next_file_number);
+        edit.set_last_sequence(self.last_sequence);
+        for (level, files) in self.current.files.iter().enumerate() {
+            for file in files {
+                edit.add_file(level, file.as_ref().clone());
+            }
+        }
+        edit
+    }
+}
+
+#[derive(Default)]
+struct ManifestReporter {
+    error: Option<String>,
+}
+
+impl Reporter for ManifestReporter {
+    fn corruption(&mut self, _bytes: usize, reason: &str) {
+        if self.error.is_none() {
+            self.error = Some(reason.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comparator::RenamedComparator;
+    use crate::dbformat::{InternalKey, ValueType};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!("saturn_versions_{name}"));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn file(number: u64, smallest: &[u8], largest: &[u8]) -> FileMetaData {
+        FileMetaData {
+            number,
+            file_size: 100,
+            smallest: InternalKey::new(smallest, number, ValueType::Value),
+            largest: InternalKey::new(largest, number, ValueType::Value),
+            smallest_seqno: number,
+            largest_seqno: number,
+            creation_time: 0,
+        }
+    }
+
+    #[test]
+    fn recover_replays_edits() -> io::Result<()> {
+        let dir = temp_dir("recover");
+        {
+            let mut versions = VersionSet::new(&dir, Arc::new(BytewiseComparator::new()));
+            versions.log_and_apply(&mut VersionEdit::default())?;
+
+            let mut edit = VersionEdit::default();
+            edit.add_file(0, file(versions.new_file_number(), b"a", b"m"));
+            edit.add_file(0, file(versions.new_file_number(), b"c", b"z"));
+            edit.set_last_sequence(42);
+            edit.set_log_number(versions.new_file_number());
+            versions.log_and_apply(&mut edit)?;
+
+            let mut edit = VersionEdit::default();
+            edit.delete_file(0, 2);
+            edit.add_file(1, file(versions.new_file_number(), b"a", b"m"));
+            versions.log_and_apply(&mut edit)?;
+        }
+
+        let mut versions = VersionSet::new(&dir, Arc::new(BytewiseComparator::new()));
+        versions.recover()?;
+        assert_eq!(versions.last_sequence(), 42);
+        assert_eq!(versions.log_number(), 4);
+        let current = versions.current();
+        assert_eq!(current.files[0].len(), 1);
+        assert_eq!(current.files[0][0].number, 3);
+        assert_eq!(current.files[1].len(), 1);
+        assert_eq!(current.files[1][0].number, 5);
+        assert!(versions.new_file_number() > 5);
+
+        let numbers: Vec<u64> = current
+            .files_for_key(b"d")
+            .iter()
+            .map(|f| f.number)
+            .collect();
+        assert_eq!(numbers, vec![3, 5]);
+        assert!(current.files_for_key(b"0").is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn reopening_switches_to_a_new_manifest() -> io::Result<()> {
+        let dir = temp_dir("switch");
+        let mut versions = VersionSet::new(&dir, Arc::new(BytewiseComparator::new()));
+        versions.log_and_apply(&mut VersionEdit::default())?;
+        let first = versions.manifest_file_number;
+
+        let mut versions = VersionSet::new(&dir, Arc::new(BytewiseComparator::new()));
+        versions.recover()?;
+        versions.log_and_apply(&mut VersionEdit::default())?;
+        assert_ne!(versions.manifest_file_number, first);
+        assert!(!filename::manifest_file_name(&dir, first).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn overlapping_inputs_and_live_files() -> io::Result<()> {
+        let dir = temp_dir("overlap");
+        let mut versions = VersionSet::new(&dir, Arc::new(BytewiseComparator::new()));
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, file(1, b"a", b"c"));
+        edit.add_file(0, file(2, b"b", b"f"));
+        edit.add_file(0, file(3, b"x", b"z"));
+        edit.add_file(1, file(4, b"a", b"d"));
+        edit.add_file(1, file(5, b"e", b"g"));
+        versions.log_and_apply(&mut edit)?;
+
+        let numbers = |files: Vec<Arc<FileMetaData>>| -> Vec<u64> {
+            files.iter().map(|f| f.number).collect()
+        };
+        let current = versions.current();
+        // Level 0 pulls in files overlapping the widened range.
+        assert_eq!(numbers(current.overlapping_inputs(0, b"a", b"a")), [1, 2]);
+        assert_eq!(numbers(current.overlapping_inputs(1, b"c", b"e")), [4, 5]);
+        assert_eq!(
+            numbers(current.overlapping_inputs(1, b"h", b"w")),
+            Vec::<u64>::new()
+        );
+        assert_eq!(current.level_bytes(1), 200);
+
+        let mut edit = VersionEdit::default();
+        edit.delete_file(0, 1);
+        versions.log_and_apply(&mut edit)?;
+        // The old version is still held, so its files stay live.
+        assert!(versions.live_files().contains(&1));
+        drop(current);
+        assert!(!versions.live_files().contains(&1));
+        assert!(versions.live_files().contains(&2));
+        Ok(())
+    }
+
+    #[test]
+    fn recover_checks_the_comparator() -> io::Result<()> {
+        let dir = temp_dir("comparator");
+        let mut versions = VersionSet::new(&dir, Arc::new(BytewiseComparator::new()));
+        versions.log_and_apply(&mut VersionEdit::default())?;
+
+        let mut versions = VersionSet::new(&dir, Arc::new(RenamedComparator));
+        let err = versions.recover().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("BytewiseComparator"));
+
+        let mut versions = VersionSet::new(&dir, Arc::new(BytewiseComparator::new()));
+        versions.recover()
+    }
+}
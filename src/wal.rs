@@ -1,176 +1,157 @@
 use std::fs::{File, OpenOptions};
-use std::io::{self, BufReader, Read};
-use std::path::{Path, PathBuf};
+use std::io::{self, BufReader, BufWriter, Seek, SeekFrom};
+use std::path::Path;
 
-use crate::common::Entry;
+use crate::options::SyncMethod;
 use crate::wal_reader::{Reader, Reporter};
 use crate::wal_writer::Writer;
-
-const PUT_TAG: u8 = 0;
-const DELETE_TAG: u8 = 1;
+use crate::write_batch::WriteBatch;
 
 pub struct WriteAheadLog {
-    path: PathBuf,
-    writer: Writer<File>,
+    writer: Writer<BufWriter<File>>,
+    // Whether records were appended since the last sync.
+    unsynced: bool,
+    // Makes the next sync fail, to test how the database handles it.
+    #[cfg(test)]
+    pub(crate) fail_next_sync: bool,
 }
 
 impl WriteAheadLog {
     pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        let path_buf = path.as_ref().to_path_buf();
-        let file = OpenOptions::new()
+        let mut file = OpenOptions::new()
             .create(true)
+            .truncate(false)
             .read(true)
             .write(true)
-            .open(&path_buf)?;
-        let len = file.metadata()?.len();
-        let writer = Writer::with_starting_offset(file, len);
+            .open(path)?;
+        // Append after the records already in the log.
+        let len = file.seek(SeekFrom::End(0))?;
+        let writer = Writer::with_starting_offset(BufWriter::new(file), len);
         Ok(Self {
-            path: path_buf,
             writer,
+            unsynced: false,
+            #[cfg(test)]
+            fail_next_sync: false,
         })
     }
 
-    pub fn append(&mut self, entry: &Entry) -> io::Result<()> {
-        let mut payload = Vec::new();
-        encode_entry(entry, &mut payload);
-        self.writer.add_record(&payload)
+    /// Logs `batch` as a single record, so it is replayed whole or not at
+    /// all.
+    pub fn append(&mut self, batch: &WriteBatch) -> io::Result<()> {
+        self.unsynced = true;
+        self.writer.add_record(batch.contents())
+    }
+
+    /// Forces appended records to stable storage, unless they are there
+    /// already.
+    pub fn sync(&mut self, method: SyncMethod) -> io::Result<()> {
+        if !self.unsynced {
+            return Ok(());
+        }
+        #[cfg(test)]
+        if std::mem::take(&mut self.fail_next_sync) {
+            return Err(io::Error::other("injected sync failure"));
+        }
+        let file = self.writer.get_mut();
+        io::Write::flush(file)?;
+        match method {
+            SyncMethod::Fsync => file.get_ref().sync_all()?,
+            SyncMethod::Fdatasync => file.get_ref().sync_data()?,
+        }
+        self.unsynced = false;
+        Ok(())
     }
 
-    pub fn iter(&self) -> io::Result<WriteAheadLogIter> {
-        WriteAheadLogIter::new(&self.path)
+    /// Returns true if records were appended since the last sync.
+    pub fn needs_sync(&self) -> bool {
+        self.unsynced
     }
 
-    pub fn into_inner(self) -> File {
-        self.writer.into_inner()
+    pub fn into_inner(self) -> io::Result<File> {
+        self.writer
+            .into_inner()
+            .into_inner()
+            .map_err(|err| err.into_error())
     }
 }
 
+/// Replays the batches of a log in order. Corrupted bytes at the end of the
+/// log, left by a write a crash cut short, are dropped; corruption followed
+/// by more batches is an error, since skipping it would lose writes.
 pub struct WriteAheadLogIter {
-    reader: Reader<BufReader<File>, NoopReporter>,
+    reader: Reader<BufReader<File>, DroppedBytes>,
     record: Vec<u8>,
+    failed: bool,
 }
 
 impl WriteAheadLogIter {
-    fn new(path: &Path) -> io::Result<Self> {
+    pub fn new(path: &Path) -> io::Result<Self> {
         let file = OpenOptions::new().read(true).open(path)?;
-        let reader = Reader::new(BufReader::new(file), Some(NoopReporter::default()), true, 0);
+        let reader = Reader::new(BufReader::new(file), Some(DroppedBytes::default()), true, 0);
         Ok(Self {
             reader,
             record: Vec::new(),
+            failed: false,
         })
     }
+
+    /// Returns the number of corrupted bytes dropped so far.
+    pub fn dropped_bytes(&self) -> usize {
+        self.reader.reporter().map_or(0, |dropped| dropped.bytes)
+    }
 }
 
 impl Iterator for WriteAheadLogIter {
-    type Item = io::Result<Entry>;
+    type Item = io::Result<WriteBatch>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            match self.reader.read_record(&mut self.record) {
-                Ok(true) => match decode_entry(&self.record) {
-                    Ok(entry) => return Some(Ok(entry)),
-                    Err(err) => return Some(Err(err)),
-                },
-                Ok(false) => return None,
-                Err(err) => return Some(Err(err)),
+        if self.failed {
+            return None;
+        }
+        match self.reader.read_record(&mut self.record) {
+            Ok(true) => {
+                if let Some(reason) = self.reader.reporter().and_then(|d| d.reason.as_ref()) {
+                    self.failed = true;
+                    return Some(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "corrupted log: {} bytes dropped before a later batch ({reason})",
+                            self.dropped_bytes()
+                        ),
+                    )));
+                }
+                Some(WriteBatch::from_contents(self.record.clone()))
+            }
+            Ok(false) => None,
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
             }
         }
     }
 }
 
+/// Counts the bytes the reader drops, keeping the first reason given.
 #[derive(Default)]
-struct NoopReporter;
-
-impl Reporter for NoopReporter {
-    fn corruption(&mut self, _bytes: usize, _reason: &str) {}
+struct DroppedBytes {
+    bytes: usize,
+    reason: Option<String>,
 }
 
-fn encode_entry(entry: &Entry, dst: &mut Vec<u8>) {
-    match entry {
-        Entry::Put { key, value } => {
-            dst.push(PUT_TAG);
-            write_len_prefixed(key, dst);
-            write_len_prefixed(value, dst);
-        }
-        Entry::Delete { key } => {
-            dst.push(DELETE_TAG);
-            write_len_prefixed(key, dst);
-        }
+impl Reporter for DroppedBytes {
+    fn corruption(&mut self, bytes: usize, reason: &str) {
+        self.bytes += bytes;
+        self.reason.get_or_insert_with(|| reason.to_string());
     }
 }
 
-fn decode_entry(src: &[u8]) -> io::Result<Entry> {
-    if src.is_empty() {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "empty WAL record",
-        ));
-    }
-    let tag = src[0];
-    let mut offset = 1;
-    let key = read_len_prefixed(src, &mut offset)?;
-    match tag {
-        PUT_TAG => {
-            let value = read_len_prefixed(src, &mut offset)?;
-            Ok(Entry::Put { key, value })
-        }
-        DELETE_TAG => Ok(Entry::Delete { key }),
-        _ => Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            format!("unknown WAL tag {}", tag),
-        )),
-    }
-}
-
-fn write_len_prefixed(bytes: &[u8], dst: &mut Vec<u8>) {
-    let len = bytes.len() as u32;
-    dst.extend(len.to_be_bytes());
-    dst.extend_from_slice(bytes);
-}
-
-fn read_len_prefixed(src: &[u8], offset: &mut usize) -> io::Result<Vec<u8>> {
-    if src.len().saturating_sub(*offset) < 4 {
-        return Err(io::Error::new(
-            io::ErrorKind::UnexpectedEof,
-            "missing length",
-        ));
-    }
-    let len = u32::from_be_bytes([
-        src[*offset],
-        src[*offset + 1],
-        src[*offset + 2],
-        src[*offset + 3],
-    ]) as usize;
-    *offset += 4;
-    if src.len().saturating_sub(*offset) < len {
-        return Err(io::Error::new(
-            io::ErrorKind::UnexpectedEof,
-            "payload truncated",
-        ));
-    }
-    let out = src[*offset..*offset + len].to_vec();
-    *offset += len;
-    Ok(out)
-}
-
-pub fn read_bytes<R: Read>(reader: &mut R) -> Option<Vec<u8>> {
-    let mut len_buf = [0u8; 4];
-    if reader.read_exact(&mut len_buf).is_err() {
-        return None;
-    }
-    let len = u32::from_be_bytes(len_buf) as usize;
-    let mut buf = vec![0u8; len];
-    if reader.read_exact(&mut buf).is_err() {
-        return None;
-    }
-    Some(buf)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::common::Entry;
+    use crate::wal_format::HEADER_SIZE;
     use std::fs;
+    use std::path::PathBuf;
 
     fn temp_path(name: &str) -> PathBuf {
         let mut path = std::env::temp_dir();
@@ -184,44 +165,98 @@ mod tests {
         let _ = fs::remove_file(&path);
 
         let mut wal = WriteAheadLog::new(&path).unwrap();
-        wal.append(&Entry::Put {
-            key: b"k1".to_vec(),
-            value: b"v1".to_vec(),
-        })
-        .unwrap();
-        wal.append(&Entry::Delete {
-            key: b"k2".to_vec(),
-        })
-        .unwrap();
-        wal.append(&Entry::Put {
-            key: b"k3".to_vec(),
-            value: b"v3".to_vec(),
-        })
-        .unwrap();
+        let mut first = WriteBatch::new();
+        first.put(b"k1", b"v1");
+        first.delete(b"k2");
+        first.set_sequence(1);
+        wal.append(&first).unwrap();
+        let mut second = WriteBatch::new();
+        second.put(b"k3", b"v3");
+        second.set_sequence(3);
+        wal.append(&second).unwrap();
+        assert!(wal.needs_sync());
+        wal.sync(SyncMethod::Fsync).unwrap();
+        assert!(!wal.needs_sync());
 
-        let mut entries = wal.iter().unwrap();
+        let mut batches = WriteAheadLogIter::new(&path).unwrap();
 
+        let batch = batches.next().unwrap().unwrap();
+        assert_eq!(batch.sequence(), 1);
+        let entries: Vec<_> = batch.iter().map(Result::unwrap).collect();
         assert_eq!(
-            entries.next().unwrap().unwrap(),
-            Entry::Put {
-                key: b"k1".to_vec(),
-                value: b"v1".to_vec(),
-            }
-        );
-        assert_eq!(
-            entries.next().unwrap().unwrap(),
-            Entry::Delete {
-                key: b"k2".to_vec(),
-            }
-        );
-        assert_eq!(
-            entries.next().unwrap().unwrap(),
-            Entry::Put {
-                key: b"k3".to_vec(),
-                value: b"v3".to_vec(),
-            }
+            entries,
+            [
+                Entry::Put {
+                    key: b"k1".to_vec(),
+                    value: b"v1".to_vec(),
+                },
+                Entry::Delete {
+                    key: b"k2".to_vec(),
+                },
+            ]
         );
-        assert!(entries.next().is_none());
+        assert_eq!(batches.next().unwrap().unwrap(), second);
+        assert!(batches.next().is_none());
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn reopened_log_appends() {
+        let path = temp_path("reopen");
+        let _ = fs::remove_file(&path);
+
+        let mut batch = WriteBatch::new();
+        batch.put(b"k1", b"v1");
+        WriteAheadLog::new(&path).unwrap().append(&batch).unwrap();
+        let mut other = WriteBatch::new();
+        other.delete(b"k1");
+        WriteAheadLog::new(&path).unwrap().append(&other).unwrap();
+
+        let batches: Vec<_> = WriteAheadLogIter::new(&path)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(batches, [batch, other]);
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn iterator_drops_corrupted_tail_only() {
+        let path = temp_path("corrupted");
+        let _ = fs::remove_file(&path);
+
+        let mut batches = Vec::new();
+        let mut wal = WriteAheadLog::new(&path).unwrap();
+        for (key, value_len) in [(b"k1", 10), (b"k2", 40 * 1024), (b"k3", 10)] {
+            let mut batch = WriteBatch::new();
+            batch.put(key, &vec![b'v'; value_len]);
+            wal.append(&batch).unwrap();
+            batches.push(batch);
+        }
+        wal.sync(SyncMethod::Fsync).unwrap();
+        drop(wal);
+        let contents = fs::read(&path).unwrap();
+        let corrupt = |offset: usize| {
+            let mut corrupted = contents.clone();
+            corrupted[offset] ^= 0xff;
+            fs::write(&path, corrupted).unwrap();
+            WriteAheadLogIter::new(&path).unwrap()
+        };
+
+        // A torn last batch is dropped, and the ones before it replayed.
+        let mut iter = corrupt(contents.len() - 1);
+        assert_eq!(iter.next().unwrap().unwrap(), batches[0]);
+        assert_eq!(iter.next().unwrap().unwrap(), batches[1]);
+        assert!(iter.next().is_none());
+        assert!(iter.dropped_bytes() > 0);
+
+        // Corruption with batches after it fails the replay.
+        let mut iter = corrupt(HEADER_SIZE);
+        let err = iter.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(iter.next().is_none());
 
         let _ = fs::remove_file(&path);
     }
@@ -230,8 +265,8 @@ mod tests {
     fn iterator_handles_empty_log() {
         let path = temp_path("empty");
         let _ = fs::remove_file(&path);
-        let wal = WriteAheadLog::new(&path).unwrap();
-        let mut iter = wal.iter().unwrap();
+        WriteAheadLog::new(&path).unwrap();
+        let mut iter = WriteAheadLogIter::new(&path).unwrap();
         assert!(iter.next().is_none());
         let _ = fs::remove_file(&path);
     }
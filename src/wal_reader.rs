@@ -278,6 +278,10 @@ impl<R: Read + Seek, Rep: Reporter> Reader<R, Rep> {
         self.report(bytes, &format!("drop: {}", reason));
     }
 
+    pub fn reporter(&self) -> Option<&Rep> {
+        self.reporter.as_ref()
+    }
+
     pub fn into_inner(self) -> (R, Option<Rep>) {
         (self.src, self.reporter)
     }
@@ -53,7 +53,6 @@ impl<W: Write> Writer<W> {
                     // pad trailer with zeros
                     let zeros = vec![0u8; leftover];
                     self.dest.write_all(&zeros)?;
-                    self.dest.flush()?;
                 }
                 self.block_offset = 0;
             }
@@ -77,7 +76,9 @@ impl<W: Write> Writer<W> {
                 break;
             }
         }
-        Ok(())
+        // Hand the whole record to the OS at once rather than fragment by
+        // fragment. Whether it reaches the disk is up to the caller.
+        self.dest.flush()
     }
 
     fn emit_physical_record(&mut self, t: RecordType, payload: &[u8]) -> io::Result<()> {
@@ -85,7 +86,7 @@ impl<W: Write> Writer<W> {
         let mut header = [0u8; HEADER_SIZE];
         header[4] = (n & 0xFF) as u8;
         header[5] = ((n >> 8) & 0xFF) as u8;
-        header[6] = t as u8;
+        header[6] = t.as_u8();
 
         let crc = crc32c::extend(self.type_crc[t as usize], payload);
         let masked = crc32c::mask(crc);
@@ -93,11 +94,18 @@ impl<W: Write> Writer<W> {
 
         self.dest.write_all(&header)?;
         self.dest.write_all(payload)?;
-        self.dest.flush()?; // matches LevelDB behavior
         self.block_offset += HEADER_SIZE + n;
         Ok(())
     }
 
+    pub fn get_ref(&self) -> &W {
+        &self.dest
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.dest
+    }
+
     pub fn into_inner(self) -> W {
         self.dest
     }
@@ -0,0 +1,255 @@
+// A write batch is stored, and logged to the write-ahead log, as:
+//
+// +-----------------------------------------------------------+
+// | sequence: u64 LE | count: u32 LE | record 1 | ... | record N |
+// +-----------------------------------------------------------+
+//
+// record := value type: u8 | key: varint-prefixed | value: varint-prefixed
+//
+// Deletion records have no value. The operations of a batch get consecutive
+// sequence numbers, starting at the one in the header.
+
+use std::io;
+
+use crate::common::{get_length_prefixed, put_length_prefixed, Entry, SequenceNumber};
+use crate::dbformat::ValueType;
+
+const HEADER_SIZE: usize = 12;
+
+/// A set of puts and deletes applied to a database atomically, in the order
+/// they were added.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteBatch {
+    rep: Vec<u8>,
+}
+
+impl Default for WriteBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self {
+            rep: vec![0; HEADER_SIZE],
+        }
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.set_count(self.count() + 1);
+        self.rep.push(ValueType::Value as u8);
+        put_length_prefixed(&mut self.rep, key);
+        put_length_prefixed(&mut self.rep, value);
+    }
+
+    pub fn delete(&mut self, key: &[u8]) {
+        self.set_count(self.count() + 1);
+        self.rep.push(ValueType::Deletion as u8);
+        put_length_prefixed(&mut self.rep, key);
+    }
+
+    /// Removes every operation from the batch.
+    pub fn clear(&mut self) {
+        self.rep.clear();
+        self.rep.resize(HEADER_SIZE, 0);
+    }
+
+    /// Returns the number of operations in the batch.
+    pub fn count(&self) -> u32 {
+        u32::from_le_bytes(self.rep[8..HEADER_SIZE].try_into().unwrap())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    /// Returns the size of the serialized batch.
+    pub fn approximate_size(&self) -> usize {
+        self.rep.len()
+    }
+
+    /// Adds the operations of `other` after those of this batch.
+    pub fn append(&mut self, other: &WriteBatch) {
+        self.set_count(self.count() + other.count());
+        self.rep.extend_from_slice(&other.rep[HEADER_SIZE..]);
+    }
+
+    /// Returns the operations of the batch, in the order they were added.
+    pub fn iter(&self) -> WriteBatchIter<'_> {
+        WriteBatchIter {
+            rep: &self.rep[HEADER_SIZE..],
+            remaining: self.count(),
+        }
+    }
+
+    /// Returns the sequence number of the first operation.
+    pub fn sequence(&self) -> SequenceNumber {
+        u64::from_le_bytes(self.rep[..8].try_into().unwrap())
+    }
+
+    pub(crate) fn set_sequence(&mut self, sequence: SequenceNumber) {
+        self.rep[..8].copy_from_slice(&sequence.to_le_bytes());
+    }
+
+    /// Returns the serialized batch.
+    pub fn contents(&self) -> &[u8] {
+        &self.rep
+    }
+
+    /// Wraps a serialized batch, such as a write-ahead log record. The
+    /// operations are checked when iterated.
+    pub fn from_contents(contents: Vec<u8>) -> io::Result<Self> {
+        if contents.len() < HEADER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "write batch too small",
+            ));
+        }
+        Ok(Self { rep: contents })
+    }
+
+    fn set_count(&mut self, count: u32) {
+        self.rep[8..HEADER_SIZE].copy_from_slice(&count.to_le_bytes());
+    }
+}
+
+/// Iterates over the operations of a `WriteBatch`.
+pub struct WriteBatchIter<'a> {
+    rep: &'a [u8],
+    remaining: u32,
+}
+
+impl WriteBatchIter<'_> {
+    fn read_slice(&mut self) -> io::Result<Vec<u8>> {
+        let (slice, len) = get_length_prefixed(self.rep).ok_or_else(|| corruption("bad entry"))?;
+        let slice = slice.to_vec();
+        self.rep = &self.rep[len..];
+        Ok(slice)
+    }
+
+    fn read_entry(&mut self) -> io::Result<Entry> {
+        let (&tag, rest) = self
+            .rep
+            .split_first()
+            .ok_or_else(|| corruption("missing entry"))?;
+        self.rep = rest;
+        if tag == ValueType::Value as u8 {
+            let key = self.read_slice()?;
+            let value = self.read_slice()?;
+            Ok(Entry::Put { key, value })
+        } else if tag == ValueType::Deletion as u8 {
+            let key = self.read_slice()?;
+            Ok(Entry::Delete { key })
+        } else {
+            Err(corruption("unknown tag"))
+        }
+    }
+}
+
+impl Iterator for WriteBatchIter<'_> {
+    type Item = io::Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            if !self.rep.is_empty() {
+                self.rep = &[];
+                return Some(Err(corruption("entries past the count")));
+            }
+            return None;
+        }
+        self.remaining -= 1;
+        let entry = self.read_entry();
+        if entry.is_err() {
+            // Stop after the first error.
+            self.remaining = 0;
+            self.rep = &[];
+        }
+        Some(entry)
+    }
+}
+
+fn corruption(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("write batch: {reason}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(batch: &WriteBatch) -> io::Result<Vec<Entry>> {
+        batch.iter().collect()
+    }
+
+    #[test]
+    fn build_and_iterate() -> io::Result<()> {
+        let mut batch = WriteBatch::new();
+        assert!(batch.is_empty());
+        assert!(entries(&batch)?.is_empty());
+
+        batch.put(b"foo", b"bar");
+        batch.delete(b"box");
+        batch.put(b"baz", b"boo");
+        batch.set_sequence(100);
+        assert_eq!(batch.count(), 3);
+        assert_eq!(batch.sequence(), 100);
+
+        let decoded = WriteBatch::from_contents(batch.contents().to_vec())?;
+        assert_eq!(decoded, batch);
+        assert_eq!(
+            entries(&decoded)?,
+            [
+                Entry::Put {
+                    key: b"foo".to_vec(),
+                    value: b"bar".to_vec()
+                },
+                Entry::Delete {
+                    key: b"box".to_vec()
+                },
+                Entry::Put {
+                    key: b"baz".to_vec(),
+                    value: b"boo".to_vec()
+                },
+            ]
+        );
+
+        let mut other = WriteBatch::new();
+        other.delete(b"foo");
+        let mut appended = decoded.clone();
+        appended.append(&other);
+        assert_eq!(appended.count(), 4);
+        assert_eq!(appended.sequence(), 100);
+        assert_eq!(
+            entries(&appended)?.last(),
+            Some(&Entry::Delete {
+                key: b"foo".to_vec()
+            })
+        );
+
+        batch.clear();
+        assert_eq!(batch.count(), 0);
+        assert_eq!(batch.approximate_size(), HEADER_SIZE);
+        Ok(())
+    }
+
+    #[test]
+    fn detects_corruption() {
+        assert!(WriteBatch::from_contents(vec![0; HEADER_SIZE - 1]).is_err());
+
+        let mut batch = WriteBatch::new();
+        batch.put(b"foo", b"bar");
+        batch.delete(b"box");
+        let contents = batch.contents();
+
+        // Truncated record.
+        let truncated = WriteBatch::from_contents(contents[..contents.len() - 1].to_vec()).unwrap();
+        let results: Vec<_> = truncated.iter().collect();
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+
+        // Count too small for the records.
+        let mut extra = WriteBatch::from_contents(contents.to_vec()).unwrap();
+        extra.set_count(1);
+        assert!(entries(&extra).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+// Concurrent writers queue up their batches. The writer at the front of the
+// queue becomes the leader: it takes the batches queued behind its own,
+// commits them as one group and hands the result to the writers it took
+// along, which never touch the log themselves. Writers that arrive while a
+// group is being committed wait for the next one.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::{Condvar, Mutex};
+
+use crate::options::WriteOptions;
+use crate::write_batch::WriteBatch;
+
+/// Upper bound on the size of a group.
+const MAX_GROUP_SIZE: usize = 1 << 20;
+/// A small leading batch only takes this much more along, so a small write
+/// is not slowed down much by the writes queued behind it.
+const SMALL_BATCH_GROWTH: usize = 128 << 10;
+
+struct PendingWrite {
+    id: u64,
+    batch: WriteBatch,
+    options: WriteOptions,
+}
+
+#[derive(Default)]
+struct QueueState {
+    next_id: u64,
+    // Writes waiting to be committed, oldest first.
+    pending: VecDeque<PendingWrite>,
+    // Whether a leader is committing a group.
+    committing: bool,
+    // Results of writes committed by a leader for their writers to collect.
+    done: HashMap<u64, io::Result<()>>,
+}
+
+/// Serializes writers, committing the batches of concurrent writers in
+/// groups.
+#[derive(Default)]
+pub struct WriteQueue {
+    state: Mutex<QueueState>,
+    cond: Condvar,
+}
+
+impl WriteQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Commits `batch` along with the batches of concurrent writers, in
+    /// arrival order. The writer that leads a group calls `commit` once with
+    /// the merged batch of the group and the options every write of the
+    /// group can be committed with; every writer of the group gets its
+    /// result.
+    pub fn write<F>(&self, batch: WriteBatch, options: WriteOptions, commit: F) -> io::Result<()>
+    where
+        F: FnOnce(WriteBatch, WriteOptions) -> io::Result<()>,
+    {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.pending.push_back(PendingWrite { id, batch, options });
+        loop {
+            if let Some(result) = state.done.remove(&id) {
+                return result;
+            }
+            if !state.committing && state.pending.front().is_some_and(|write| write.id == id) {
+                break;
+            }
+            state = self.cond.wait(state).unwrap();
+        }
+
+        state.committing = true;
+        let (group, followers, options) = take_group(&mut state.pending);
+        drop(state);
+
+        let result = commit(group, options);
+
+        let mut state = self.state.lock().unwrap();
+        state.committing = false;
+        for follower in followers {
+            let result = match &result {
+                Ok(()) => Ok(()),
+                Err(err) => Err(io::Error::new(err.kind(), err.to_string())),
+            };
+            state.done.insert(follower, result);
+        }
+        self.cond.notify_all();
+        result
+    }
+}
+
+/// Removes the leading write and the writes that can join its group from
+/// `pending`. Returns the merged batch, the ids of the writes that joined
+/// the leader, and the options of the leader, which the group is committed
+/// with.
+fn take_group(pending: &mut VecDeque<PendingWrite>) -> (WriteBatch, Vec<u64>, WriteOptions) {
+    let leader = pending.pop_front().unwrap();
+    let mut max_size = MAX_GROUP_SIZE;
+    if leader.batch.approximate_size() <= SMALL_BATCH_GROWTH {
+        max_size = leader.batch.approximate_size() + SMALL_BATCH_GROWTH;
+    }
+
+    let mut group = leader.batch;
+    let mut followers = Vec::new();
+    while let Some(next) = pending.front() {
+        // A write asking for a sync cannot ride along with one that does
+        // not sync, and writes skipping the log only group with each other.
+        if next.options.sync && !leader.options.sync
+            || next.options.disable_wal != leader.options.disable_wal
+        {
+            break;
+        }
+        if group.approximate_size() + next.batch.approximate_size() > max_size {
+            break;
+        }
+        let next = pending.pop_front().unwrap();
+        group.append(&next.batch);
+        followers.push(next.id);
+    }
+    (group, followers, leader.options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn concurrent_writes_are_grouped() {
+        let queue = Arc::new(WriteQueue::new());
+        let groups = Arc::new(Mutex::new(Vec::new()));
+        let threads: Vec<_> = (0..8)
+            .map(|t| {
+                let queue = queue.clone();
+                let groups = groups.clone();
+                thread::spawn(move || {
+                    for i in 0..50 {
+                        let mut batch = WriteBatch::new();
+                        batch.put(format!("{t}-{i}").as_bytes(), b"");
+                        queue
+                            .write(batch, WriteOptions::default(), |group, _| {
+                                // Give the other writers time to queue up.
+                                thread::sleep(Duration::from_micros(100));
+                                groups.lock().unwrap().push(group.count());
+                                Ok(())
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let groups = groups.lock().unwrap();
+        assert_eq!(groups.iter().sum::<u32>(), 8 * 50);
+        assert!(groups.len() < 8 * 50);
+    }
+
+    #[test]
+    fn groups_share_write_options() {
+        let mut pending = VecDeque::new();
+        for (id, sync, disable_wal) in [
+            (0, false, false),
+            (1, false, false),
+            (2, true, false),
+            (3, false, false),
+            (4, false, true),
+        ] {
+            let mut batch = WriteBatch::new();
+            batch.put(b"k", b"v");
+            let options = WriteOptions { sync, disable_wal };
+            pending.push_back(PendingWrite { id, batch, options });
+        }
+        let (group, followers, options) = take_group(&mut pending);
+        assert_eq!(group.count(), 2);
+        assert_eq!(followers, [1]);
+        assert!(!options.sync);
+
+        // A syncing leader takes non-syncing writes along.
+        let (group, followers, options) = take_group(&mut pending);
+        assert_eq!(group.count(), 2);
+        assert_eq!(followers, [3]);
+        assert!(options.sync);
+
+        let (_, followers, options) = take_group(&mut pending);
+        assert!(followers.is_empty());
+        assert!(options.disable_wal);
+        assert!(pending.is_empty());
+
+        let queue = WriteQueue::new();
+        let err = queue
+            .write(WriteBatch::new(), WriteOptions::default(), |_, _| {
+                Err(io::Error::other("disk full"))
+            })
+            .unwrap_err();
+        assert_eq!(err.to_string(), "disk full");
+    }
+}
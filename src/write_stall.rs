@@ -0,0 +1,144 @@
+// Writes are throttled when flushes or compactions fall behind, so that
+// level 0 and the backlog of compaction work stay bounded. Past a soft limit
+// each write is delayed a little; past a hard limit writes stop until the
+// background thread catches up.
+
+use std::time::Duration;
+
+use crate::compaction;
+use crate::options::{CompactionStyle, Options};
+use crate::version_set::Version;
+
+/// What is holding writes back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteStallCause {
+    /// Full memtables waiting to be flushed.
+    MemtableLimit,
+    /// Level-0 tables waiting to be compacted.
+    Level0FileCount,
+    /// Estimated bytes compaction has left to rewrite.
+    PendingCompactionBytes,
+}
+
+/// Whether writes are currently throttled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WriteStallCondition {
+    #[default]
+    Normal,
+    /// Each write is delayed a little.
+    Delayed(WriteStallCause),
+    /// Writes wait until the background thread catches up.
+    Stopped(WriteStallCause),
+}
+
+/// The current write stall condition, and how much writes have been held
+/// back since the database was opened. Writes committed together as a group
+/// count once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteStallStats {
+    pub condition: WriteStallCondition,
+    pub delayed_writes: u64,
+    pub delay_time: Duration,
+    pub stopped_writes: u64,
+    pub stop_time: Duration,
+}
+
+/// Returns the condition a write faces given the table set, the number of
+/// memtables waiting to be flushed and whether the active one is full.
+pub(crate) fn condition(
+    version: &Version,
+    immutable_memtables: usize,
+    memtable_full: bool,
+    options: &Options,
+) -> WriteStallCondition {
+    let memtable_limit = immutable_memtables >= options.max_immutable_memtables.max(1);
+    let level0_files = match options.compaction_style {
+        // Only deleting tables ever lowers the count.
+        CompactionStyle::Fifo => 0,
+        _ => version.files[0].len(),
+    };
+    let pending_bytes = compaction::pending_compaction_bytes(version, options);
+    let over = |limit: u64| limit > 0 && pending_bytes >= limit;
+
+    if memtable_limit && memtable_full {
+        WriteStallCondition::Stopped(WriteStallCause::MemtableLimit)
+    } else if level0_files >= options.level0_stop_writes_trigger {
+        WriteStallCondition::Stopped(WriteStallCause::Level0FileCount)
+    } else if over(options.hard_pending_compaction_bytes_limit) {
+        WriteStallCondition::Stopped(WriteStallCause::PendingCompactionBytes)
+    } else if memtable_limit {
+        WriteStallCondition::Delayed(WriteStallCause::MemtableLimit)
+    } else if level0_files >= options.level0_slowdown_writes_trigger {
+        WriteStallCondition::Delayed(WriteStallCause::Level0FileCount)
+    } else if over(options.soft_pending_compaction_bytes_limit) {
+        WriteStallCondition::Delayed(WriteStallCause::PendingCompactionBytes)
+    } else {
+        WriteStallCondition::Normal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbformat::{InternalKey, ValueType};
+    use crate::version_edit::FileMetaData;
+    use std::sync::Arc;
+
+    fn level0_version(tables: usize) -> Version {
+        let mut version = Version::default();
+        for number in 1..=tables as u64 {
+            version.files[0].push(Arc::new(FileMetaData {
+                number,
+                file_size: 100,
+                smallest: InternalKey::new(b"a", number, ValueType::Value),
+                largest: InternalKey::new(b"z", number, ValueType::Value),
+                smallest_seqno: number,
+                largest_seqno: number,
+                creation_time: 0,
+            }));
+        }
+        version
+    }
+
+    #[test]
+    fn stall_conditions() {
+        use WriteStallCause::*;
+        use WriteStallCondition::*;
+
+        let options = Options {
+            max_immutable_memtables: 2,
+            level0_file_num_compaction_trigger: 100,
+            level0_slowdown_writes_trigger: 4,
+            level0_stop_writes_trigger: 8,
+            ..Options::default()
+        };
+        let condition = |tables, immutable, full, options: &Options| {
+            condition(&level0_version(tables), immutable, full, options)
+        };
+        assert_eq!(condition(0, 1, true, &options), Normal);
+        assert_eq!(condition(0, 2, false, &options), Delayed(MemtableLimit));
+        assert_eq!(condition(0, 2, true, &options), Stopped(MemtableLimit));
+        assert_eq!(condition(4, 0, false, &options), Delayed(Level0FileCount));
+        assert_eq!(condition(8, 2, false, &options), Stopped(Level0FileCount));
+
+        let options = Options {
+            level0_file_num_compaction_trigger: 1,
+            soft_pending_compaction_bytes_limit: 300,
+            hard_pending_compaction_bytes_limit: 0,
+            ..options
+        };
+        assert_eq!(condition(2, 0, false, &options), Normal);
+        assert_eq!(
+            condition(3, 0, false, &options),
+            Delayed(PendingCompactionBytes)
+        );
+        assert_eq!(condition(30, 0, false, &options), Stopped(Level0FileCount));
+
+        // FIFO never compacts level 0 down.
+        let fifo = Options {
+            compaction_style: CompactionStyle::Fifo,
+            ..options
+        };
+        assert_eq!(condition(30, 0, false, &fifo), Normal);
+    }
+}